@@ -0,0 +1,54 @@
+// end to end rendering of representative inputs with the built binary,
+// the test font keeps results independent of the fonts installed
+use criterion::{criterion_group, criterion_main, Criterion};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+const TEST_FONT: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fonts/Text2svgTest-Regular.ttf");
+
+fn render(args: &[&str], output: &Path) {
+    let status = Command::new(env!("CARGO_BIN_EXE_text2svg"))
+        .args(["-q", "--font-file", TEST_FONT, "-o"])
+        .arg(output)
+        .args(args)
+        .status()
+        .unwrap();
+    assert!(status.success());
+}
+
+// a 1000 line code file from the sources, repeated as needed
+fn code_file(dir: &Path) -> PathBuf {
+    let source = include_str!("../src/render.rs");
+    let lines: Vec<&str> = source.lines().cycle().take(1000).collect();
+    let path = dir.join("text2svg-bench-code.rs");
+    std::fs::write(&path, lines.join("\n")).unwrap();
+    path
+}
+
+fn bench_render(c: &mut Criterion) {
+    let dir = std::env::temp_dir();
+    let output = dir.join("text2svg-bench.svg");
+    let code = code_file(&dir);
+    // filled by a first run, so the timed runs read outlines from it
+    let cache = dir.join("text2svg-bench-cache");
+    let cjk = "天地玄黄，宇宙洪荒。日月盈昃，辰宿列张。寒来暑往，秋收冬藏。闰余成岁，律吕调阳。".repeat(4);
+
+    c.bench_function("short text", |b| b.iter(|| render(&["AVo fop"], &output)));
+    c.bench_function("highlight 1000 lines", |b| b.iter(|| render(&["--highlight", "-f", code.to_str().unwrap()], &output)));
+    let cached = ["--cache-dir", cache.to_str().unwrap(), "--highlight", "-f", code.to_str().unwrap()];
+    render(&cached, &output);
+    c.bench_function("highlight 1000 lines, warm cache", |b| b.iter(|| render(&cached, &output)));
+    c.bench_function("cjk paragraph", |b| b.iter(|| render(&[&cjk], &output)));
+
+    std::fs::remove_file(&code).unwrap();
+    std::fs::remove_dir_all(&cache).unwrap();
+    std::fs::remove_file(&output).unwrap();
+}
+
+criterion_group! {
+    name = benches;
+    // a run of the binary takes milliseconds, fewer samples keep the suite short
+    config = Criterion::default().sample_size(20);
+    targets = bench_render
+}
+criterion_main!(benches);
@@ -0,0 +1,175 @@
+// parse ANSI escape sequences of terminal output into styled runs
+use crate::color::Rgba;
+use crate::font::FontStyle;
+
+const ESC: char = '\x1b';
+
+/// xterm default colors of codes 30-37 and the bright 90-97
+const BASIC_COLORS: [u32; 16] = [
+    0x000000, 0xcd0000, 0x00cd00, 0xcdcd00, 0x0000ee, 0xcd00cd, 0x00cdcd, 0xe5e5e5,
+    0x7f7f7f, 0xff0000, 0x00ff00, 0xffff00, 0x5c5cff, 0xff00ff, 0x00ffff, 0xffffff,
+];
+
+/// Text of a line drawn with the same SGR attributes
+#[derive(Debug, PartialEq, Clone)]
+pub struct AnsiRun {
+    pub text: String,
+    // foreground color, the default color of the font when not set
+    pub color: Option<Rgba>,
+    pub bold: bool,
+    pub italic: bool,
+}
+
+impl AnsiRun {
+    /// Face of the run like highlight mode, italic wins over bold
+    pub fn font_style(&self, default: &FontStyle) -> FontStyle {
+        if self.italic {
+            return FontStyle::Italic;
+        }
+        if self.bold {
+            return FontStyle::Bold;
+        }
+        default.clone()
+    }
+}
+
+/// SGR attributes, kept from one line to the next like a terminal does
+#[derive(Debug, Default, Clone)]
+pub struct AnsiParser {
+    color: Option<Rgba>,
+    bold: bool,
+    italic: bool,
+}
+
+impl AnsiParser {
+    /// Split a line into runs at SGR sequences, other escape sequences are dropped
+    pub fn parse_line(&mut self, line: &str) -> Vec<AnsiRun> {
+        let mut runs = vec![];
+        let mut text = String::new();
+        let mut chars = line.chars().peekable();
+        while let Some(ch) = chars.next() {
+            if ch != ESC {
+                text.push(ch);
+                continue;
+            }
+            // control sequence: ESC [ parameters final byte
+            if chars.peek() != Some(&'[') {
+                continue;
+            }
+            chars.next();
+            let mut params = String::new();
+            let mut end = None;
+            for ch in chars.by_ref() {
+                if ('\x40'..='\x7e').contains(&ch) {
+                    end = Some(ch);
+                    break;
+                }
+                params.push(ch);
+            }
+            if end != Some('m') {
+                continue;
+            }
+            self.push_run(&mut runs, &mut text);
+            self.apply_sgr(&params);
+        }
+        self.push_run(&mut runs, &mut text);
+        runs
+    }
+
+    fn push_run(&self, runs: &mut Vec<AnsiRun>, text: &mut String) {
+        if text.is_empty() {
+            return;
+        }
+        runs.push(AnsiRun {
+            text: std::mem::take(text),
+            color: self.color,
+            bold: self.bold,
+            italic: self.italic,
+        });
+    }
+
+    // apply `;` separated SGR parameters, unknown ones and backgrounds are ignored
+    fn apply_sgr(&mut self, params: &str) {
+        let codes: Vec<u32> = params.split([';', ':']).map(|code| code.parse().unwrap_or(0)).collect();
+        let mut codes = codes.into_iter();
+        while let Some(code) = codes.next() {
+            match code {
+                0 => *self = Self::default(),
+                1 => self.bold = true,
+                3 => self.italic = true,
+                22 => self.bold = false,
+                23 => self.italic = false,
+                30..=37 => self.color = Some(Rgba::from_rgb(BASIC_COLORS[code as usize - 30])),
+                90..=97 => self.color = Some(Rgba::from_rgb(BASIC_COLORS[code as usize - 90 + 8])),
+                39 => self.color = None,
+                38 => self.color = extended_color(&mut codes).or(self.color),
+                // the color of a background is skipped along with it
+                48 => {
+                    extended_color(&mut codes);
+                }
+                _ => (),
+            }
+        }
+    }
+}
+
+// color of `5;n` from the 256 color palette or `2;r;g;b`
+fn extended_color(codes: &mut impl Iterator<Item = u32>) -> Option<Rgba> {
+    match codes.next()? {
+        5 => Some(Rgba::from_rgb(palette_256(codes.next()?))),
+        2 => {
+            let (r, g, b) = (codes.next()?, codes.next()?, codes.next()?);
+            Some(Rgba::from_rgb((r.min(255) << 16) | (g.min(255) << 8) | b.min(255)))
+        }
+        _ => None,
+    }
+}
+
+/// xterm 256 color palette: basic colors, a 6x6x6 color cube and 24 grays
+pub fn palette_256(index: u32) -> u32 {
+    match index {
+        0..=15 => BASIC_COLORS[index as usize],
+        16..=231 => {
+            let level = |value: u32| if value == 0 { 0 } else { 55 + value * 40 };
+            let index = index - 16;
+            (level(index / 36) << 16) | (level(index / 6 % 6) << 8) | level(index % 6)
+        }
+        232..=255 => {
+            let gray = 8 + (index - 232) * 10;
+            (gray << 16) | (gray << 8) | gray
+        }
+        _ => 0,
+    }
+}
+
+#[cfg(test)]
+mod test_ansi {
+  use super::*;
+
+  #[test]
+  fn test_parse_line() {
+      let mut parser = AnsiParser::default();
+      let runs = parser.parse_line("ok \x1b[31mred\x1b[0m \x1b[1;38;5;21mblue\x1b[K");
+      let texts: Vec<&str> = runs.iter().map(|run| run.text.as_str()).collect();
+      assert_eq!(texts, vec!["ok ", "red", " ", "blue"]);
+      assert_eq!(runs[0].color, None);
+      assert_eq!(runs[1].color.unwrap().to_string(), "#cd0000");
+      assert_eq!(runs[2].color, None);
+      assert_eq!(runs[3].color.unwrap().to_string(), "#0000ff");
+      assert!(runs[3].bold);
+      // attributes carry over to the next line until reset
+      let runs = parser.parse_line("still\x1b[22;38;2;1;2;3m rgb");
+      assert!(runs[0].bold);
+      assert_eq!(runs[1].color.unwrap().to_string(), "#010203");
+      assert!(!runs[1].bold);
+  }
+
+  #[test]
+  fn test_palette_256() {
+      assert_eq!(palette_256(9), 0xff0000);
+      assert_eq!(palette_256(16), 0x000000);
+      assert_eq!(palette_256(196), 0xff0000);
+      assert_eq!(palette_256(231), 0xffffff);
+      assert_eq!(palette_256(244), 0x808080);
+  }
+}
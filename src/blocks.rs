@@ -0,0 +1,97 @@
+// text blocks composed into one svg, read from a json file
+use anyhow::{Context, Result, anyhow};
+use serde::Deserialize;
+use std::path::Path;
+
+use crate::color::parse_paint;
+use crate::utils::Failure;
+
+/// A text placed on the canvas, e.g.
+/// `{"text": "Hello", "font": "Arial", "size": 32, "color": "red", "x": 10, "y": 20, "rotate": -15}`
+#[derive(Debug, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct TextBlock {
+    /// lines of the block separated by line breaks
+    pub text: String,
+    /// font family, --font when not given
+    #[serde(default)]
+    pub font: Option<String>,
+    /// font size in px, --size when not given
+    #[serde(default)]
+    pub size: Option<u32>,
+    /// stroke color, --color when not given
+    #[serde(default)]
+    pub color: Option<String>,
+    /// fill, --fill when not given
+    #[serde(default)]
+    pub fill: Option<String>,
+    /// top left corner of the first line box
+    #[serde(default)]
+    pub x: f32,
+    #[serde(default)]
+    pub y: f32,
+    /// clockwise rotation in degrees around the top left corner
+    #[serde(default)]
+    pub rotate: f32,
+}
+
+impl TextBlock {
+    // check values serde can't, colors are normalized like the command line ones
+    fn validate(&mut self) -> Result<()> {
+        if self.text.is_empty() {
+            return Err(anyhow!("text is empty"));
+        }
+        if self.size == Some(0) {
+            return Err(anyhow!("size must be positive"));
+        }
+        for (name, value) in [("x", self.x), ("y", self.y), ("rotate", self.rotate)] {
+            if !value.is_finite() {
+                return Err(anyhow!("{} must be a finite number", name));
+            }
+        }
+        for paint in [&mut self.color, &mut self.fill].into_iter().flatten() {
+            *paint = parse_paint(paint)?;
+        }
+        Ok(())
+    }
+}
+
+/// Parse and validate a json array of text blocks
+pub fn parse_blocks(json: &str) -> Result<Vec<TextBlock>> {
+    let mut blocks: Vec<TextBlock> = serde_json::from_str(json)?;
+    for (index, block) in blocks.iter_mut().enumerate() {
+        block.validate().map_err(|err| anyhow!("block {}: {}", index + 1, err))?;
+    }
+    Ok(blocks)
+}
+
+pub fn read_blocks<P: AsRef<Path>>(path: P) -> Result<Vec<TextBlock>> {
+    let path = path.as_ref();
+    let json = std::fs::read_to_string(path)
+        .map_err(|err| anyhow!(format!("{}: {}", path.display(), err)))
+        .context(Failure::InputNotFound)?;
+    parse_blocks(&json).map_err(|err| anyhow!(format!("{}: {}", path.display(), err)))
+}
+
+#[cfg(test)]
+mod test_blocks {
+  use super::*;
+
+  #[test]
+  fn test_parse_blocks() {
+      let blocks = parse_blocks(r#"[
+          {"text": "Hello", "x": 10, "y": 20},
+          {"text": "World", "font": "Arial", "size": 32, "color": "red", "rotate": 90}
+      ]"#).unwrap();
+      assert_eq!(blocks.len(), 2);
+      assert_eq!((blocks[0].x, blocks[0].y, blocks[0].size), (10.0, 20.0, None));
+      assert_eq!(blocks[1].color.as_deref(), Some("#ff0000"));
+      assert_eq!(blocks[1].font.as_deref(), Some("Arial"));
+
+      let invalid = |json: &str| parse_blocks(json).unwrap_err().to_string();
+      assert!(invalid(r#"[{"text": "a", "colour": "red"}]"#).contains("unknown field"));
+      assert!(invalid(r#"[{"x": 1}]"#).contains("missing field `text`"));
+      assert_eq!(invalid(r#"[{"text": "a"}, {"text": "b", "size": 0}]"#), "block 2: size must be positive");
+      assert!(invalid(r#"[{"text": "a", "fill": "nope"}]"#).starts_with("block 1:"));
+  }
+}
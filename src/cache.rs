@@ -0,0 +1,205 @@
+// glyph outlines kept on disk with --cache-dir, so repeated runs with a font don't outline its glyphs again.
+// Outlines are in font units and don't depend on the size, a file holds the outlines of one face
+// named by a hash of the font data and the face index, so a changed font gets a file of its own.
+use anyhow::{anyhow, Result};
+use rustybuzz::ttf_parser::Rect;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use crate::svg::{GlyphOutline, GlyphOutlines};
+use crate::utils::stable_hash;
+use crate::warning::{self, Warning};
+
+// first line of a cache file, files of another format are outlined again
+const HEADER: &str = "text2svg outlines 1";
+
+static CACHE: Mutex<Option<OutlineCache>> = Mutex::new(None);
+
+/// Read and write outlines in dir from now on
+pub fn enable(dir: PathBuf) {
+    *CACHE.lock().unwrap() = Some(OutlineCache::new(dir));
+}
+
+/// Outlines of the glyphs, taken from the cache when enabled and outlined otherwise
+pub fn outlines(face_data: &Arc<Vec<u8>>, face_index: u32, glyph_ids: &[u16], outline: impl FnOnce(&[u16]) -> GlyphOutlines) -> GlyphOutlines {
+    match CACHE.lock().unwrap().as_mut() {
+        Some(cache) => cache.outlines(face_data, face_index, glyph_ids, outline),
+        None => outline(glyph_ids),
+    }
+}
+
+/// Write the faces with glyphs outlined in this run
+pub fn save() -> Result<()> {
+    match CACHE.lock().unwrap().as_mut() {
+        Some(cache) => cache.save(),
+        None => Ok(()),
+    }
+}
+
+struct OutlineCache {
+    dir: PathBuf,
+    // address of font data and face index to the file name of the face, so the data is hashed once
+    names: HashMap<(usize, u32), FaceName>,
+    faces: HashMap<String, FaceOutlines>,
+}
+
+struct FaceName {
+    // kept alive, so its address can't be taken by other data
+    _data: Arc<Vec<u8>>,
+    name: String,
+}
+
+#[derive(Default)]
+struct FaceOutlines {
+    outlines: GlyphOutlines,
+    // glyphs were outlined since the file was read
+    changed: bool,
+}
+
+impl OutlineCache {
+    fn new(dir: PathBuf) -> Self {
+        Self { dir, names: HashMap::new(), faces: HashMap::new() }
+    }
+
+    fn outlines(&mut self, face_data: &Arc<Vec<u8>>, face_index: u32, glyph_ids: &[u16], outline: impl FnOnce(&[u16]) -> GlyphOutlines) -> GlyphOutlines {
+        let name = &self
+            .names
+            .entry((Arc::as_ptr(face_data) as usize, face_index))
+            .or_insert_with(|| FaceName { _data: face_data.clone(), name: file_name(face_data, face_index) })
+            .name;
+        let dir = &self.dir;
+        let face = self.faces.entry(name.clone()).or_insert_with(|| FaceOutlines::read(&dir.join(name.as_str())));
+        let missing: Vec<u16> = glyph_ids.iter().filter(|glyph_id| !face.outlines.contains_key(glyph_id)).copied().collect();
+        if !missing.is_empty() {
+            face.outlines.extend(outline(&missing));
+            face.changed = true;
+        }
+        glyph_ids
+            .iter()
+            .filter_map(|glyph_id| face.outlines.get(glyph_id).map(|outline| (*glyph_id, outline.clone())))
+            .collect()
+    }
+
+    fn save(&mut self) -> Result<()> {
+        let changed: Vec<_> = self.faces.iter_mut().filter(|(_, face)| face.changed).collect();
+        if changed.is_empty() {
+            return Ok(());
+        }
+        std::fs::create_dir_all(&self.dir).map_err(|err| anyhow!("{}: {}", self.dir.display(), err))?;
+        for (name, face) in changed {
+            face.write(&self.dir.join(name))?;
+            face.changed = false;
+        }
+        Ok(())
+    }
+}
+
+fn file_name(face_data: &[u8], face_index: u32) -> String {
+    format!("{:016x}-{}.outlines", stable_hash(&[face_data]), face_index)
+}
+
+impl FaceOutlines {
+    // outlines in the file, none when it doesn't exist yet or can't be read
+    fn read(path: &Path) -> Self {
+        let outlines = match std::fs::read_to_string(path) {
+            Ok(content) => parse_outlines(&content).unwrap_or_else(|err| {
+                warning::warn(
+                    Warning::new("cache_unreadable", format!("{}: {}, outlining again", path.display(), err))
+                        .field("path", path.display().to_string()),
+                );
+                GlyphOutlines::new()
+            }),
+            Err(_) => GlyphOutlines::new(),
+        };
+        Self { outlines, changed: false }
+    }
+
+    // written next to the file and renamed over it, so concurrent runs never read half a file
+    fn write(&self, path: &Path) -> Result<()> {
+        let mut glyph_ids: Vec<&u16> = self.outlines.keys().collect();
+        glyph_ids.sort_unstable();
+        let mut content = format!("{}\n", HEADER);
+        for glyph_id in glyph_ids {
+            content += &match &self.outlines[glyph_id] {
+                Some((outline, bbox)) => {
+                    format!("{} {} {} {} {} {}\n", glyph_id, bbox.x_min, bbox.y_min, bbox.x_max, bbox.y_max, outline)
+                }
+                None => format!("{} -\n", glyph_id),
+            };
+        }
+        let temp = path.with_extension(format!("{}.tmp", std::process::id()));
+        std::fs::write(&temp, content)
+            .and_then(|_| std::fs::rename(&temp, path))
+            .map_err(|err| anyhow!("{}: {}", path.display(), err))
+    }
+}
+
+// a line for each glyph: id and bounding box followed by the outline, or id and `-` for glyphs without outline
+fn parse_outlines(content: &str) -> Result<GlyphOutlines> {
+    let mut lines = content.lines();
+    if lines.next() != Some(HEADER) {
+        return Err(anyhow!("not a cache file of this version"));
+    }
+    let mut outlines = GlyphOutlines::new();
+    for (index, line) in lines.enumerate() {
+        let error = |message: &str| anyhow!("line {}: {}", index + 2, message);
+        let mut fields = line.splitn(6, ' ');
+        let mut field = || fields.next().ok_or_else(|| error("missing field"));
+        let glyph_id: u16 = field()?.parse().map_err(|_| error("expect a glyph id"))?;
+        let first = field()?;
+        if first == "-" {
+            outlines.insert(glyph_id, None);
+            continue;
+        }
+        let mut bbox = [0i16; 4];
+        bbox[0] = first.parse().map_err(|_| error("expect a bounding box"))?;
+        for value in bbox.iter_mut().skip(1) {
+            *value = field()?.parse().map_err(|_| error("expect a bounding box"))?;
+        }
+        let outline: GlyphOutline = field()?.parse().map_err(|err: String| error(&err))?;
+        let bbox = Rect { x_min: bbox[0], y_min: bbox[1], x_max: bbox[2], y_max: bbox[3] };
+        outlines.insert(glyph_id, Some((outline, bbox)));
+    }
+    Ok(outlines)
+}
+
+#[cfg(test)]
+mod test_cache {
+  use super::*;
+  use crate::svg::outline_glyphs;
+
+  #[test]
+  fn test_outline_cache() {
+      let dir = std::env::temp_dir().join("text2svg-test-cache");
+      let _ = std::fs::remove_dir_all(&dir);
+      let font = Arc::new(std::fs::read(concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fonts/Text2svgTest-Regular.ttf")).unwrap());
+      // .notdef, space and A
+      let glyph_ids = [0, 1, 2];
+      let expected = outline_glyphs(&font, 0, &glyph_ids, 1);
+
+      let mut cache = OutlineCache::new(dir.clone());
+      assert_eq!(cache.outlines(&font, 0, &glyph_ids, |ids| outline_glyphs(&font, 0, ids, 1)), expected);
+      cache.save().unwrap();
+
+      // a later run reads the outlines instead of outlining
+      let mut cache = OutlineCache::new(dir.clone());
+      assert_eq!(cache.outlines(&font, 0, &glyph_ids, |_| unreachable!()), expected);
+      let mut outlined = vec![];
+      cache.outlines(&font, 0, &[2, 3], |ids| {
+          outlined = ids.to_vec();
+          outline_glyphs(&font, 0, ids, 1)
+      });
+      assert_eq!(outlined, vec![3]);
+
+      // another face of the data or changed data doesn't take the outlines
+      assert_ne!(file_name(&font, 0), file_name(&font, 1));
+      let mut changed = font.to_vec();
+      changed.push(0);
+      assert_ne!(file_name(&font, 0), file_name(&changed, 0));
+
+      assert!(parse_outlines("text2svg outlines 0\n").is_err());
+      assert!(parse_outlines(&format!("{}\n2 0 0 600\n", HEADER)).is_err());
+      std::fs::remove_dir_all(&dir).unwrap();
+  }
+}
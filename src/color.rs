@@ -0,0 +1,297 @@
+// parse css colors given from command line
+use anyhow::{Result, anyhow};
+use clap::ValueEnum;
+use std::fmt::Display;
+
+/// css named colors https://www.w3.org/TR/css-color-4/#named-colors
+const NAMED_COLORS: [(&str, u32); 148] = [
+    ("aliceblue", 0xf0f8ff), ("antiquewhite", 0xfaebd7), ("aqua", 0x00ffff),
+    ("aquamarine", 0x7fffd4), ("azure", 0xf0ffff), ("beige", 0xf5f5dc),
+    ("bisque", 0xffe4c4), ("black", 0x000000), ("blanchedalmond", 0xffebcd),
+    ("blue", 0x0000ff), ("blueviolet", 0x8a2be2), ("brown", 0xa52a2a),
+    ("burlywood", 0xdeb887), ("cadetblue", 0x5f9ea0), ("chartreuse", 0x7fff00),
+    ("chocolate", 0xd2691e), ("coral", 0xff7f50), ("cornflowerblue", 0x6495ed),
+    ("cornsilk", 0xfff8dc), ("crimson", 0xdc143c), ("cyan", 0x00ffff),
+    ("darkblue", 0x00008b), ("darkcyan", 0x008b8b), ("darkgoldenrod", 0xb8860b),
+    ("darkgray", 0xa9a9a9), ("darkgreen", 0x006400), ("darkgrey", 0xa9a9a9),
+    ("darkkhaki", 0xbdb76b), ("darkmagenta", 0x8b008b), ("darkolivegreen", 0x556b2f),
+    ("darkorange", 0xff8c00), ("darkorchid", 0x9932cc), ("darkred", 0x8b0000),
+    ("darksalmon", 0xe9967a), ("darkseagreen", 0x8fbc8f), ("darkslateblue", 0x483d8b),
+    ("darkslategray", 0x2f4f4f), ("darkslategrey", 0x2f4f4f), ("darkturquoise", 0x00ced1),
+    ("darkviolet", 0x9400d3), ("deeppink", 0xff1493), ("deepskyblue", 0x00bfff),
+    ("dimgray", 0x696969), ("dimgrey", 0x696969), ("dodgerblue", 0x1e90ff),
+    ("firebrick", 0xb22222), ("floralwhite", 0xfffaf0), ("forestgreen", 0x228b22),
+    ("fuchsia", 0xff00ff), ("gainsboro", 0xdcdcdc), ("ghostwhite", 0xf8f8ff),
+    ("gold", 0xffd700), ("goldenrod", 0xdaa520), ("gray", 0x808080),
+    ("green", 0x008000), ("greenyellow", 0xadff2f), ("grey", 0x808080),
+    ("honeydew", 0xf0fff0), ("hotpink", 0xff69b4), ("indianred", 0xcd5c5c),
+    ("indigo", 0x4b0082), ("ivory", 0xfffff0), ("khaki", 0xf0e68c),
+    ("lavender", 0xe6e6fa), ("lavenderblush", 0xfff0f5), ("lawngreen", 0x7cfc00),
+    ("lemonchiffon", 0xfffacd), ("lightblue", 0xadd8e6), ("lightcoral", 0xf08080),
+    ("lightcyan", 0xe0ffff), ("lightgoldenrodyellow", 0xfafad2), ("lightgray", 0xd3d3d3),
+    ("lightgreen", 0x90ee90), ("lightgrey", 0xd3d3d3), ("lightpink", 0xffb6c1),
+    ("lightsalmon", 0xffa07a), ("lightseagreen", 0x20b2aa), ("lightskyblue", 0x87cefa),
+    ("lightslategray", 0x778899), ("lightslategrey", 0x778899), ("lightsteelblue", 0xb0c4de),
+    ("lightyellow", 0xffffe0), ("lime", 0x00ff00), ("limegreen", 0x32cd32),
+    ("linen", 0xfaf0e6), ("magenta", 0xff00ff), ("maroon", 0x800000),
+    ("mediumaquamarine", 0x66cdaa), ("mediumblue", 0x0000cd), ("mediumorchid", 0xba55d3),
+    ("mediumpurple", 0x9370db), ("mediumseagreen", 0x3cb371), ("mediumslateblue", 0x7b68ee),
+    ("mediumspringgreen", 0x00fa9a), ("mediumturquoise", 0x48d1cc), ("mediumvioletred", 0xc71585),
+    ("midnightblue", 0x191970), ("mintcream", 0xf5fffa), ("mistyrose", 0xffe4e1),
+    ("moccasin", 0xffe4b5), ("navajowhite", 0xffdead), ("navy", 0x000080),
+    ("oldlace", 0xfdf5e6), ("olive", 0x808000), ("olivedrab", 0x6b8e23),
+    ("orange", 0xffa500), ("orangered", 0xff4500), ("orchid", 0xda70d6),
+    ("palegoldenrod", 0xeee8aa), ("palegreen", 0x98fb98), ("paleturquoise", 0xafeeee),
+    ("palevioletred", 0xdb7093), ("papayawhip", 0xffefd5), ("peachpuff", 0xffdab9),
+    ("peru", 0xcd853f), ("pink", 0xffc0cb), ("plum", 0xdda0dd),
+    ("powderblue", 0xb0e0e6), ("purple", 0x800080), ("rebeccapurple", 0x663399),
+    ("red", 0xff0000), ("rosybrown", 0xbc8f8f), ("royalblue", 0x4169e1),
+    ("saddlebrown", 0x8b4513), ("salmon", 0xfa8072), ("sandybrown", 0xf4a460),
+    ("seagreen", 0x2e8b57), ("seashell", 0xfff5ee), ("sienna", 0xa0522d),
+    ("silver", 0xc0c0c0), ("skyblue", 0x87ceeb), ("slateblue", 0x6a5acd),
+    ("slategray", 0x708090), ("slategrey", 0x708090), ("snow", 0xfffafa),
+    ("springgreen", 0x00ff7f), ("steelblue", 0x4682b4), ("tan", 0xd2b48c),
+    ("teal", 0x008080), ("thistle", 0xd8bfd8), ("tomato", 0xff6347),
+    ("turquoise", 0x40e0d0), ("violet", 0xee82ee), ("wheat", 0xf5deb3),
+    ("white", 0xffffff), ("whitesmoke", 0xf5f5f5), ("yellow", 0xffff00),
+    ("yellowgreen", 0x9acd32),
+];
+
+/// keywords which are valid paint values but not colors
+const PAINT_KEYWORDS: [&str; 3] = ["none", "currentColor", "transparent"];
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct Rgba {
+    pub r: u8,
+    pub g: u8,
+    pub b: u8,
+    pub a: u8,
+}
+
+impl Rgba {
+    pub fn from_rgb(rgb: u32) -> Self {
+        Self {
+            r: (rgb >> 16) as u8,
+            g: (rgb >> 8) as u8,
+            b: rgb as u8,
+            a: 255,
+        }
+    }
+}
+
+impl Rgba {
+    /// Complement of the color, dark turns light and vice versa, the alpha is kept
+    pub fn inverted(&self) -> Self {
+        Self { r: 255 - self.r, g: 255 - self.g, b: 255 - self.b, a: self.a }
+    }
+}
+
+impl Display for Rgba {
+    // canonical form is lowercase #rrggbb, or #rrggbbaa for translucent colors
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "#{:02x}{:02x}{:02x}", self.r, self.g, self.b)?;
+        if self.a != 255 {
+            write!(f, "{:02x}", self.a)?;
+        }
+        Ok(())
+    }
+}
+
+/// How glyph outlines are painted
+#[derive(ValueEnum, Debug, PartialEq, Clone, Copy, Eq)]
+#[value(rename_all="lower")]
+pub enum PaintMode {
+    Filled,
+    Stroked,
+    Both,
+}
+
+/// Fill and stroke paint of a mode, the fill follows the stroke color unless a fill is given
+pub fn mode_paint(mode: PaintMode, fill: &str, color: &str) -> (String, String) {
+    let fill_or_color = if fill == "none" { color } else { fill };
+    let (fill, stroke) = match mode {
+        PaintMode::Filled => (fill_or_color, "none"),
+        PaintMode::Stroked => ("none", color),
+        PaintMode::Both => (fill_or_color, color),
+    };
+    (fill.to_string(), stroke.to_string())
+}
+
+/// Parse a svg paint value, colors are normalized to the canonical hex form
+pub fn parse_paint(value: &str) -> Result<String> {
+    if let Some(keyword) = PAINT_KEYWORDS.iter().find(|k| k.eq_ignore_ascii_case(value.trim())) {
+        return Ok(keyword.to_string());
+    }
+    Ok(parse_color(value)?.to_string())
+}
+
+/// Complement of a paint parsed by `parse_paint`, keywords stay as they are
+pub fn invert_paint(paint: &str) -> String {
+    match paint.strip_prefix('#').and_then(parse_hex) {
+        Some(color) => color.inverted().to_string(),
+        None => paint.to_string(),
+    }
+}
+
+/// Parse css color in hex, named, rgb()/rgba() or hsl()/hsla() notation
+pub fn parse_color(value: &str) -> Result<Rgba> {
+    let color = value.trim().to_ascii_lowercase();
+    let invalid = || anyhow!(format!("invalid color: {:?}", value));
+
+    if let Some(hex) = color.strip_prefix('#') {
+        return parse_hex(hex).ok_or_else(invalid);
+    }
+    if let Some(args) = function_args(&color, "rgb").or_else(|| function_args(&color, "rgba")) {
+        return parse_rgb(&args).ok_or_else(invalid);
+    }
+    if let Some(args) = function_args(&color, "hsl").or_else(|| function_args(&color, "hsla")) {
+        return parse_hsl(&args).ok_or_else(invalid);
+    }
+    NAMED_COLORS
+        .iter()
+        .find(|(name, _)| *name == color)
+        .map(|(_, rgb)| Rgba::from_rgb(*rgb))
+        .ok_or_else(invalid)
+}
+
+fn parse_hex(hex: &str) -> Option<Rgba> {
+    if !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+        return None;
+    }
+    let digits: Vec<u8> = hex.chars().map(|c| c.to_digit(16).unwrap() as u8).collect();
+    match digits.len() {
+        3 | 4 => Some(Rgba {
+            r: digits[0] * 17,
+            g: digits[1] * 17,
+            b: digits[2] * 17,
+            a: digits.get(3).map_or(255, |a| a * 17),
+        }),
+        6 | 8 => {
+            let byte = |i: usize| digits[i] * 16 + digits[i + 1];
+            Some(Rgba {
+                r: byte(0),
+                g: byte(2),
+                b: byte(4),
+                a: if digits.len() == 8 { byte(6) } else { 255 },
+            })
+        }
+        _ => None,
+    }
+}
+
+// Arguments of a css function, separated by commas or spaces with optional `/ alpha`
+fn function_args(color: &str, name: &str) -> Option<Vec<String>> {
+    let args = color.strip_prefix(name)?.trim_start().strip_prefix('(')?.strip_suffix(')')?;
+    Some(
+        args.split(|c: char| c == ',' || c == '/' || c.is_whitespace())
+            .filter(|arg| !arg.is_empty())
+            .map(|arg| arg.to_string())
+            .collect(),
+    )
+}
+
+// number or percentage of given full scale
+fn parse_component(arg: &str, scale: f32) -> Option<f32> {
+    let value = match arg.strip_suffix('%') {
+        Some(percent) => percent.parse::<f32>().ok()? / 100.0 * scale,
+        None => arg.parse::<f32>().ok()?,
+    };
+    value.is_finite().then_some(value)
+}
+
+fn parse_alpha(args: &[String]) -> Option<u8> {
+    match args.get(3) {
+        Some(alpha) => Some((parse_component(alpha, 1.0)?.clamp(0.0, 1.0) * 255.0).round() as u8),
+        None => Some(255),
+    }
+}
+
+fn parse_rgb(args: &[String]) -> Option<Rgba> {
+    if args.len() != 3 && args.len() != 4 {
+        return None;
+    }
+    let channel = |i: usize| parse_component(&args[i], 255.0).map(|v| v.clamp(0.0, 255.0).round() as u8);
+    Some(Rgba {
+        r: channel(0)?,
+        g: channel(1)?,
+        b: channel(2)?,
+        a: parse_alpha(args)?,
+    })
+}
+
+fn parse_hsl(args: &[String]) -> Option<Rgba> {
+    if args.len() != 3 && args.len() != 4 {
+        return None;
+    }
+    let hue = args[0].strip_suffix("deg").unwrap_or(&args[0]).parse::<f32>().ok()?.rem_euclid(360.0);
+    let saturation = parse_component(args[1].strip_suffix('%')?, 1.0)?.clamp(0.0, 100.0) / 100.0;
+    let lightness = parse_component(args[2].strip_suffix('%')?, 1.0)?.clamp(0.0, 100.0) / 100.0;
+
+    // https://www.w3.org/TR/css-color-4/#hsl-to-rgb
+    let channel = |n: f32| {
+        let k = (n + hue / 30.0) % 12.0;
+        let a = saturation * lightness.min(1.0 - lightness);
+        let v = lightness - a * (k - 3.0).min(9.0 - k).clamp(-1.0, 1.0);
+        (v * 255.0).round() as u8
+    };
+    Some(Rgba {
+        r: channel(0.0),
+        g: channel(8.0),
+        b: channel(4.0),
+        a: parse_alpha(args)?,
+    })
+}
+
+#[cfg(test)]
+mod test_color {
+  use super::*;
+
+  #[test]
+  fn test_parse_hex() {
+      assert_eq!(parse_paint("#000").unwrap(), "#000000");
+      assert_eq!(parse_paint("#F0F8").unwrap(), "#ff00ff88");
+      assert_eq!(parse_paint("#663399").unwrap(), "#663399");
+      assert_eq!(parse_paint("#66339980").unwrap(), "#66339980");
+      assert!(parse_paint("#12345").is_err());
+      assert!(parse_paint("#ggg").is_err());
+  }
+
+  #[test]
+  fn test_parse_named() {
+      assert_eq!(parse_paint("red").unwrap(), "#ff0000");
+      assert_eq!(parse_paint("RebeccaPurple").unwrap(), "#663399");
+      assert_eq!(parse_paint("none").unwrap(), "none");
+      assert_eq!(parse_paint("currentcolor").unwrap(), "currentColor");
+      assert!(parse_paint("bluee").is_err());
+  }
+
+  #[test]
+  fn test_parse_rgb() {
+      assert_eq!(parse_paint("rgb(255, 0, 128)").unwrap(), "#ff0080");
+      assert_eq!(parse_paint("rgba(255,0,0,0.5)").unwrap(), "#ff000080");
+      assert_eq!(parse_paint("rgb(100% 0% 0% / 50%)").unwrap(), "#ff000080");
+      assert!(parse_paint("rgb(1,2)").is_err());
+      assert!(parse_paint("rgb(a,b,c)").is_err());
+  }
+
+  #[test]
+  fn test_parse_hsl() {
+      assert_eq!(parse_paint("hsl(0, 100%, 50%)").unwrap(), "#ff0000");
+      assert_eq!(parse_paint("hsl(120deg 100% 25%)").unwrap(), "#008000");
+      assert_eq!(parse_paint("hsla(270, 50%, 40%, 1)").unwrap(), "#663399");
+      assert!(parse_paint("hsl(0, 100, 50)").is_err());
+  }
+
+  #[test]
+  fn test_mode_paint() {
+      let (fill, stroke) = mode_paint(PaintMode::Filled, "none", "#000000");
+      let mut builder = crate::svg::Text::builder();
+      builder.set_fill_color(&fill).set_color(&stroke);
+      let style = crate::svg::style_declarations(&builder.presentation_attributes());
+      assert!(style.starts_with("fill:#000000;stroke:none;"));
+
+      assert_eq!(mode_paint(PaintMode::Filled, "#ff0000", "#000000"), ("#ff0000".to_string(), "none".to_string()));
+      assert_eq!(mode_paint(PaintMode::Stroked, "#ff0000", "#000000"), ("none".to_string(), "#000000".to_string()));
+      assert_eq!(mode_paint(PaintMode::Both, "none", "#000000"), ("#000000".to_string(), "#000000".to_string()));
+  }
+}
@@ -1,25 +1,52 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+use std::path::PathBuf;
 use std::str::FromStr;
 
 use clap::ValueEnum;
 use font_kit::error::{FontLoadingError, SelectionError};
+use font_kit::family_name::FamilyName;
 use font_kit::font::Font;
-use font_kit::properties::{Style, Weight};
+use font_kit::handle::Handle;
+use font_kit::properties::{Properties, Style, Weight};
 use font_kit::source::SystemSource;
-use rustybuzz::Feature;
+use rustybuzz::{ttf_parser, Feature, Language, Script};
+
+use crate::warning::{self, Warning};
+
+thread_local! {
+    // Scanning installed fonts is slow, so the source is created once and shared.
+    // It is not Sync on every platform (e.g. fontconfig), hence thread local rather than a static.
+    static SYSTEM_SOURCE: SystemSource = SystemSource::new();
+}
+
+/// Run f with the shared system font source
+pub fn with_system_source<T>(f: impl FnOnce(&SystemSource) -> T) -> T {
+    SYSTEM_SOURCE.with(f)
+}
 
 /// names of installed fonts
 pub fn fonts() -> Vec<String> {
     let arr: Vec<String> = Vec::new();
-    let sys_fonts = SystemSource::new();
-    let _families = sys_fonts.all_families();
+    let _families = with_system_source(|source| source.all_families());
     match _families {
         Ok(families) => families,
         Err(_) => arr,
     }
 }
 
-#[derive(ValueEnum, Debug, PartialEq, Clone, Eq, Hash)]
+/// environment variable of the default font
+pub const FONT_ENV: &str = "TEXT2SVG_FONT";
+
+/// Font name with precedence: command line > TEXT2SVG_FONT
+pub fn resolve_font_name(font: Option<String>) -> Option<String> {
+    select_font_name(font, std::env::var(FONT_ENV).ok())
+}
+
+fn select_font_name(font: Option<String>, env_font: Option<String>) -> Option<String> {
+    font.or(env_font.filter(|name| !name.trim().is_empty()))
+}
+
+#[derive(ValueEnum, Debug, PartialEq, Clone, Eq, Hash, PartialOrd, Ord)]
 #[value(rename_all="lower")]
 pub enum FontStyle {
     // Weight
@@ -36,9 +63,73 @@ pub enum FontStyle {
     Italic,
 }
 
+impl FontStyle {
+    /// Name as given on the command line, e.g. `extralight`
+    pub fn name(&self) -> String {
+        self.to_possible_value().map(|value| value.get_name().to_string()).unwrap_or_default()
+    }
+}
+
+/// Face used for a requested style: the style itself, else regular, else the closest weight
+pub fn nearest_style(style: &FontStyle, available: &[FontStyle]) -> Option<FontStyle> {
+    if available.contains(style) {
+        return Some(style.clone());
+    }
+    if available.contains(&FontStyle::Regular) {
+        return Some(FontStyle::Regular);
+    }
+    let rank = |style: &FontStyle| style.clone() as i32;
+    available.iter().min_by_key(|face| (rank(face) - rank(style)).abs()).cloned()
+}
+
+fn fallback_notice(font_name: &str, requested: &FontStyle, used: &FontStyle, available: &[FontStyle]) -> Warning {
+    let available: Vec<String> = available.iter().map(|style| style.name()).collect();
+    let message = format!(
+        "Requested style '{}' not available for '{}'; rendered as '{}'. Available: [{}]",
+        requested.name(),
+        font_name,
+        used.name(),
+        available.join(", ")
+    );
+    Warning::new("style_fallback", message)
+        .field("font", font_name)
+        .field("requested", requested.name())
+        .field("used", used.name())
+        .field("available", available)
+}
+
 #[derive(Debug, PartialEq, Eq)]
 pub struct ParseFontStyleErr;
 
+/// base direction of a line of text
+#[derive(ValueEnum, Debug, PartialEq, Clone, Copy, Eq, Default)]
+#[value(rename_all="lower")]
+pub enum BaseDirection {
+    /// from the first strong character, a leading RLM or LRM mark sets it
+    #[default]
+    Auto,
+    Ltr,
+    Rtl,
+}
+
+/// unit of letter space
+#[derive(ValueEnum, Debug, PartialEq, Clone, Copy, Eq)]
+#[value(rename_all="lower")]
+pub enum LetterSpaceUnit {
+    Em,
+    Px,
+}
+
+impl LetterSpaceUnit {
+    /// Letter space in target pixels, em_size is the scaled em square
+    pub fn to_px(self, space: f32, em_size: f32) -> f32 {
+        match self {
+            LetterSpaceUnit::Em => space * em_size,
+            LetterSpaceUnit::Px => space,
+        }
+    }
+}
+
 impl ToString for FontStyle {
     fn to_string(&self) -> String {
         match *self {
@@ -92,17 +183,29 @@ impl From<FontLoadingError> for FontError {
     }
 }
 
+/// Faces of a font with the size and colors to render it in.
+/// Loading the faces is the costly part, they are kept when size and colors change so a config can render again.
 #[derive(Debug)]
 pub struct FontConfig {
     font_name: String,
     size: u32,
     feature_map: HashMap<String,Feature>,
     features: Vec<Feature>,
+    // shaping script and language, rustybuzz guesses them when unset
+    script: Option<Script>,
+    language: Option<Language>,
+    direction: BaseDirection,
     faces: HashMap<FontStyle, Font>,
+    face_paths: HashMap<FontStyle, Vec<PathBuf>>,
+    face_indices: HashMap<FontStyle, u32>,
+    // requested styles already reported as falling back to another face
+    fallback_reported: HashSet<FontStyle>,
     letter_space: f32,
+    letter_space_unit: LetterSpaceUnit,
     fill_color: String,
     color: String,
-    debug: bool,
+    // fonts for the glyphs this one lacks, tried in order
+    fallbacks: Vec<FontConfig>,
 }
 
 // Get font style from keywords in its full name
@@ -162,44 +265,137 @@ fn approximate_font_weight(weight: Weight) -> FontStyle {
     FontStyle::Black
 }
 
+/// Bold, italic and regular as marked in a face
+#[derive(Debug, PartialEq, Clone, Copy, Default)]
+struct StyleBits {
+    bold: bool,
+    italic: bool,
+    regular: bool,
+}
+
+// Style bits from OS/2 fsSelection, or from head macStyle in fonts without an OS/2 table
+fn style_bits(data: &[u8], face_index: u32) -> Option<StyleBits> {
+    let face = ttf_parser::Face::parse(data, face_index).ok()?;
+    let raw = face.raw_face();
+    let read_u16 = |table: &[u8], offset: usize| Some(u16::from_be_bytes([*table.get(offset)?, *table.get(offset + 1)?]));
+    // fsSelection bits: 0 italic, 5 bold, 6 regular
+    if let Some(fs_selection) = raw.table(ttf_parser::Tag::from_bytes(b"OS/2")).and_then(|os2| read_u16(os2, 62)) {
+        return Some(StyleBits {
+            bold: fs_selection & 0x20 != 0,
+            italic: fs_selection & 0x01 != 0,
+            regular: fs_selection & 0x40 != 0,
+        });
+    }
+    // macStyle bits: 0 bold, 1 italic, no bit set doesn't tell regular apart from unmarked
+    let mac_style = read_u16(raw.table(ttf_parser::Tag::from_bytes(b"head"))?, 44)?;
+    Some(StyleBits { bold: mac_style & 0x01 != 0, italic: mac_style & 0x02 != 0, regular: false })
+}
+
+// The name and weight can mislead, e.g. a bold face named Regular or a family named "Bold Sans".
+// The bits of the face settle regular, bold and italic, other weights aren't told apart by them.
+fn settle_style(style: FontStyle, bits: StyleBits) -> FontStyle {
+    if !matches!(style, FontStyle::Regular | FontStyle::Bold | FontStyle::Italic) {
+        return style;
+    }
+    if bits.bold {
+        FontStyle::Bold
+    } else if bits.italic {
+        FontStyle::Italic
+    } else if bits.regular {
+        FontStyle::Regular
+    } else {
+        style
+    }
+}
+
 impl FontConfig {
     pub fn new(
         font_name: String,
         size: u32,
         fill_color: String,
         color: String,
-        debug: bool,
     ) -> Result<Self, FontError> {
-        let font_family = SystemSource::new().select_family_by_name(&font_name)?;
+        if let Some(generic) = generic_family(&font_name) {
+            let handles = select_generic_family(generic)?;
+            return Self::from_handles(font_name, &handles, size, fill_color, color);
+        }
+        let font_family = with_system_source(|source| source.select_family_by_name(&font_name))?;
+        Self::from_handles(font_name, font_family.fonts(), size, fill_color, color)
+    }
+
+    /// Font config of a face in a font file or collection (.ttc/.otc), named after its family
+    pub fn from_path(
+        path: PathBuf,
+        face_index: u32,
+        size: u32,
+        fill_color: String,
+        color: String,
+    ) -> Result<Self, FontError> {
+        let data = std::fs::read(&path).map_err(FontLoadingError::Io)?;
+        let face_count = ttf_parser::fonts_in_collection(&data).unwrap_or(1);
+        log::debug!("{} faces in {:?}", face_count, path);
+        if face_index >= face_count {
+            return Err(FontLoadingError::NoSuchFontInCollection.into());
+        }
+        let handle = Handle::from_path(path, face_index);
+        let font_name = handle.load()?.family_name();
+        Self::from_handles(font_name, &[handle], size, fill_color, color)
+    }
 
+    fn from_handles(
+        font_name: String,
+        handles: &[Handle],
+        size: u32,
+        fill_color: String,
+        color: String,
+    ) -> Result<Self, FontError> {
         let mut faces = HashMap::new();
+        // files providing each style, several files claim the family on manual installs
+        let mut face_paths: HashMap<FontStyle, Vec<PathBuf>> = HashMap::new();
+        // index of each face in its file, non-zero in collections
+        let mut face_indices = HashMap::new();
 
-        for handle in font_family.fonts() {
+        for handle in handles {
             let font = handle.load()?;
             let properties = font.properties();
 
-            if debug {
-                println!("font name:\n {:?}", font.full_name());
-                println!("font properties:\n {:?}", properties);
-            }
-
-            if let Some(style) = font_full_name_to_weight(font.full_name()) {
-                faces.insert(style, font);
-                continue;
-            }
-
-            match properties.style {
-                Style::Normal => {
-                    let weight = approximate_font_weight(properties.weight);
-                    faces.insert(weight, font);
+            log::debug!("font name: {:?}", font.full_name());
+            log::debug!("font properties: {:?}", properties);
+
+            let face_index = match handle {
+                Handle::Path { font_index, .. } | Handle::Memory { font_index, .. } => *font_index,
+            };
+            let style = match font_full_name_to_weight(font.full_name()) {
+                Some(style) => style,
+                None => match properties.style {
+                    Style::Normal => approximate_font_weight(properties.weight),
+                    Style::Italic => FontStyle::Italic,
+                    _ => {
+                        warning::warn(
+                            Warning::new("unsupported_style", format!("Unsupported font style {:?}", properties))
+                                .field("font", font.full_name()),
+                        );
+                        continue;
+                    },
                 },
-                Style::Italic => {
-                    faces.insert(FontStyle::Italic, font);
+            };
+            let bits = font.copy_font_data().and_then(|data| style_bits(&data, face_index));
+            log::debug!("font style bits: {:?}", bits);
+            let style = match bits {
+                Some(bits) => settle_style(style, bits),
+                None => style,
+            };
+
+            if let Handle::Path { path, .. } = handle {
+                log::debug!("font path: {:?}", path);
+                let paths = face_paths.entry(style.clone()).or_default();
+                if !paths.contains(path) {
+                    paths.push(path.clone());
                 }
-                _ => {
-                    eprintln!("Unsupported font style\n {:?}", properties);
-                },
             }
+            // the last face of a style wins
+            face_indices.insert(style.clone(), face_index);
+            faces.insert(style, font);
         }
         let mut feature_map = HashMap::new();
         feature_map.insert("kern".to_owned(),Feature::from_str("kern").unwrap());
@@ -208,9 +404,7 @@ impl FontConfig {
         feature_map.insert("clig".to_owned(),Feature::from_str("clig").unwrap());
         let features = feature_map.values().cloned().collect();
 
-        if debug {
-            println!("faces:\n {:?}", faces);
-        }
+        log::debug!("faces: {:?}", faces);
 
         // now only supports horizontal writing mode default features
         Ok(Self {
@@ -218,11 +412,18 @@ impl FontConfig {
             size,
             feature_map,
             features,
+            script: None,
+            direction: BaseDirection::Auto,
+            language: None,
             fill_color,
             color,
             faces,
+            face_paths,
+            face_indices,
+            fallback_reported: HashSet::new(),
             letter_space:0.0,
-            debug,
+            letter_space_unit: LetterSpaceUnit::Em,
+            fallbacks: vec![],
         })
     }
 
@@ -230,22 +431,109 @@ impl FontConfig {
         self.feature_map.get(name).is_some()
     }
 
-    pub fn add_feature(&mut self, name: &str)  {
-        self.feature_map.insert(name.to_owned(),Feature::from_str(name).unwrap());
-        self.features = self.feature_map.values().cloned().collect();
+    /// Add a feature setting in harfbuzz syntax, e.g. `fina`, `-liga`, `ss01=1` or `zero[0:3]`.
+    /// Settings of the whole text are keyed by tag, so `-liga` replaces the default `liga`.
+    /// Settings of a character range are keyed by tag and range, and override the former within the range.
+    pub fn add_feature(&mut self, name: &str) -> anyhow::Result<()> {
+        let feature = feature_from_str(name).map_err(|e| anyhow::anyhow!(e))?;
+        let key = if is_global(&feature) {
+            feature.tag.to_string()
+        } else {
+            format!("{}[{}:{}]", feature.tag, feature.start, feature.end)
+        };
+        self.feature_map.insert(key, feature);
+        self.update_features();
+        Ok(())
     }
 
     pub fn remove_feature(&mut self, name: &str) {
         if self.has_feature(name) {
             self.feature_map.remove(name);
-            self.features = self.feature_map.values().cloned().collect();
+            self.update_features();
         }
     }
 
+    // shaping applies later features over earlier ones, so ranged settings go last
+    fn update_features(&mut self) {
+        let (mut features, ranged): (Vec<Feature>, Vec<Feature>) = self.feature_map.values().cloned().partition(is_global);
+        features.extend(ranged);
+        self.features = features;
+    }
+
     pub fn get_features(&self) -> &Vec<Feature> {
         &self.features
     }
 
+    pub fn set_script(&mut self, script: Option<Script>) -> &mut Self {
+        self.script = script;
+        self
+    }
+
+    pub fn get_script(&self) -> Option<Script> {
+        self.script
+    }
+
+    pub fn set_language(&mut self, language: Option<Language>) -> &mut Self {
+        self.language = language;
+        self
+    }
+
+    pub fn get_language(&self) -> Option<&Language> {
+        self.language.as_ref()
+    }
+
+    pub fn set_direction(&mut self, direction: BaseDirection) -> &mut Self {
+        self.direction = direction;
+        self
+    }
+
+    pub fn get_direction(&self) -> BaseDirection {
+        self.direction
+    }
+
+    /// Index of the face of given style in its font file
+    pub fn get_face_index(&self, style: &FontStyle) -> u32 {
+        self.face_indices.get(style).copied().unwrap_or(0)
+    }
+
+    /// Whether the face of given style has an OpenType substitution feature, e.g. sups
+    pub fn has_gsub_feature(&self, style: &FontStyle, tag: &[u8; 4]) -> bool {
+        self.get_font_by_style(style)
+            .and_then(|font| font.copy_font_data())
+            .and_then(|data| {
+                let face = ttf_parser::Face::parse(&data, self.get_face_index(style)).ok()?;
+                let gsub = face.tables().gsub?;
+                gsub.features.find(ttf_parser::Tag::from_bytes(tag)).map(|_| ())
+            })
+            .is_some()
+    }
+
+    /// Characters the face of given style has a glyph for, in code point order
+    pub fn characters(&self, style: &FontStyle) -> Vec<char> {
+        let Some(data) = self.get_font_by_style(style).and_then(|font| font.copy_font_data()) else {
+            return vec![];
+        };
+        let Ok(face) = ttf_parser::Face::parse(&data, self.get_face_index(style)) else {
+            return vec![];
+        };
+        let mut chars = vec![];
+        for subtable in face.tables().cmap.into_iter().flat_map(|cmap| cmap.subtables) {
+            if subtable.is_unicode() {
+                subtable.codepoints(|codepoint| chars.extend(char::from_u32(codepoint)));
+            }
+        }
+        chars.sort_unstable();
+        chars.dedup();
+        chars.retain(|ch| face.glyph_index(*ch).is_some_and(|glyph_id| glyph_id.0 != 0));
+        chars
+    }
+
+    /// Styles provided by more than one font file, with the files in load order.
+    /// The last file is the one in use.
+    pub fn ambiguous_faces(&self) -> Vec<(&FontStyle, &Vec<PathBuf>)> {
+        ambiguous_faces(&self.face_paths)
+    }
+
     pub fn get_regular_font(&self) -> Option<&Font> {
         self.faces.get(&FontStyle::Regular)
     }
@@ -254,6 +542,42 @@ impl FontConfig {
         self.faces.get(style)
     }
 
+    /// Styles of the loaded faces in weight order
+    pub fn available_styles(&self) -> Vec<FontStyle> {
+        let mut styles: Vec<FontStyle> = self.faces.keys().cloned().collect();
+        styles.sort();
+        styles
+    }
+
+    /// Style of the face rendering the requested style, a fallback is reported once per style
+    pub fn resolve_style(&mut self, style: &FontStyle) -> Option<FontStyle> {
+        let available = self.available_styles();
+        let used = nearest_style(style, &available)?;
+        if used != *style && self.fallback_reported.insert(style.clone()) {
+            warning::warn(fallback_notice(&self.font_name, style, &used, &available));
+        }
+        Some(used)
+    }
+
+    /// Whether the face of given style has fixed-width glyphs,
+    /// fonts without the fixed pitch flag are checked by comparing a narrow and a wide glyph.
+    pub fn is_monospace(&self, style: &FontStyle) -> bool {
+        if let Some(font) = self.get_font_by_style(style) {
+            if font.is_monospace() {
+                return true;
+            }
+            let advance = |ch: char| {
+                font.glyph_for_char(ch)
+                    .and_then(|glyph_id| font.advance(glyph_id).ok())
+                    .map(|v| v.x())
+            };
+            if let (Some(narrow), Some(wide)) = (advance('i'), advance('W')) {
+                return narrow == wide;
+            }
+        }
+        false
+    }
+
     pub fn set_letter_space(&mut self, space: f32) -> &mut Self {
         self.letter_space = space;
         self
@@ -263,23 +587,234 @@ impl FontConfig {
         self.letter_space
     }
 
+    pub fn set_letter_space_unit(&mut self, unit: LetterSpaceUnit) -> &mut Self {
+        self.letter_space_unit = unit;
+        self
+    }
+
+    pub fn get_letter_space_unit(&self) -> LetterSpaceUnit {
+        self.letter_space_unit
+    }
+
     pub fn get_font_name(&self) -> &String {
         &self.font_name
     }
 
+    /// Stroke of glyphs
+    pub fn set_color(&mut self, color: &str) -> &mut Self {
+        self.color = color.to_string();
+        self
+    }
+
     pub fn get_color(&self) -> &String {
         &self.color
     }
 
+    pub fn set_fill_color(&mut self, fill_color: &str) -> &mut Self {
+        self.fill_color = fill_color.to_string();
+        self
+    }
+
     pub fn get_fill_color(&self) -> &String {
         &self.fill_color
     }
 
+    /// Font size in px
+    pub fn set_size(&mut self, size: u32) -> &mut Self {
+        self.size = size;
+        self
+    }
+
     pub fn get_size(&self) -> u32 {
         self.size
     }
 
-    pub fn get_debug(&self) -> bool {
-        self.debug
+    /// Add a font for the glyphs missing from this font and the fallbacks added before it
+    pub fn add_fallback(&mut self, fallback: FontConfig) -> &mut Self {
+        self.fallbacks.push(fallback);
+        self
+    }
+
+    /// Number of fonts in the fallback chain, this font included
+    pub fn chain_len(&self) -> usize {
+        1 + self.fallbacks.len()
+    }
+
+    /// Font of the fallback chain, 0 is this font and 1 its first fallback
+    pub fn chain(&self, face: usize) -> &FontConfig {
+        match face {
+            0 => self,
+            _ => &self.fallbacks[face - 1],
+        }
+    }
+
+    pub fn chain_mut(&mut self, face: usize) -> &mut FontConfig {
+        match face {
+            0 => self,
+            _ => &mut self.fallbacks[face - 1],
+        }
+    }
+
+    /// Style of the face in font `face` of the chain rendering glyphs of given style,
+    /// fallbacks use their nearest style
+    pub fn chain_style(&self, face: usize, style: &FontStyle) -> Option<FontStyle> {
+        nearest_style(style, &self.chain(face).available_styles())
+    }
+}
+
+/// Validate a feature setting given on the command line
+pub fn parse_feature(value: &str) -> Result<String, String> {
+    feature_from_str(value).map(|_| value.to_string())
+}
+
+fn feature_from_str(value: &str) -> Result<Feature, String> {
+    Feature::from_str(value).map_err(|e| format!("{}, got {:?}", e, value))
+}
+
+// the setting applies to the whole text rather than a character range
+fn is_global(feature: &Feature) -> bool {
+    feature.start == 0 && feature.end == u32::MAX
+}
+
+/// CSS generic family of a font name, e.g. `sans-serif`
+fn generic_family(font_name: &str) -> Option<FamilyName> {
+    match font_name.trim().to_ascii_lowercase().as_str() {
+        "serif" => Some(FamilyName::Serif),
+        "sans-serif" => Some(FamilyName::SansSerif),
+        "monospace" => Some(FamilyName::Monospace),
+        "cursive" => Some(FamilyName::Cursive),
+        "fantasy" => Some(FamilyName::Fantasy),
+        _ => None,
     }
 }
+
+/// Faces of the family the system picks for a generic family,
+/// only the picked face when its family can't be selected by name
+fn select_generic_family(generic: FamilyName) -> Result<Vec<Handle>, FontError> {
+    let handle = with_system_source(|source| source.select_best_match(std::slice::from_ref(&generic), &Properties::new()))?;
+    let family_name = handle.load()?.family_name();
+    log::debug!("{:?} resolves to '{}'", generic, family_name);
+    match with_system_source(|source| source.select_family_by_name(&family_name)) {
+        Ok(family) => Ok(family.fonts().to_vec()),
+        Err(_) => Ok(vec![handle]),
+    }
+}
+
+/// Scripts (ISO 15924) whose shapers apply the feature, empty for features applying to any script.
+/// These features are looked up in the GSUB table of the shaping script, so they only take effect
+/// when the text is shaped as one of these scripts.
+pub fn feature_scripts(tag: &str) -> &'static [&'static str] {
+    match tag {
+        "init" | "medi" | "fina" | "isol" | "med2" | "fin2" | "fin3" | "rlig" | "mset" => &["arab", "syrc", "mong", "nkoo", "phag", "mand", "mani"],
+        "akhn" | "rphf" | "blwf" | "half" | "pstf" | "vatu" | "cjct" | "nukt" | "pref" | "abvf" | "pres" | "abvs" | "blws" | "psts" | "haln" => &["deva", "beng", "guru", "gujr", "orya", "taml", "telu", "knda", "mlym", "sinh"],
+        "ljmo" | "vjmo" | "tjmo" => &["hang"],
+        _ => &[],
+    }
+}
+
+fn ambiguous_faces(face_paths: &HashMap<FontStyle, Vec<PathBuf>>) -> Vec<(&FontStyle, &Vec<PathBuf>)> {
+    let mut faces: Vec<_> = face_paths.iter().filter(|(_, paths)| paths.len() > 1).collect();
+    faces.sort_by_key(|(_, paths)| paths[0].clone());
+    faces
+}
+
+#[cfg(test)]
+mod test_font {
+  use super::*;
+
+  #[test]
+  fn test_select_font_name() {
+      let cli = Some("Fira Code".to_string());
+      let env = Some("DejaVu Sans".to_string());
+      assert_eq!(select_font_name(cli.clone(), env.clone()), cli);
+      assert_eq!(select_font_name(None, env.clone()), env);
+      assert_eq!(select_font_name(None, Some(" ".to_string())), None);
+      assert_eq!(select_font_name(None, None), None);
+  }
+
+  #[test]
+  fn test_generic_families() {
+      assert_eq!(generic_family("Sans-Serif"), Some(FamilyName::SansSerif));
+      assert_eq!(generic_family("DejaVu Sans"), None);
+      for name in ["sans-serif", "serif", "monospace"] {
+          let font_config = FontConfig::new(name.to_string(), 16, "none".to_string(), "#000".to_string()).unwrap();
+          assert!(!font_config.available_styles().is_empty(), "{}", name);
+          assert_eq!(font_config.get_font_name(), name);
+      }
+  }
+
+  #[test]
+  fn test_ambiguous_faces() {
+      let mut face_paths = HashMap::new();
+      face_paths.insert(FontStyle::Regular, vec![PathBuf::from("/usr/share/fonts/Arial.ttf"), PathBuf::from("/home/me/.fonts/Arial.ttf")]);
+      face_paths.insert(FontStyle::Bold, vec![PathBuf::from("/usr/share/fonts/Arial Bold.ttf")]);
+      let faces = ambiguous_faces(&face_paths);
+      assert_eq!(faces.len(), 1);
+      assert_eq!(faces[0].0, &FontStyle::Regular);
+      assert_eq!(faces[0].1.last(), Some(&PathBuf::from("/home/me/.fonts/Arial.ttf")));
+  }
+
+  #[test]
+  fn test_parse_feature() {
+      assert_eq!(parse_feature("fina"), Ok("fina".to_string()));
+      assert_eq!(parse_feature("-liga"), Ok("-liga".to_string()));
+      assert_eq!(parse_feature("ss01=1"), Ok("ss01=1".to_string()));
+      assert!(parse_feature("").is_err());
+      assert_eq!(parse_feature("zero[0:3]"), Ok("zero[0:3]".to_string()));
+      assert_eq!(parse_feature("bogus[["), Err("invalid feature, got \"bogus[[\"".to_string()));
+      assert!(feature_scripts("fina").contains(&"arab"));
+      assert!(feature_scripts("akhn").contains(&"deva"));
+      assert!(feature_scripts("liga").is_empty());
+  }
+
+  #[test]
+  fn test_style_fallback() {
+      // single face font
+      let available = [FontStyle::Regular];
+      let used = nearest_style(&FontStyle::Italic, &available).unwrap();
+      assert_eq!(used, FontStyle::Regular);
+      let notice = fallback_notice("Inconsolata", &FontStyle::Italic, &used, &available);
+      assert_eq!(
+          notice.to_string(),
+          "Requested style 'italic' not available for 'Inconsolata'; rendered as 'regular'. Available: [regular]"
+      );
+      // as written with --warnings-format json
+      assert_eq!(
+          notice.to_json(),
+          r#"{"kind":"style_fallback","font":"Inconsolata","requested":"italic","used":"regular","available":["regular"],"message":"Requested style 'italic' not available for 'Inconsolata'; rendered as 'regular'. Available: [regular]"}"#
+      );
+      assert_eq!(nearest_style(&FontStyle::ExtraBold, &[FontStyle::Light, FontStyle::Bold]), Some(FontStyle::Bold));
+      assert_eq!(nearest_style(&FontStyle::Bold, &[]), None);
+  }
+
+  #[test]
+  fn test_style_bits() {
+      // named Text2svg Test Misnamed Regular, marked bold in fsSelection, macStyle and the weight class
+      let path = PathBuf::from(concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fonts/Text2svgTestMisnamed.ttf"));
+      let data = std::fs::read(&path).unwrap();
+      assert_eq!(style_bits(&data, 0), Some(StyleBits { bold: true, italic: false, regular: false }));
+      let font_config = FontConfig::from_path(path, 0, 64, "none".to_string(), "#000".to_string()).unwrap();
+      assert_eq!(font_config.available_styles(), vec![FontStyle::Bold]);
+
+      let data = std::fs::read(concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fonts/Text2svgTest-Regular.ttf")).unwrap();
+      assert_eq!(style_bits(&data, 0), Some(StyleBits { bold: false, italic: false, regular: true }));
+
+      let bold = StyleBits { bold: true, ..StyleBits::default() };
+      let regular = StyleBits { regular: true, ..StyleBits::default() };
+      assert_eq!(settle_style(FontStyle::Regular, bold), FontStyle::Bold);
+      // e.g. the regular face of a family named "Bold Sans"
+      assert_eq!(settle_style(FontStyle::Bold, regular), FontStyle::Regular);
+      assert_eq!(settle_style(FontStyle::Regular, StyleBits { italic: true, ..StyleBits::default() }), FontStyle::Italic);
+      // unmarked faces and weights the bits don't tell apart keep their style
+      assert_eq!(settle_style(FontStyle::Bold, StyleBits::default()), FontStyle::Bold);
+      assert_eq!(settle_style(FontStyle::Light, bold), FontStyle::Light);
+  }
+
+  #[test]
+  fn test_letter_space_unit() {
+      // 2048 units per em scaled by 64/2048
+      let em_size = 2048.0 * (64.0 / 2048.0);
+      assert_eq!(LetterSpaceUnit::Em.to_px(0.1, em_size), 6.4);
+      assert_eq!(LetterSpaceUnit::Px.to_px(2.0, em_size), 2.0);
+  }
+}
@@ -1,7 +1,12 @@
-use syntect::{parsing::SyntaxSet, highlighting::{ThemeSet, Color, FontStyle, Theme}};
-use std::{fmt::Display, path::Path};
+use anyhow::{Result, anyhow};
+use clap::ValueEnum;
+use serde::Deserialize;
+use syntect::easy::ScopeRangeIterator;
+use syntect::{parsing::{SyntaxSet, SyntaxReference, ParseState, ScopeStack}, highlighting::{ThemeSet, Color, FontStyle, Theme, ThemeItem, ThemeSettings, ScopeSelectors, StyleModifier, Highlighter, Style}};
+use std::{fmt::Display, fs::File, io::BufReader, path::Path, str::FromStr};
 
 use crate::font::FontStyle as FFontStyle;
+use crate::utils::read_first_line;
 
 #[derive(Debug)]
 pub enum HighlightTheme{
@@ -13,6 +18,12 @@ pub struct HighlightSetting {
     pub syntax_set: SyntaxSet,
     pub theme_set: ThemeSet,
     pub theme: String,
+    // add syntect scope stack of each token as data-scope attribute
+    pub emit_scopes: bool,
+    // tint lines starting with + or - like a unified diff
+    pub diff: Option<DiffMarkers>,
+    // number of the first line in the line number gutter, no gutter when not set
+    pub first_line: Option<usize>,
 }
 
 impl Default for HighlightSetting {
@@ -23,15 +34,26 @@ impl Default for HighlightSetting {
             syntax_set: ss,
             theme_set: ts,
             theme: "base16-ocean.dark".to_string(),
+            emit_scopes: false,
+            diff: None,
+            first_line: None,
         }
     }
 }
 
 impl HighlightSetting {
-    pub fn add_theme<P: AsRef<Path>>(&mut self, name: &str, path:P) -> &mut Self{
-        let theme = ThemeSet::get_theme(path).unwrap();
+    /// Load a .tmTheme file under the given name, replacing a theme with the same name
+    pub fn add_theme<P: AsRef<Path>>(&mut self, name: &str, path:P) -> Result<&mut Self> {
+        let path = path.as_ref();
+        let theme = ThemeSet::get_theme(path).map_err(|e| anyhow!("{}: {}", path.display(), e))?;
         self.theme_set.themes.insert(name.to_string(),theme);
-        self
+        Ok(self)
+    }
+
+    pub fn add_theme_from_palette(&mut self, name: &str, palette: &Palette) -> Result<&mut Self> {
+        let theme = palette.to_theme(name)?;
+        self.theme_set.themes.insert(name.to_string(), theme);
+        Ok(self)
     }
 
     pub fn get_theme(&self, name: &str) -> Option<&Theme> {
@@ -42,6 +64,201 @@ impl HighlightSetting {
         self.theme = name.to_string();
         self
     }
+
+    /// Switch to the light variant of a dark theme or vice versa, a theme without one gets its colors inverted
+    pub fn invert_theme(&mut self) -> &mut Self {
+        if let Some(name) = counterpart_name(&self.theme).filter(|name| self.theme_set.themes.contains_key(name)) {
+            self.theme = name;
+            return self;
+        }
+        if let Some(theme) = self.get_theme(&self.theme) {
+            let inverted = inverted_theme(theme);
+            let name = format!("{} (inverted)", self.theme);
+            self.theme_set.themes.insert(name.clone(), inverted);
+            self.theme = name;
+        }
+        self
+    }
+
+    /// Syntax by file extension or first line, plain text if none matches
+    pub fn find_syntax<P: AsRef<Path>>(&self, path: P) -> &SyntaxReference {
+        let path = path.as_ref();
+        let file_name = path.file_name().and_then(|n| n.to_str()).unwrap_or("");
+        let extension = path.extension().and_then(|x| x.to_str()).unwrap_or("");
+        self.syntax_set.find_syntax_by_extension(file_name)
+            .or_else(|| self.syntax_set.find_syntax_by_extension(extension))
+            // the first line is read without byte order mark, which breaks shebang detection
+            .or_else(|| read_first_line(path).and_then(|line| self.syntax_set.find_syntax_by_first_line(&line)))
+            .unwrap_or_else(|| self.syntax_set.find_syntax_plain_text())
+    }
+}
+
+/// What to do with the leading `+`, `-` or space of diff lines
+#[derive(ValueEnum, Debug, PartialEq, Clone, Copy, Eq)]
+#[value(rename_all="lower")]
+pub enum DiffMarkers {
+    Keep,
+    /// drop the marker column, so the code is highlighted and aligned as in the source
+    Strip,
+}
+
+/// Kind of a line of a unified diff
+#[derive(Debug, PartialEq, Clone, Copy, Eq)]
+pub enum DiffLine {
+    Added,
+    Removed,
+    /// context, hunk and file headers
+    Other,
+}
+
+impl DiffLine {
+    /// `+++` and `---` are file headers rather than changes
+    pub fn classify(line: &str) -> Self {
+        if line.starts_with("+++") || line.starts_with("---") {
+            return DiffLine::Other;
+        }
+        match line.chars().next() {
+            Some('+') => DiffLine::Added,
+            Some('-') => DiffLine::Removed,
+            _ => DiffLine::Other,
+        }
+    }
+
+    /// Translucent band drawn behind the line, so that it tints the theme background
+    pub fn band_color(&self) -> Option<&'static str> {
+        match self {
+            DiffLine::Added => Some("rgba(46,160,67,0.3)"),
+            DiffLine::Removed => Some("rgba(248,81,73,0.3)"),
+            DiffLine::Other => None,
+        }
+    }
+
+    /// Text of the line as rendered, context lines lose their leading space when stripped
+    pub fn text<'a>(&self, line: &'a str, markers: DiffMarkers) -> &'a str {
+        match (self, markers) {
+            (DiffLine::Added | DiffLine::Removed, DiffMarkers::Strip) => &line[1..],
+            (DiffLine::Other, DiffMarkers::Strip) => line.strip_prefix(' ').unwrap_or(line),
+            (_, DiffMarkers::Keep) => line,
+        }
+    }
+}
+
+// name of the light variant of a dark theme and vice versa, e.g. base16-ocean.light for base16-ocean.dark
+fn counterpart_name(name: &str) -> Option<String> {
+    [("dark", "light"), ("light", "dark"), ("Dark", "Light"), ("Light", "Dark")]
+        .iter()
+        .find_map(|(from, to)| name.rfind(from).map(|index| format!("{}{}{}", &name[..index], to, &name[index + from.len()..])))
+}
+
+// theme with the complement of its colors, so that dark turns light and vice versa
+fn inverted_theme(theme: &Theme) -> Theme {
+    let invert = |color: Option<Color>| color.map(|c| Color { r: 255 - c.r, g: 255 - c.g, b: 255 - c.b, a: c.a });
+    let mut theme = theme.clone();
+    let settings = &mut theme.settings;
+    settings.foreground = invert(settings.foreground);
+    settings.background = invert(settings.background);
+    settings.gutter = invert(settings.gutter);
+    settings.gutter_foreground = invert(settings.gutter_foreground);
+    for item in theme.scopes.iter_mut() {
+        item.style.foreground = invert(item.style.foreground);
+        item.style.background = invert(item.style.background);
+    }
+    theme
+}
+
+/// Line highlighter which keeps track of the scope stack,
+/// so that tokens can be reported along with their scopes.
+pub struct ScopedHighlighter<'a> {
+    syntax_set: &'a SyntaxSet,
+    highlighter: Highlighter<'a>,
+    parse_state: ParseState,
+    scope_stack: ScopeStack,
+}
+
+impl<'a> ScopedHighlighter<'a> {
+    pub fn new(syntax_set: &'a SyntaxSet, syntax: &SyntaxReference, theme: &'a Theme) -> Self {
+        Self {
+            syntax_set,
+            highlighter: Highlighter::new(theme),
+            parse_state: ParseState::new(syntax),
+            scope_stack: ScopeStack::new(),
+        }
+    }
+
+    /// Split a line into tokens with their style and space separated scope stack
+    pub fn highlight_line<'b>(&mut self, line: &'b str) -> Result<Vec<(Style, String, &'b str)>> {
+        let ops = self.parse_state.parse_line(line, self.syntax_set)?;
+        let mut tokens = vec![];
+        for (range, op) in ScopeRangeIterator::new(&ops, line) {
+            self.scope_stack.apply(op)?;
+            if range.is_empty() {
+                continue;
+            }
+            let scopes = self.scope_stack.as_slice();
+            let style = self.highlighter.style_for_stack(scopes);
+            let scope = scopes.iter().map(|scope| scope.build_string()).collect::<Vec<_>>().join(" ");
+            tokens.push((style, scope, &line[range]));
+        }
+        Ok(tokens)
+    }
+}
+
+/// A minimal color scheme as an alternative to .tmTheme files
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Palette {
+    pub background: String,
+    pub foreground: String,
+    pub keyword: String,
+    pub string: String,
+    pub comment: String,
+    pub function: String,
+}
+
+fn parse_palette_color(key: &str, value: &str) -> Result<Color> {
+    Color::from_str(value).map_err(|_| anyhow!(format!(
+                "palette {}: invalid color {:?}, expect #rgb, #rrggbb or #rrggbbaa", key, value)))
+}
+
+impl Palette {
+    pub fn from_path<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+        let file = File::open(path).map_err(|err| anyhow!(format!("{}: {}", path.display(), err)))?;
+        serde_json::from_reader(BufReader::new(file))
+            .map_err(|err| anyhow!(format!("{}: {}", path.display(), err)))
+    }
+
+    pub fn to_theme(&self, name: &str) -> Result<Theme> {
+        let settings = ThemeSettings {
+            foreground: Some(parse_palette_color("foreground", &self.foreground)?),
+            background: Some(parse_palette_color("background", &self.background)?),
+            ..ThemeSettings::default()
+        };
+
+        let mut scopes = vec![];
+        for (key, scope, value) in [
+            ("keyword", "keyword, storage", &self.keyword),
+            ("string", "string", &self.string),
+            ("comment", "comment", &self.comment),
+            ("function", "entity.name.function, support.function", &self.function),
+        ] {
+            scopes.push(ThemeItem {
+                scope: ScopeSelectors::from_str(scope).unwrap(),
+                style: StyleModifier {
+                    foreground: Some(parse_palette_color(key, value)?),
+                    background: None,
+                    font_style: None,
+                },
+            });
+        }
+
+        Ok(Theme {
+            name: Some(name.to_string()),
+            settings,
+            scopes,
+            ..Theme::default()
+        })
+    }
 }
 
 pub struct HighlightColor {
@@ -96,4 +313,117 @@ use super::*;
       let bold_style = HighlightFontStyle::new(bold);
       assert_eq!(bold_style.get_style(),FFontStyle::Bold);
   }
+
+  #[test]
+  fn test_invert_theme() {
+      let mut setting = HighlightSetting::default();
+      setting.invert_theme();
+      assert_eq!(setting.theme, "base16-ocean.light");
+      setting.invert_theme();
+      assert_eq!(setting.theme, "base16-ocean.dark");
+      assert_eq!(setting.set_theme("Solarized (dark)").invert_theme().theme, "Solarized (light)");
+
+      let palette = Palette {
+          background: "#000000".to_string(),
+          foreground: "#ffffff".to_string(),
+          keyword: "#ff0000".to_string(),
+          string: "#00ff00".to_string(),
+          comment: "#808080".to_string(),
+          function: "#0000ff".to_string(),
+      };
+      setting.add_theme_from_palette("user-palette", &palette).unwrap().set_theme("user-palette").invert_theme();
+      assert_eq!(setting.theme, "user-palette (inverted)");
+      let theme = setting.get_theme(&setting.theme).unwrap();
+      assert_eq!(theme.settings.background, Some(Color { r: 255, g: 255, b: 255, a: 255 }));
+      assert_eq!(theme.scopes[0].style.foreground, Some(Color { r: 0, g: 255, b: 255, a: 255 }));
+  }
+
+  #[test]
+  fn test_diff_line() {
+      assert_eq!(DiffLine::classify("+let a = 1;"), DiffLine::Added);
+      assert_eq!(DiffLine::classify("-let a = 0;"), DiffLine::Removed);
+      assert_eq!(DiffLine::classify("--- a/src/main.rs"), DiffLine::Other);
+      assert_eq!(DiffLine::classify("+++ b/src/main.rs"), DiffLine::Other);
+      assert_eq!(DiffLine::classify(" fn main() {"), DiffLine::Other);
+      assert_eq!(DiffLine::Added.text("+let a = 1;", DiffMarkers::Strip), "let a = 1;");
+      assert_eq!(DiffLine::Added.text("+let a = 1;", DiffMarkers::Keep), "+let a = 1;");
+      assert_eq!(DiffLine::Other.text(" fn main() {", DiffMarkers::Strip), "fn main() {");
+      assert_eq!(DiffLine::Other.text("@@ -1 +1 @@", DiffMarkers::Strip), "@@ -1 +1 @@");
+  }
+
+  #[test]
+  fn test_scoped_highlighter() {
+      let setting = HighlightSetting::default();
+      let syntax = setting.syntax_set.find_syntax_by_extension("rs").unwrap();
+      let theme = setting.get_theme(&setting.theme).unwrap();
+      let mut highlighter = ScopedHighlighter::new(&setting.syntax_set, syntax, theme);
+      let tokens = highlighter.highlight_line("fn main() {}").unwrap();
+      let (_, scope, token) = &tokens[0];
+      assert_eq!(*token, "fn");
+      assert!(scope.starts_with("source.rust"));
+      assert!(scope.contains("storage.type"));
+      assert_eq!(tokens.iter().map(|(_, _, token)| *token).collect::<String>(), "fn main() {}");
+  }
+
+  #[test]
+  fn test_find_syntax_with_bom() {
+      let path = std::env::temp_dir().join("text2svg-test-bom-script");
+      std::fs::write(&path, "\u{FEFF}#!/bin/bash\necho hi\n").unwrap();
+      let setting = HighlightSetting::default();
+      let syntax = setting.find_syntax(&path);
+      std::fs::remove_file(&path).unwrap();
+      assert_eq!(syntax.name, "Bourne Again Shell (bash)");
+  }
+
+  #[test]
+  fn test_add_theme_files() {
+      let dir = std::env::temp_dir();
+      let mut setting = HighlightSetting::default();
+      for (name, background) in [("brand-dark", "#101010"), ("brand-light", "#F0F0F0")] {
+          let path = dir.join(format!("text2svg-test-{}.tmTheme", name));
+          std::fs::write(&path, format!(r#"<?xml version="1.0" encoding="UTF-8"?>
+<plist version="1.0"><dict>
+<key>name</key><string>{name}</string>
+<key>settings</key><array><dict><key>settings</key><dict>
+<key>background</key><string>{background}</string>
+</dict></dict></array>
+</dict></plist>"#)).unwrap();
+          let loaded = setting.add_theme(name, &path).map(|_| ());
+          std::fs::remove_file(&path).unwrap();
+          loaded.unwrap();
+      }
+      setting.set_theme("brand-dark");
+      let theme = setting.get_theme(&setting.theme).unwrap();
+      assert_eq!(theme.settings.background, Some(Color { r: 0x10, g: 0x10, b: 0x10, a: 255 }));
+      setting.set_theme("brand-light");
+      let theme = setting.get_theme(&setting.theme).unwrap();
+      assert_eq!(theme.settings.background, Some(Color { r: 0xf0, g: 0xf0, b: 0xf0, a: 255 }));
+
+      assert!(setting.add_theme("missing", dir.join("text2svg-test-missing.tmTheme")).is_err());
+  }
+
+  #[test]
+  fn test_palette_to_theme() {
+      let palette: Palette = serde_json::from_str(r##"{
+          "background": "#2b303b", "foreground": "#c0c5ce",
+          "keyword": "#b48ead", "string": "#a3be8c",
+          "comment": "#65737e", "function": "#8fa1b3"
+      }"##).unwrap();
+      let theme = palette.to_theme("palette").unwrap();
+      assert_eq!(theme.settings.background, Some(Color { r: 0x2b, g: 0x30, b: 0x3b, a: 255 }));
+      assert_eq!(theme.scopes.len(), 4);
+  }
+
+  #[test]
+  fn test_palette_invalid() {
+      let missing = serde_json::from_str::<Palette>(r##"{"background": "#000"}"##);
+      assert!(missing.unwrap_err().to_string().contains("missing field"));
+
+      let palette: Palette = serde_json::from_str(r##"{
+          "background": "#000", "foreground": "#fff",
+          "keyword": "purple", "string": "#0f0",
+          "comment": "#888", "function": "#00f"
+      }"##).unwrap();
+      assert!(palette.to_theme("palette").unwrap_err().to_string().contains("keyword"));
+  }
 }
@@ -1,44 +1,329 @@
+mod ansi;
+mod blocks;
+mod cache;
+mod color;
 mod font;
 mod render;
 mod svg;
+mod timing;
 mod utils;
+mod warning;
 mod highlight;
 
-use anyhow::Error;
+use anyhow::{anyhow, Context, Error};
 use clap::Parser;
-use font::{FontConfig, FontStyle};
-use highlight::HighlightSetting;
-use render::RenderConfig;
+use color::PaintMode;
+use font::{BaseDirection, FontConfig, FontStyle, LetterSpaceUnit};
+use highlight::{DiffMarkers, HighlightSetting, Palette};
+use render::{AnimateLength, ColumnAlign, Coverage, Crop, GlyphDefsOverflow, Layers, LineHeight, OnShapeFailure, Overflow, Padding, RenderConfig, ShapeRendering, Tile, VAlign};
+use crate::svg::{Decoration, DefMode, FillRule, HrefAttr, MissingGlyph};
+use utils::{Failure, Normalization};
+use warning::{Warning, WarningsFormat};
+use rustybuzz::{Language, Script};
+use std::collections::HashMap;
+use std::io::{IsTerminal, Write};
 use std::path::PathBuf;
+use std::str::FromStr;
+use std::time::Instant;
 
 #[derive(Debug, Parser)]
 #[command(about,version,long_about=None)]
+// options for lines of input file also apply to --line
+#[command(group(clap::ArgGroup::new("lines").args(["file", "line"])))]
 struct Args {
     /// input text string
-    #[arg(conflicts_with = "file")]
+    #[arg(conflicts_with_all = ["file", "line"])]
     text: Option<String>,
 
-    /// max width per line
-    #[arg(long, conflicts_with = "highlight")]
+    /// json array of text blocks composed into one svg, each with text and optional font, size, color, fill, x, y and rotate
+    #[arg(long, conflicts_with_all = ["text", "file", "line", "highlight", "markup"])]
+    json_input: Option<PathBuf>,
+
+    /// write each glyph of the text, or of the whole font without text, to its own svg in this directory, named like u0041-g36.svg
+    #[arg(long, conflicts_with_all = ["file", "line", "json_input", "highlight"])]
+    export_glyphs: Option<PathBuf>,
+
+    /// report the characters of the input the font has no glyph for, instead of rendering
+    #[arg(long, conflicts_with_all = ["json_input", "export_glyphs", "highlight"])]
+    check_coverage: bool,
+
+    /// exit with an error when --check-coverage finds missing characters
+    #[arg(long, requires = "check_coverage")]
+    fail_on_missing: bool,
+
+    /// write only the <defs> of the glyphs of the input, a library other documents <use> (see --id-prefix)
+    #[arg(long, conflicts_with_all = [
+        "json_input", "export_glyphs", "check_coverage", "highlight", "compat_safe", "trim", "html", "markup", "ansi", "columns",
+        "layers", "circle", "tile", "crop", "background_image", "grid", "guides", "hybrid", "symbols", "decoration", "animate",
+        "max_width_px",
+    ])]
+    defs_only: bool,
+
+    /// a line of text, repeat for multiple lines laid out like the lines of a file
+    #[arg(long, conflicts_with_all = ["file", "highlight"])]
+    line: Vec<String>,
+
+    /// max width per line in characters
+    #[arg(long, conflicts_with = "highlight", value_parser = clap::builder::RangedU64ValueParser::<usize>::new().range(1..))]
     width: Option<usize>,
 
-    /// input file
+    /// join the lines of each paragraph before wrapping at --width, paragraphs are separated by blank lines
+    #[arg(long, requires = "width")]
+    reflow: bool,
+
+    /// input file, - reads standard input which is also read when piped without text, --file or --line
     #[arg(long,short, conflicts_with = "text")]
     file: Option<PathBuf>,
 
-    /// output svg file path
+    /// output svg file path, gzip compressed when it ends in .svgz
     #[arg(short, long, default_value = "output.svg")]
     output: Option<PathBuf>,
 
-    /// font
+    /// font, defaults to TEXT2SVG_FONT environment variable
     #[arg(long)]
     font: Option<String>,
 
+    /// font file to use instead of an installed font
+    #[arg(long, conflicts_with = "font")]
+    font_file: Option<PathBuf>,
+
+    /// face of --font-file to use, for collections (.ttc/.otc) with several faces
+    #[arg(long, requires = "font_file", default_value_t = 0)]
+    face_index: u32,
+
+    /// font for the glyphs missing from --font, repeat to try more fonts in order
+    #[arg(long)]
+    font_fallback: Vec<String>,
+
     /// font size
     #[arg(long, default_value_t = 64)]
     size: u32,
 
-    /// svg fill mode or fill color
+    /// px from the top of a line to the baseline, instead of the font size
+    #[arg(long, value_parser = parse_non_negative)]
+    ascent: Option<f32>,
+
+    /// px from the baseline to the bottom of a line, instead of the depth of the glyphs
+    #[arg(long, value_parser = parse_non_negative)]
+    descent: Option<f32>,
+
+    /// height of lines, --ascent and --descent override the metrics of the font
+    #[arg(value_enum, long, default_value = "size")]
+    line_height: LineHeight,
+
+    /// set fill and stroke in a style attribute instead of presentation attributes
+    #[arg(long)]
+    style_attr: bool,
+
+    /// parse ^{superscript}, _{subscript} and @name{text in alternate font} markup in text
+    #[arg(long, requires = "text")]
+    markup: bool,
+
+    /// alternate font used by @name{...} markup, as name:font
+    #[arg(long, requires = "markup", value_parser = parse_font_alt)]
+    font_alt: Vec<(String, String)>,
+
+    /// color lines with their ANSI escape sequences, e.g. captured terminal output
+    #[arg(long, requires = "lines", conflicts_with_all = ["highlight", "width", "columns", "symbols", "hybrid"])]
+    ansi: bool,
+
+    /// strip leading whitespace common to all lines of input file, e.g. an excerpt of indented code
+    #[arg(long, requires = "lines", conflicts_with = "width")]
+    dedent: bool,
+
+    /// leave out blank lines at the end of input file, so the svg ends at the last line with content
+    #[arg(long, requires = "lines")]
+    strip_empty_trailing_lines: bool,
+
+    /// title of the svg, read by assistive technology and shown as tooltip
+    #[arg(long)]
+    title: Option<String>,
+
+    /// description of the svg for assistive technology
+    #[arg(long)]
+    desc: Option<String>,
+
+    /// lay out tab separated cells of input file in aligned columns
+    #[arg(long, requires = "lines", conflicts_with_all = ["highlight", "width", "symbols", "hybrid"])]
+    columns: bool,
+
+    /// alignment of each column for --columns, e.g. left,right, missing columns are left aligned
+    #[arg(value_enum, long, requires = "columns", value_delimiter = ',')]
+    column_align: Vec<ColumnAlign>,
+
+    /// wrap each line of input file in a <symbol> referenced by <use>
+    #[arg(long, requires = "lines", conflicts_with = "highlight")]
+    symbols: bool,
+
+    /// add the source as invisible text over the outlines so it can be selected and copied
+    #[arg(long, conflicts_with = "highlight")]
+    hybrid: bool,
+
+    /// crop output to a region given as x,y,width,height in px
+    #[arg(long)]
+    crop: Option<Crop>,
+
+    /// shape-rendering hint, crisp for pixel-aligned edges, geometric for smooth anti-aliased edges
+    #[arg(value_enum, long, default_value = "auto")]
+    shape_rendering: ShapeRendering,
+
+    /// also write an html page previewing the svg, e.g. output.html
+    #[arg(long)]
+    html: bool,
+
+    /// repeat the rendered text as cols,rows tiles of a pattern, e.g. for watermarks
+    #[arg(long, conflicts_with = "highlight")]
+    tile: Option<Tile>,
+
+    /// space between tiles in px
+    #[arg(long, requires = "tile", default_value_t = 0.0)]
+    tile_gap: f32,
+
+    /// warn about neighbouring glyphs whose ink overlaps, e.g. with negative --space
+    #[arg(long)]
+    warn_overlap: bool,
+
+    /// overlap in px tolerated by --warn-overlap
+    #[arg(long, requires = "warn_overlap", default_value_t = 1.0, value_parser = parse_positive)]
+    overlap_threshold: f32,
+
+    /// what to do with lines of file that fail to shape
+    #[arg(value_enum, long, requires = "lines", default_value = "skip")]
+    on_shape_failure: OnShapeFailure,
+
+    /// print the number of lines, glyphs and glyph definitions to stderr
+    #[arg(long)]
+    stats: bool,
+
+    /// draw lines at the baseline, ascent, descent, x-height and cap height of each line for debugging
+    #[arg(long)]
+    guides: bool,
+
+    /// unicode normalization of the text before shaping
+    #[arg(value_enum, long, default_value = "nfc")]
+    normalize: Normalization,
+
+    /// write glyph outlines inline, once as path or symbol definitions placed with <use>, or as a standalone path per glyph with none
+    #[arg(value_enum, long, default_value = "inline", conflicts_with = "symbols")]
+    def_mode: DefMode,
+
+    /// outline and define every glyph occurrence on its own with an id of its own, to debug glyph definitions
+    #[arg(long)]
+    no_glyph_cache: bool,
+
+    /// cap on the glyph definitions of a document, against inputs with thousands of distinct glyphs
+    #[arg(long)]
+    max_glyph_defs: Option<usize>,
+
+    /// what to do with a document needing more glyph definitions than --max-glyph-defs
+    #[arg(value_enum, long, requires = "max_glyph_defs", default_value = "inline")]
+    on_max_glyph_defs: GlyphDefsOverflow,
+
+    /// reference glyph definitions, symbols and images with xlink:href for SVG 1.1 renderers without bare href
+    #[arg(long)]
+    xlink: bool,
+
+    /// reference with both href and xlink:href, for viewers reading only one of them
+    #[arg(long, conflicts_with = "xlink")]
+    use_compat: bool,
+
+    /// standalone paths without <use> or <defs>, use this if the svg shows up blank in a viewer
+    #[arg(long, conflicts_with_all = ["def_mode", "symbols", "xlink", "use_compat", "tile", "crop"])]
+    compat_safe: bool,
+
+    /// draw glyphs missing from the font as its .notdef glyph, or as a box taking the same space
+    #[arg(value_enum, long, default_value = "notdef")]
+    missing: MissingGlyph,
+
+    /// fill-rule of glyph paths, the svg default nonzero of TrueType and CFF outlines is left implicit unless given
+    #[arg(value_enum, long)]
+    fill_rule: Option<FillRule>,
+
+    /// lay each line out on a circle of this radius in px, starting at the top and running clockwise
+    #[arg(long, value_parser = parse_positive, conflicts_with_all = ["highlight", "ansi", "columns", "markup", "hybrid", "guides", "json_input"])]
+    circle: Option<f32>,
+
+    /// draw underline, overline or line-through across each line, repeatable or comma separated
+    #[arg(value_enum, long, value_delimiter = ',', conflicts_with_all = ["highlight", "circle"])]
+    decoration: Vec<Decoration>,
+
+    /// wrap each line, or each word or glyph within a line layer, in an Inkscape layer labeled with its text
+    #[arg(value_enum, long, conflicts_with_all = ["highlight", "ansi", "columns", "symbols", "hybrid", "markup", "json_input"])]
+    layers: Option<Layers>,
+
+    /// threads outlining the distinct glyphs of a line
+    #[arg(short, long, default_value_t = 1, value_parser = clap::builder::RangedU64ValueParser::<usize>::new().range(1..))]
+    jobs: usize,
+
+    /// space in px around the content
+    #[arg(long, default_value_t = 0.0, value_parser = parse_non_negative)]
+    padding: f32,
+
+    /// space in px above the content, overrides --padding
+    #[arg(long, value_parser = parse_non_negative)]
+    padding_top: Option<f32>,
+
+    /// space in px right of the content, overrides --padding
+    #[arg(long, value_parser = parse_non_negative)]
+    padding_right: Option<f32>,
+
+    /// space in px below the content, overrides --padding
+    #[arg(long, value_parser = parse_non_negative)]
+    padding_bottom: Option<f32>,
+
+    /// space in px left of the content, overrides --padding
+    #[arg(long, value_parser = parse_non_negative)]
+    padding_left: Option<f32>,
+
+    /// image drawn behind the text covering the output: a PNG or JPEG file embedded in the svg, or a url or data uri
+    #[arg(long, conflicts_with_all = ["highlight", "trim"])]
+    background_image: Option<String>,
+
+    /// draw a light grid with lines every this many px behind the text, e.g. to check sizes and positions with --guides
+    #[arg(long, value_parser = parse_positive, conflicts_with = "highlight")]
+    grid: Option<f32>,
+
+    /// swap dark on light and light on dark: the light or dark variant of the theme, or the complement of the colors on black
+    #[arg(long)]
+    invert: bool,
+
+    /// shrink the output to the painted pixels, padding is added around them
+    #[arg(long, conflicts_with_all = ["crop", "height_px", "highlight"])]
+    trim: bool,
+
+    /// output height in px independent of content, see --valign
+    #[arg(long, value_parser = parse_positive)]
+    height_px: Option<f32>,
+
+    /// vertical alignment of content within --height-px
+    #[arg(value_enum, long, requires = "height_px", default_value = "top")]
+    valign: VAlign,
+
+    /// center content vertically within --height-px, same as --valign center
+    #[arg(long, requires = "height_px", conflicts_with = "valign")]
+    center_vertically: bool,
+
+    /// width in px of the box the text is shown in like a UI field, see --overflow
+    #[arg(long, value_parser = parse_positive, requires = "text", conflicts_with_all = ["markup", "layers", "circle", "tile", "crop"])]
+    max_width_px: Option<f32>,
+
+    /// how text wider than --max-width-px is shown
+    #[arg(value_enum, long, requires = "max_width_px", default_value = "clip")]
+    overflow: Overflow,
+
+    /// output resolution, sets svg size in millimeters for print
+    #[arg(long, conflicts_with_all = ["width_mm", "height_mm"])]
+    dpi: Option<f32>,
+
+    /// output width in millimeters
+    #[arg(long)]
+    width_mm: Option<f32>,
+
+    /// output height in millimeters
+    #[arg(long)]
+    height_mm: Option<f32>,
+
+    /// svg fill mode or fill color, auto fills with currentColor to inherit the color of inline html
     #[arg(long, conflicts_with="highlight", default_value = "none")]
     fill: String,
 
@@ -46,15 +331,52 @@ struct Args {
     #[arg(long, conflicts_with="highlight", default_value = "#000")]
     color: String,
 
+    /// paint outlines filled, stroked or both, the fill follows --color unless --fill is given
+    #[arg(value_enum, long, conflicts_with="highlight")]
+    mode: Option<PaintMode>,
+
     /// animation style flag
     #[arg(long, conflicts_with="highlight")]
     animate: bool,
 
+    /// dash length of animation, auto covers the longest glyph contour
+    #[arg(long, requires = "animate", default_value = "450")]
+    animate_length: AnimateLength,
+
+    /// prefix of class and animation names to avoid collisions when embedding several svgs
+    #[arg(long, conflicts_with="unique_ids", default_value = "")]
+    id_prefix: String,
+
+    /// generate a random prefix of class and animation names
+    #[arg(long)]
+    unique_ids: bool,
+
     /// font style
     #[arg(value_enum, long, conflicts_with="highlight", default_value = "regular")]
     style: Option<FontStyle>,
 
-    /// letter space (em)
+    /// unit of letter space
+    #[arg(value_enum, long, default_value = "em")]
+    letter_spacing_unit: LetterSpaceUnit,
+
+    /// shaping script as ISO 15924 tag (e.g. latn, arab), guessed from text by default
+    #[arg(long, value_parser = Script::from_str)]
+    script: Option<Script>,
+
+    /// OpenType feature in harfbuzz syntax (e.g. fina, -liga, ss01=1, zero[0:3]), repeatable.
+    /// Script specific features such as fina or akhn need text shaped as their script, see --script
+    #[arg(long, value_parser = font::parse_feature)]
+    feature: Vec<String>,
+
+    /// shaping language as BCP 47 tag (e.g. tr, sr), guessed by default
+    #[arg(long, value_parser = Language::from_str)]
+    language: Option<Language>,
+
+    /// base direction of each line, auto takes it from the first strong character or a leading RLM/LRM mark
+    #[arg(value_enum, long, default_value = "auto")]
+    direction: BaseDirection,
+
+    /// letter space (see --letter-spacing-unit)
     #[arg(long, default_value_t = 0.1)]
     space: f32,
 
@@ -66,6 +388,30 @@ struct Args {
     #[arg(long, requires="highlight", default_value="base16-ocean.dark")]
     theme: Option<String>,
 
+    /// load a custom highlight theme as name=path.tmTheme, repeatable, select it with --theme name
+    #[arg(long, requires="highlight", value_parser = parse_theme_file)]
+    theme_file: Vec<(String, PathBuf)>,
+
+    /// highlight color palette json with background, foreground, keyword, string, comment and function colors
+    #[arg(long, requires="highlight")]
+    palette: Option<PathBuf>,
+
+    /// add syntax scopes of each token as data-scope attribute
+    #[arg(long, requires="highlight")]
+    emit_scopes: bool,
+
+    /// tint lines starting with + green and lines starting with - red, as in a unified diff
+    #[arg(long, requires="highlight")]
+    diff: bool,
+
+    /// keep the +/- marker column of diff lines or strip it
+    #[arg(value_enum, long, requires="diff", default_value = "keep")]
+    diff_markers: DiffMarkers,
+
+    /// number the lines in a gutter starting at the given line, e.g. the line of an excerpt in its source
+    #[arg(long, requires="highlight")]
+    first_line: Option<usize>,
+
     /// list supported file types/syntax
     #[arg(long)]
     list_syntax: bool,
@@ -74,7 +420,31 @@ struct Args {
     #[arg(long)]
     list_theme: bool,
 
-    /// debug mode
+    /// show progress of rendering files on stderr
+    #[arg(long)]
+    progress: bool,
+
+    /// print the time spent loading fonts, shaping, outlining and serializing to stderr
+    #[arg(long)]
+    timing: bool,
+
+    /// keep glyph outlines in this directory, so later runs with the same fonts don't outline them again
+    #[arg(long)]
+    cache_dir: Option<PathBuf>,
+
+    /// suppress warnings and progress
+    #[arg(short, long)]
+    quiet: bool,
+
+    /// write warnings as text or as a json object per line for scripts
+    #[arg(value_enum, long, default_value = "text")]
+    warnings_format: WarningsFormat,
+
+    /// more log output, repeat for debug (-vv) and trace (-vvv) level, TEXT2SVG_LOG filters by module
+    #[arg(short, long, action = clap::ArgAction::Count)]
+    verbose: u8,
+
+    /// debug mode, same as -vvv
     #[arg(short, long)]
     debug: bool,
 
@@ -83,89 +453,569 @@ struct Args {
     list_fonts: bool,
 }
 
-fn main() {
+fn parse_font_alt(value: &str) -> Result<(String, String), String> {
+    match value.split_once(':') {
+        Some((name, font)) if !name.is_empty() && !font.is_empty() => Ok((name.to_string(), font.to_string())),
+        _ => Err(format!("expect name:font, got {:?}", value)),
+    }
+}
 
-    if let Err(e) = run() {
-        eprintln!("error: {}", e);
+fn parse_theme_file(value: &str) -> Result<(String, PathBuf), String> {
+    match value.split_once('=') {
+        Some((name, path)) if !name.is_empty() && !path.is_empty() => Ok((name.to_string(), PathBuf::from(path))),
+        _ => Err(format!("expect name=path, got {:?}", value)),
     }
 }
 
-fn run() -> Result<(),Error> {
-    let args = Args::parse();
+fn parse_positive(value: &str) -> Result<f32, String> {
+    match value.parse::<f32>() {
+        Ok(number) if number > 0.0 && number.is_finite() => Ok(number),
+        _ => Err(format!("expect a positive number, got {:?}", value)),
+    }
+}
 
-    if args.debug {
-        println!("debug: {:?}", args.debug);
-        println!("args: {:?}", args);
+fn parse_non_negative(value: &str) -> Result<f32, String> {
+    match value.parse::<f32>() {
+        Ok(number) if number >= 0.0 && number.is_finite() => Ok(number),
+        _ => Err(format!("expect a non-negative number, got {:?}", value)),
     }
+}
 
-    if args.list_fonts {
-        let fonts = font::fonts();
-        for name in fonts.iter() {
-            println!("{}", name);
+// font other than --font, shaped and spaced like it
+fn alt_font_config(font: &str, args: &Args, fill_color: &str, stroke_color: &str) -> Result<FontConfig, Error> {
+    let mut alt_config = timing::time(timing::Phase::FontLoading, || {
+        FontConfig::new(font.to_string(), args.size, fill_color.to_string(), stroke_color.to_string())
+    }).context(Failure::FontNotFound)?;
+    alt_config.set_letter_space(args.space);
+    alt_config.set_letter_space_unit(args.letter_spacing_unit);
+    alt_config.set_script(args.script);
+    alt_config.set_language(args.language.clone());
+    alt_config.set_direction(args.direction);
+    for feature in args.feature.iter() {
+        alt_config.add_feature(feature)?;
+    }
+    Ok(alt_config)
+}
+
+/// Source of the text to render
+#[derive(Debug, PartialEq)]
+enum Input {
+    Text(String),
+    /// lines of --line, which may hold line breaks of their own
+    Lines(Vec<String>),
+    File(PathBuf),
+    Stdin,
+}
+
+impl Input {
+    /// Lines of the input, the text is a single line
+    fn read_lines(&self) -> Result<Vec<String>, Error> {
+        Ok(match self {
+            Input::Text(text) => vec![text.clone()],
+            Input::Lines(lines) => lines.iter().flat_map(|line| line.split('\n')).map(String::from).collect(),
+            Input::File(file) => utils::open_file_by_lines(file).context(Failure::InputNotFound)?,
+            Input::Stdin => utils::read_stdin_lines(),
+        })
+    }
+}
+
+impl Args {
+    /// Where the text comes from: the text, --line or --file in that order, which clap keeps exclusive,
+    /// then standard input when it is piped
+    fn input(&self, stdin_piped: bool) -> Result<Input, Error> {
+        if let Some(text) = self.text.as_ref() {
+            return Ok(Input::Text(text.clone()));
+        }
+        if !self.line.is_empty() {
+            return Ok(Input::Lines(self.line.clone()));
+        }
+        match self.file.as_ref() {
+            Some(file) if file.as_os_str() == "-" => Ok(Input::Stdin),
+            Some(file) => Ok(Input::File(file.clone())),
+            None if stdin_piped => Ok(Input::Stdin),
+            None => Err(anyhow!("provide exactly one of: text, --file, --line, or stdin")),
         }
-        return Ok(());
     }
 
-    let mut highight_setting = HighlightSetting::default();
-    if let Some(theme) = args.theme {
-        if highight_setting.get_theme(theme.as_str()).is_none() {
-            highight_setting.add_theme("user-theme", theme);
-            highight_setting.set_theme("user-theme");
+    fn lists(&self) -> bool {
+        self.list_fonts || self.list_syntax || self.list_theme
+    }
+
+    // glyph outlines are always inline with --compat-safe
+    fn def_mode(&self) -> DefMode {
+        if self.compat_safe {
+            return DefMode::Inline;
+        }
+        self.def_mode
+    }
+
+    fn href(&self) -> HrefAttr {
+        match (self.xlink, self.use_compat) {
+            _ if self.compat_safe => HrefAttr::Href,
+            (_, true) => HrefAttr::Both,
+            (true, _) => HrefAttr::Xlink,
+            _ => HrefAttr::Href,
+        }
+    }
+
+    // fill and stroke paint, auto fills with the inherited color and drops the outline
+    fn paint(&self) -> Result<(String, String), Error> {
+        let auto = self.fill.eq_ignore_ascii_case("auto");
+        let fill = if auto { "currentColor".to_string() } else { color::parse_paint(&self.fill)? };
+        let stroke = color::parse_paint(&self.color)?;
+        let (fill, stroke) = match self.mode {
+            Some(mode) => color::mode_paint(mode, &fill, &stroke),
+            None if auto => (fill, "none".to_string()),
+            None => (fill, stroke),
+        };
+        if self.invert {
+            return Ok((color::invert_paint(&fill), color::invert_paint(&stroke)));
+        }
+        Ok((fill, stroke))
+    }
+}
+
+// print the fonts, syntaxes or themes asked for
+fn list(args: &Args, highight_setting: &HighlightSetting, out: &mut impl Write) -> std::io::Result<()> {
+    if args.list_fonts {
+        for name in font::fonts().iter() {
+            writeln!(out, "{}", name)?;
         }
     }
 
     if args.list_syntax {
         for syntax in highight_setting.syntax_set.syntaxes() {
-            println!("- {} (.{})",syntax.name, syntax.file_extensions.join(", ."));
+            writeln!(out, "- {} (.{})",syntax.name, syntax.file_extensions.join(", ."))?;
         }
     }
 
     if args.list_theme {
         for theme in highight_setting.theme_set.themes.keys() {
-            println!("- {} ",theme);
+            writeln!(out, "- {} ",theme)?;
         }
     }
+    Ok(())
+}
 
-    if let Some(font) = args.font {
+// render, and again with outlines inline when the document needs more glyph definitions than --max-glyph-defs
+fn render_capped(
+    overflow: GlyphDefsOverflow,
+    render_config: &mut RenderConfig,
+    mut render: impl FnMut(&RenderConfig) -> Result<(), Error>,
+) -> Result<(), Error> {
+    match render(render_config) {
+        Err(err) if overflow == GlyphDefsOverflow::Inline && err.is::<render::TooManyGlyphDefs>() => {
+            let too_many = err.downcast_ref::<render::TooManyGlyphDefs>().unwrap();
+            warning::warn(
+                Warning::new("too_many_glyph_defs", format!("{}, writing outlines inline", err))
+                    .field("count", too_many.count)
+                    .field("max", too_many.max),
+            );
+            render_config.set_def_mode(DefMode::Inline);
+            render(render_config)
+        }
+        result => result,
+    }
+}
 
-        let mut font_config = FontConfig::new(font,args.size,args.fill,args.color,args.debug)?;
+fn main() {
+    let start = Instant::now();
+    let args = Args::parse();
+    utils::init_logger(utils::log_level(args.verbose, args.debug, args.quiet));
+    warning::set_format(args.warnings_format);
+    let result = run(args).and_then(|_| cache::save());
+    if timing::enabled() {
+        eprint!("{}", timing::report(start.elapsed()));
+    }
+    if let Err(e) = result {
+        eprintln!("error: {:#}", e);
+        std::process::exit(Failure::of(&e).map_or(1, |failure| failure.exit_code()));
+    }
+}
+
+fn run(args: Args) -> Result<(),Error> {
+    if args.timing {
+        timing::enable();
+    }
+    if let Some(dir) = args.cache_dir.clone() {
+        cache::enable(dir);
+    }
+    log::debug!("args: {:?}", args);
+    let stdin_piped = !std::io::stdin().is_terminal();
+
+    let mut highight_setting = HighlightSetting::default();
+    for (name, path) in args.theme_file.iter() {
+        highight_setting.add_theme(name, path)?;
+    }
+    if let Some(theme) = args.theme.as_ref() {
+        if highight_setting.get_theme(theme.as_str()).is_some() {
+            highight_setting.set_theme(theme);
+        } else {
+            highight_setting.add_theme("user-theme", theme)?;
+            highight_setting.set_theme("user-theme");
+        }
+    }
+
+    highight_setting.emit_scopes = args.emit_scopes;
+    highight_setting.diff = args.diff.then_some(args.diff_markers);
+    highight_setting.first_line = args.first_line;
+
+    if let Some(path) = args.palette.as_ref() {
+        let palette = Palette::from_path(path)?;
+        highight_setting.add_theme_from_palette("user-palette", &palette)?;
+        highight_setting.set_theme("user-palette");
+    }
+    if args.invert {
+        highight_setting.invert_theme();
+    }
+
+    // listing exits before rendering, whatever else is given
+    if args.lists() {
+        list(&args, &highight_setting, &mut std::io::stdout().lock())?;
+        return Ok(());
+    }
+
+    let font = font::resolve_font_name(args.font.clone());
+    if font.is_some() || args.font_file.is_some() {
+        let input = args.input(stdin_piped);
+
+        let (fill_color, stroke_color) = args.paint()?;
+        let (def_mode, href) = (args.def_mode(), args.href());
+        let mut alt_fonts = HashMap::new();
+        for (name, alt_font) in args.font_alt.iter() {
+            alt_fonts.insert(name.clone(), alt_font_config(alt_font, &args, &fill_color, &stroke_color)?);
+        }
+        let blocks = args.json_input.as_ref().map(blocks::read_blocks).transpose()?;
+        // fonts named by blocks, by family name
+        let mut block_fonts = HashMap::new();
+        for block_font in blocks.iter().flatten().filter_map(|block| block.font.as_ref()) {
+            if !block_fonts.contains_key(block_font) {
+                block_fonts.insert(block_font.clone(), alt_font_config(block_font, &args, &fill_color, &stroke_color)?);
+            }
+        }
+        let mut fallback_fonts = vec![];
+        for fallback_font in args.font_fallback.iter() {
+            fallback_fonts.push(alt_font_config(fallback_font, &args, &fill_color, &stroke_color)?);
+        }
+        let mut font_config = timing::time(timing::Phase::FontLoading, || match args.font_file {
+            Some(path) => FontConfig::from_path(path, args.face_index, args.size, fill_color, stroke_color),
+            None => FontConfig::new(font.unwrap(), args.size, fill_color, stroke_color),
+        }).context(Failure::FontNotFound)?;
         font_config.set_letter_space(args.space);
+        font_config.set_letter_space_unit(args.letter_spacing_unit);
+        font_config.set_script(args.script);
+        font_config.set_language(args.language);
+        font_config.set_direction(args.direction);
+        for fallback_font in fallback_fonts {
+            font_config.add_fallback(fallback_font);
+        }
+        for feature in args.feature.iter() {
+            font_config.add_feature(feature)?;
+            let tag = feature.trim_start_matches(['+', '-']).get(..4).unwrap_or_default();
+            let scripts = font::feature_scripts(tag);
+            let script = args.script.map(|script| script.tag().to_string().to_lowercase());
+            if !scripts.is_empty() && !script.as_ref().is_some_and(|script| scripts.contains(&script.as_str())) {
+                warning::warn(
+                    Warning::new(
+                        "feature_script",
+                        format!("feature '{}' only applies to {} scripts, use --script to shape text as one of them", tag, scripts.join(", ")),
+                    )
+                    .field("feature", tag)
+                    .field("scripts", scripts.to_vec()),
+                );
+            }
+        }
 
-        if args.debug {
-            println!("{:?}", font_config);
+        log::debug!("{:?}", font_config);
+
+        let style = args.style.unwrap_or(FontStyle::Regular);
+        // highlight mode uses the styles of the theme
+        for (face_style, paths) in font_config.ambiguous_faces() {
+            log::debug!("{:?} style of '{}' found in {:?}", face_style, font_config.get_font_name(), paths);
+            if args.highlight || *face_style == style {
+                let message = format!(
+                    "{} files provide {:?} style of '{}', using {}",
+                    paths.len(),
+                    face_style,
+                    font_config.get_font_name(),
+                    paths.last().unwrap().display()
+                );
+                let files: Vec<String> = paths.iter().map(|path| path.display().to_string()).collect();
+                warning::warn(
+                    Warning::new("ambiguous_face", message)
+                        .field("font", font_config.get_font_name().as_str())
+                        .field("style", face_style.name())
+                        .field("files", files)
+                        .field("used", paths.last().unwrap().display().to_string()),
+                );
+            }
         }
 
-        let mut render_config = RenderConfig::new(args.animate, args.style.unwrap_or(FontStyle::Regular));
+        // --width counts characters which only lines up with fixed-width glyphs
+        if args.width.is_some() && !font_config.is_monospace(&style) {
+            warning::warn(
+                Warning::new(
+                    "proportional_width",
+                    format!("--width counts characters, lines of proportional font '{}' may have uneven right edges", font_config.get_font_name()),
+                )
+                .field("font", font_config.get_font_name().as_str()),
+            );
+        }
+
+        let (font_ascent, font_descent) = font_config
+            .resolve_style(&style)
+            .and_then(|face_style| args.line_height.metrics(&font_config, &face_style))
+            .unzip();
+        let mut render_config = RenderConfig::new(args.animate, style);
+        render_config.set_def_mode(def_mode);
+        render_config.set_glyph_cache(!args.no_glyph_cache);
+        render_config.set_max_glyph_defs(args.max_glyph_defs);
+        render_config.set_href(href);
         render_config.set_max_width(args.width);
+        render_config.set_reflow(args.reflow);
+        render_config.set_animate_length(args.animate_length);
+        render_config.set_progress(args.progress && !args.quiet);
+        render_config.set_style_attr(args.style_attr);
+        render_config.set_markup(args.markup);
+        render_config.set_ansi(args.ansi);
+        render_config.set_symbols(args.symbols);
+        render_config.set_hybrid(args.hybrid);
+        render_config.set_crop(args.crop);
+        render_config.set_shape_rendering(args.shape_rendering);
+        render_config.set_html(args.html);
+        render_config.set_dedent(args.dedent);
+        render_config.set_trim_trailing_lines(args.strip_empty_trailing_lines);
+        let valign = if args.center_vertically { VAlign::Center } else { args.valign };
+        render_config.set_description(args.title, args.desc);
+        render_config.set_columns(args.columns.then_some(args.column_align));
+        render_config.set_tile(args.tile.map(|tile| Tile { gap: args.tile_gap, ..tile }));
+        render_config.set_warn_overlap(args.warn_overlap.then_some(args.overlap_threshold));
+        render_config.set_jobs(args.jobs);
+        render_config.set_missing(args.missing);
+        render_config.set_fill_rule(args.fill_rule);
+        render_config.set_circle(args.circle);
+        render_config.set_decorations(args.decoration);
+        render_config.set_layers(args.layers);
+        render_config.set_metrics(args.ascent.or(font_ascent), args.descent.or(font_descent));
+        render_config.set_normalization(args.normalize);
+        render_config.set_stats(args.stats);
+        render_config.set_guides(args.guides);
+        render_config.set_on_shape_failure(args.on_shape_failure);
+        // individual sides win over the shorthand
+        let mut padding = Padding::uniform(args.padding);
+        padding.top = args.padding_top.unwrap_or(padding.top);
+        padding.right = args.padding_right.unwrap_or(padding.right);
+        padding.bottom = args.padding_bottom.unwrap_or(padding.bottom);
+        padding.left = args.padding_left.unwrap_or(padding.left);
+        render_config.set_padding(padding);
+        render_config.set_trim(args.trim);
+        // colors are taken to be meant for a white page, which inverts to black
+        render_config.set_background((args.invert && !args.highlight).then(|| color::invert_paint("#ffffff")));
+        render_config.set_background_image(args.background_image.as_deref().map(render::image_href).transpose()?);
+        render_config.set_grid(args.grid);
+        render_config.set_height_px(args.height_px, valign);
+        render_config.set_max_width_px(args.max_width_px, args.overflow);
+        render_config.set_physical_size(args.dpi, args.width_mm, args.height_mm);
+        if args.unique_ids {
+            render_config.set_id_prefix(&utils::random_id_prefix());
+        } else {
+            render_config.set_id_prefix(&args.id_prefix);
+        }
 
-        if let Some(text) = args.text {
-            render::render_text_to_svg_file(
-                &text,
-                &mut font_config,
-                &render_config,
-                args.output.unwrap(),
-            );
+        if args.check_coverage {
+            let lines = input?.read_lines()?;
+            let mut coverage = Coverage::default();
+            for line in lines.iter() {
+                coverage.check(line, &mut font_config, &render_config);
+            }
+            println!("{}", coverage);
+            if args.fail_on_missing && !coverage.missing.is_empty() {
+                return Err(anyhow!("'{}' has no glyph for {} characters", font_config.get_font_name(), coverage.missing.len()));
+            }
             return Ok(());
-        } else if let Some(file) = args.file {
-            if args.highlight {
-                render::render_file_highlight(
-                    &file,
-                    &mut font_config,
-                    &highight_setting,
-                    args.output.unwrap(),
-                );
-            }else{
-                render::render_text_file_to_svg(
-                    &file,
-                    &mut font_config,
-                    &render_config,
-                    args.output.unwrap(),
-                );
+        } else if let Some(dir) = args.export_glyphs {
+            let count = render::export_glyphs(args.text.as_deref(), &mut font_config, &render_config, &dir)?;
+            log::info!("exported {} glyphs to {}", count, dir.display());
+            return Ok(());
+        } else if args.defs_only {
+            // glyphs are defined as paths unless --def-mode asks for symbols
+            if !def_mode.has_defs() {
+                render_config.set_def_mode(DefMode::Path);
             }
+            let output = args.output.unwrap();
+            let count = render::render_glyph_defs(&input?.read_lines()?, &mut font_config, &render_config, output.clone())?;
+            log::info!("defined {} glyphs in {}", count, output.display());
             return Ok(());
+        }
 
+        let output = args.output.unwrap();
+        if let Some(blocks) = blocks {
+            return render_capped(args.on_max_glyph_defs, &mut render_config, |render_config| {
+                render::render_blocks_to_svg(&blocks, &mut font_config, &mut block_fonts, render_config, output.clone())
+            });
         }
-        return Ok(());
+        let input = match input? {
+            // the syntax is picked by file name
+            Input::Stdin if args.highlight => return Err(anyhow!("--highlight needs an input file")),
+            // read once, the document may be rendered twice
+            Input::Stdin => Input::Lines(utils::read_stdin_lines()),
+            input => input,
+        };
+        return render_capped(args.on_max_glyph_defs, &mut render_config, |render_config| match &input {
+            // layers are made of lines
+            Input::Text(text) if args.layers.is_some() => {
+                render::render_text_lines_to_svg(std::slice::from_ref(text), &mut font_config, render_config, output.clone())
+            }
+            Input::Text(text) => {
+                render::render_text_to_svg_file(text, &mut font_config, &mut alt_fonts, render_config, output.clone())
+            }
+            Input::File(file) if args.highlight => {
+                render::render_file_highlight(file, &mut font_config, &highight_setting, render_config, output.clone())
+            }
+            Input::File(file) => render::render_text_file_to_svg(file, &mut font_config, render_config, output.clone()),
+            input => render::render_text_lines_to_svg(&input.read_lines()?, &mut font_config, render_config, output.clone()),
+        });
+    }
+
+    if args.json_input.is_some() || args.export_glyphs.is_some() || args.input(stdin_piped).is_ok() {
+        return Err(anyhow!("--font or --font-file is required, or set {} environment variable", font::FONT_ENV));
     }
     Ok(())
 }
+
+#[cfg(test)]
+mod test_main {
+  use super::*;
+
+  #[test]
+  fn test_list_with_text() {
+      // stray text doesn't turn listing into rendering
+      let args = Args::try_parse_from(["text2svg", "--list-theme", "Hello"]).unwrap();
+      assert!(args.lists());
+      let mut out = vec![];
+      list(&args, &HighlightSetting::default(), &mut out).unwrap();
+      let out = String::from_utf8(out).unwrap();
+      assert!(out.contains("- base16-ocean.dark"), "{}", out);
+
+      let args = Args::try_parse_from(["text2svg", "--list-fonts", "Hello"]).unwrap();
+      assert!(args.lists());
+      let mut out = vec![];
+      list(&args, &HighlightSetting::default(), &mut out).unwrap();
+      assert_eq!(String::from_utf8(out).unwrap().lines().count(), font::fonts().len());
+
+      assert!(!Args::try_parse_from(["text2svg", "Hello"]).unwrap().lists());
+  }
+
+  #[test]
+  fn test_defs_only() {
+      // flags drawing anything beside glyph definitions don't apply
+      for flag in ["--markup", "--layers=line", "--circle=100", "--tile=2,2", "--crop=0,0,10,10", "--background-image=a.png", "--decoration=underline", "--animate"] {
+          assert!(Args::try_parse_from(["text2svg", "--defs-only", "--line", "Hello", flag]).is_err(), "{}", flag);
+      }
+      assert!(Args::try_parse_from(["text2svg", "--defs-only", "--max-glyph-defs=10", "--id-prefix=lib-", "--line", "Hello"]).is_ok());
+  }
+
+  #[test]
+  fn test_compat_safe() {
+      for flag in ["--def-mode=path", "--symbols", "--xlink", "--use-compat", "--tile=2,2", "--crop=0,0,10,10"] {
+          assert!(Args::try_parse_from(["text2svg", "--compat-safe", "--line", "Hello", flag]).is_err(), "{}", flag);
+      }
+      let args = Args::try_parse_from(["text2svg", "--compat-safe", "--line", "oo"]).unwrap();
+      let mut render_config = RenderConfig::new(false, FontStyle::Regular);
+      render_config.set_def_mode(args.def_mode());
+      render_config.set_href(args.href());
+      let mut font_config = FontConfig::from_path(PathBuf::from(concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fonts/Text2svgTest-Regular.ttf")), 0, 64, "none".to_string(), "#000".to_string()).unwrap();
+      let output = std::env::temp_dir().join("text2svg-test-compat-safe.svg");
+      render::render_text_lines_to_svg(&args.line, &mut font_config, &render_config, output.clone()).unwrap();
+      let svg = std::fs::read_to_string(&output).unwrap();
+      std::fs::remove_file(&output).unwrap();
+      assert!(svg.contains("<path d="), "{}", svg);
+      assert!(!svg.contains("<use") && !svg.contains("<defs"), "{}", svg);
+  }
+
+  #[test]
+  fn test_input() {
+      let args = |args: &[&str]| Args::try_parse_from([&["text2svg"], args].concat());
+      let input = |args_: &[&str], piped: bool| args(args_).unwrap().input(piped);
+      assert_eq!(input(&["Hello"], false).unwrap(), Input::Text("Hello".to_string()));
+      // explicit input wins over piped standard input
+      assert_eq!(input(&["Hello"], true).unwrap(), Input::Text("Hello".to_string()));
+      assert_eq!(input(&["-f", "a.txt"], true).unwrap(), Input::File(PathBuf::from("a.txt")));
+      assert_eq!(input(&["--line", "a", "--line", "b"], true).unwrap(), Input::Lines(vec!["a".to_string(), "b".to_string()]));
+      assert_eq!(input(&["-f", "-"], false).unwrap(), Input::Stdin);
+      assert_eq!(input(&[], true).unwrap(), Input::Stdin);
+      assert_eq!(input(&[], false).unwrap_err().to_string(), "provide exactly one of: text, --file, --line, or stdin");
+      assert!(args(&["Hello", "-f", "a.txt"]).is_err());
+      assert!(args(&["--line", "a", "-f", "a.txt"]).is_err());
+      assert_eq!(Input::Lines(vec!["a\nb".to_string(), "c".to_string()]).read_lines().unwrap(), vec!["a", "b", "c"]);
+  }
+
+  #[test]
+  fn test_fill_auto() {
+      let paint = |args: &[&str]| Args::try_parse_from([&["text2svg", "Hello"], args].concat()).unwrap().paint().unwrap();
+      assert_eq!(paint(&["--fill", "auto"]), ("currentColor".to_string(), "none".to_string()));
+      assert_eq!(paint(&["--fill", "auto", "--mode", "both", "--color", "red"]), ("currentColor".to_string(), "#ff0000".to_string()));
+      assert_eq!(paint(&[]), ("none".to_string(), "#000000".to_string()));
+
+      let (fill, stroke) = paint(&["--fill", "AUTO"]);
+      let mut font_config = FontConfig::from_path(PathBuf::from(concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fonts/Text2svgTest-Regular.ttf")), 0, 64, fill, stroke).unwrap();
+      let output = std::env::temp_dir().join("text2svg-test-fill-auto.svg");
+      let render_config = RenderConfig::new(false, FontStyle::Regular);
+      render::render_text_lines_to_svg(&["o".to_string()], &mut font_config, &render_config, output.clone()).unwrap();
+      let svg = std::fs::read_to_string(&output).unwrap();
+      std::fs::remove_file(&output).unwrap();
+      assert!(svg.contains(r#"fill="currentColor""#) && svg.contains(r#"stroke="none""#), "{}", svg);
+  }
+
+  #[test]
+  fn test_invert() {
+      let args = Args::try_parse_from(["text2svg", "Hello", "--invert", "--fill", "currentColor"]).unwrap();
+      assert_eq!(args.paint().unwrap(), ("currentColor".to_string(), "#ffffff".to_string()));
+
+      let (fill, stroke) = Args::try_parse_from(["text2svg", "Hello", "--invert"]).unwrap().paint().unwrap();
+      let mut font_config = FontConfig::from_path(PathBuf::from(concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fonts/Text2svgTest-Regular.ttf")), 0, 64, fill, stroke).unwrap();
+      let output = std::env::temp_dir().join("text2svg-test-invert.svg");
+      let mut render_config = RenderConfig::new(false, FontStyle::Regular);
+      render_config.set_background(Some(color::invert_paint("#ffffff")));
+      render::render_text_lines_to_svg(&["o".to_string()], &mut font_config, &render_config, output.clone()).unwrap();
+      let svg = std::fs::read_to_string(&output).unwrap();
+      std::fs::remove_file(&output).unwrap();
+      assert!(svg.contains(r##"<rect fill="#000000""##), "{}", svg);
+      assert!(svg.contains(r##"stroke="#ffffff""##), "{}", svg);
+  }
+
+  #[test]
+  fn test_exit_code() {
+      let exit_code = |args: &[&str]| {
+          let args = Args::try_parse_from([&["text2svg", "-q"], args].concat()).unwrap();
+          Failure::of(&run(args).unwrap_err()).map(|failure| failure.exit_code())
+      };
+      let font = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fonts/Text2svgTest-Regular.ttf");
+      assert_eq!(exit_code(&["--font-file", "/tmp/font-does-not-exist.ttf", "Hello"]), Some(2));
+      assert_eq!(exit_code(&["--font-file", font, "-f", "/tmp/file-does-not-exist"]), Some(3));
+      assert_eq!(exit_code(&["--font-file", font, "-o", "/tmp/dir-does-not-exist/out.svg", "Hello"]), Some(5));
+      assert_eq!(exit_code(&["--font-file", font, "--json-input", "/tmp/file-does-not-exist.json"]), Some(3));
+  }
+
+  #[test]
+  fn test_max_glyph_defs() {
+      let output = std::env::temp_dir().join("text2svg-test-max-glyph-defs.svg");
+      let run_with = |args: &[&str]| {
+          let base = ["text2svg", "-q", "--font-file", concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fonts/Text2svgTest-Regular.ttf"), "--def-mode", "path", "-o", output.to_str().unwrap(), "AVoA"];
+          run(Args::try_parse_from([&base[..], args].concat()).unwrap())
+      };
+      run_with(&["--max-glyph-defs", "3"]).unwrap();
+      let svg = std::fs::read_to_string(&output).unwrap();
+      assert_eq!(svg.matches("<use").count(), 4, "{}", svg);
+
+      // one more distinct glyph than allowed renders the outlines inline
+      run_with(&["--max-glyph-defs", "2"]).unwrap();
+      let svg = std::fs::read_to_string(&output).unwrap();
+      assert!(!svg.contains("<defs") && !svg.contains("<use"), "{}", svg);
+
+      std::fs::remove_file(&output).unwrap();
+      let error = run_with(&["--max-glyph-defs", "2", "--on-max-glyph-defs", "error"]).unwrap_err();
+      assert_eq!(error.to_string(), "3 glyph definitions, more than --max-glyph-defs 2");
+      assert!(!output.exists());
+  }
+}
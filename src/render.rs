@@ -1,35 +1,195 @@
+use anyhow::{anyhow, Context, Result};
+use base64::Engine;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use clap::ValueEnum;
 use resvg::tiny_skia::Point;
-use std::io::BufRead;
+use std::cell::Cell;
+use std::collections::{HashMap, HashSet};
+use std::fmt::Display;
+use std::ops::Range;
 use std::path::PathBuf;
+use std::rc::Rc;
+use std::io::Write;
+use std::str::FromStr;
 use svg::node::element::Rectangle;
-use syntect::easy::HighlightFile;
 
+use rustybuzz::Direction;
 use rustybuzz::Face;
 use rustybuzz::GlyphBuffer;
 
-use crate::font::{FontConfig, FontStyle};
-use crate::highlight::{HighlightColor, HighlightFontStyle, HighlightSetting};
-use crate::svg::Text;
+use crate::ansi::{AnsiParser, AnsiRun};
+use crate::blocks::TextBlock;
+use crate::font::{BaseDirection, FontConfig, FontStyle};
+use crate::highlight::{DiffLine, HighlightColor, HighlightFontStyle, HighlightSetting, ScopedHighlighter};
+use crate::svg::{glyph_height_units, Bounds, Decoration, DefMode, FillRule, GlyphExtent, GlyphRun, HrefAttr, MissingGlyph, Text, TextBuilder, INKSCAPE_NS, XLINK_NS};
+use crate::utils::dedent;
+use crate::utils::Normalization;
 use crate::utils::open_file_by_lines;
+use crate::utils::stream_file_lines;
+use crate::utils::trim_trailing_blank_lines;
+use crate::utils::wrap_lines;
 use crate::utils::open_file_by_lines_width;
+use crate::timing::{self, Phase};
+use crate::utils::Progress;
+use crate::utils::Failure;
+use crate::warning::{self, Warning};
 
-use svg::node::element::{Group, Style};
+use svg::node::element::{ClipPath, Definitions, Description, Group, Image, Line, LinearGradient, Mask, Path, Pattern, Stop, Style, Symbol, Title, Use};
+use svg::node::element::Text as TextElement;
 use svg::Document;
-use syntect::highlighting::Style as TokenStyle;
+use syntect::highlighting::{Color, Style as TokenStyle};
+use unicode_bidi::BidiInfo;
+use unicode_bidi::Level;
 
-// render config for non-highlight mode
+const MM_PER_INCH: f32 = 25.4;
+
+// render config, font style and animation are used in non-highlight mode only
 pub struct RenderConfig {
     animate: bool,
+    animate_length: AnimateLength,
     font_style: FontStyle,
     max_width: Option<usize>,
+    // prefix of class names and keyframes to avoid collisions between embedded svgs
+    id_prefix: String,
+    progress: bool,
+    style_attr: bool,
+    // parse ^{superscript}, _{subscript} and @font{text} markup
+    markup: bool,
+    // color lines with their ANSI escape sequences
+    ansi: bool,
+    // wrap each line of file in a symbol
+    symbols: bool,
+    // add the source as invisible selectable text over the outlines
+    hybrid: bool,
+    // region of the render to output
+    crop: Option<Crop>,
+    shape_rendering: ShapeRendering,
+    // write an html page previewing the svg next to it
+    html: bool,
+    // strip indentation common to all lines of file
+    dedent: bool,
+    // leave out blank lines at the end of file
+    trim_trailing_lines: bool,
+    // accessible name and description of the document
+    title: Option<String>,
+    desc: Option<String>,
+    // lay out tab separated cells of file in aligned columns
+    columns: Option<Vec<ColumnAlign>>,
+    // repeat the content as a pattern, e.g. for watermarks
+    tile: Option<Tile>,
+    // warn about neighbouring glyphs overlapping by more than this many px
+    warn_overlap: Option<f32>,
+    // threads outlining glyphs of a line
+    jobs: usize,
+    // inline glyph outlines or reference definitions
+    def_mode: DefMode,
+    // define each distinct glyph once instead of each occurrence
+    glyph_cache: bool,
+    // occurrences defined on their own without the glyph cache, numbering their ids in the document
+    glyph_occurrences: Cell<usize>,
+    // fail documents needing more glyph definitions
+    max_glyph_defs: Option<usize>,
+    // draw glyphs missing from the font as .notdef or a box
+    missing: MissingGlyph,
+    fill_rule: Option<FillRule>,
+    // radius of the circle lines follow
+    circle: Option<f32>,
+    // lines drawn across each line of text
+    decorations: Vec<Decoration>,
+    // wrap lines, words or glyphs in inkscape layers
+    layers: Option<Layers>,
+    // baseline below the top of a line and depth of the line below it in px
+    ascent: Option<f32>,
+    descent: Option<f32>,
+    // attribute referencing definitions and images
+    href: HrefAttr,
+    // unicode normalization of input text
+    normalization: Normalization,
+    // print a summary of rendered glyphs to stderr
+    stats: bool,
+    // join lines of paragraphs before wrapping at max width
+    reflow: bool,
+    // draw lines at the vertical metrics of the font over each text
+    guides: bool,
+    // lines of file that fail to shape
+    on_shape_failure: OnShapeFailure,
+    // space around the content box
+    padding: Padding,
+    // shrink the viewBox to the painted pixels
+    trim: bool,
+    // color filling the canvas behind the text and background image
+    background: Option<String>,
+    // href of an image drawn behind the text
+    background_image: Option<String>,
+    // spacing in px of the grid drawn behind the text
+    grid: Option<f32>,
+    // fixed output height in px and where content sits in it
+    height_px: Option<f32>,
+    valign: VAlign,
+    // width in px of the box a single line is shown in and how text overflowing it is shown
+    max_width_px: Option<f32>,
+    overflow: Overflow,
+    // physical size of output for print
+    dpi: Option<f32>,
+    width_mm: Option<f32>,
+    height_mm: Option<f32>,
 }
 
 impl RenderConfig {
     pub fn new(animate: bool, style: FontStyle) -> Self {
         Self {
             animate,
+            animate_length: AnimateLength::Fixed(450.0),
             font_style: style,
             max_width: None,
+            id_prefix: String::new(),
+            progress: false,
+            style_attr: false,
+            markup: false,
+            ansi: false,
+            symbols: false,
+            hybrid: false,
+            crop: None,
+            shape_rendering: ShapeRendering::Auto,
+            html: false,
+            dedent: false,
+            trim_trailing_lines: false,
+            title: None,
+            desc: None,
+            columns: None,
+            tile: None,
+            warn_overlap: None,
+            jobs: 1,
+            def_mode: DefMode::Inline,
+            glyph_cache: true,
+            glyph_occurrences: Cell::new(0),
+            max_glyph_defs: None,
+            missing: MissingGlyph::Notdef,
+            fill_rule: None,
+            circle: None,
+            decorations: vec![],
+            layers: None,
+            ascent: None,
+            descent: None,
+            href: HrefAttr::Href,
+            normalization: Normalization::Nfc,
+            stats: false,
+            reflow: false,
+            guides: false,
+            on_shape_failure: OnShapeFailure::Skip,
+            padding: Padding::default(),
+            trim: false,
+            background: None,
+            background_image: None,
+            grid: None,
+            height_px: None,
+            valign: VAlign::Top,
+            max_width_px: None,
+            overflow: Overflow::Clip,
+            dpi: None,
+            width_mm: None,
+            height_mm: None,
         }
     }
 
@@ -38,6 +198,423 @@ impl RenderConfig {
         self
     }
 
+    pub fn set_id_prefix(&mut self, prefix: &str) -> &mut Self {
+        self.id_prefix = prefix.to_string();
+        self
+    }
+
+    pub fn get_id_prefix(&self) -> &str {
+        &self.id_prefix
+    }
+
+    pub fn get_class_name(&self) -> String {
+        format!("{}text", self.id_prefix)
+    }
+
+    pub fn set_progress(&mut self, progress: bool) -> &mut Self {
+        self.progress = progress;
+        self
+    }
+
+    pub fn get_progress(&self) -> bool {
+        self.progress
+    }
+
+    pub fn set_style_attr(&mut self, style_attr: bool) -> &mut Self {
+        self.style_attr = style_attr;
+        self
+    }
+
+    pub fn get_style_attr(&self) -> bool {
+        self.style_attr
+    }
+
+    pub fn set_markup(&mut self, markup: bool) -> &mut Self {
+        self.markup = markup;
+        self
+    }
+
+    pub fn get_markup(&self) -> bool {
+        self.markup
+    }
+
+    pub fn set_ansi(&mut self, ansi: bool) -> &mut Self {
+        self.ansi = ansi;
+        self
+    }
+
+    pub fn get_ansi(&self) -> bool {
+        self.ansi
+    }
+
+    pub fn set_symbols(&mut self, symbols: bool) -> &mut Self {
+        self.symbols = symbols;
+        self
+    }
+
+    pub fn get_symbols(&self) -> bool {
+        self.symbols
+    }
+
+    pub fn set_hybrid(&mut self, hybrid: bool) -> &mut Self {
+        self.hybrid = hybrid;
+        self
+    }
+
+    pub fn get_hybrid(&self) -> bool {
+        self.hybrid
+    }
+
+    pub fn set_crop(&mut self, crop: Option<Crop>) -> &mut Self {
+        self.crop = crop;
+        self
+    }
+
+    pub fn get_crop(&self) -> Option<Crop> {
+        self.crop
+    }
+
+    pub fn set_shape_rendering(&mut self, shape_rendering: ShapeRendering) -> &mut Self {
+        self.shape_rendering = shape_rendering;
+        self
+    }
+
+    pub fn get_shape_rendering(&self) -> ShapeRendering {
+        self.shape_rendering
+    }
+
+    pub fn set_html(&mut self, html: bool) -> &mut Self {
+        self.html = html;
+        self
+    }
+
+    pub fn get_html(&self) -> bool {
+        self.html
+    }
+
+    pub fn set_dedent(&mut self, dedent: bool) -> &mut Self {
+        self.dedent = dedent;
+        self
+    }
+
+    pub fn get_dedent(&self) -> bool {
+        self.dedent
+    }
+
+    pub fn set_trim_trailing_lines(&mut self, trim_trailing_lines: bool) -> &mut Self {
+        self.trim_trailing_lines = trim_trailing_lines;
+        self
+    }
+
+    pub fn get_trim_trailing_lines(&self) -> bool {
+        self.trim_trailing_lines
+    }
+
+    pub fn set_description(&mut self, title: Option<String>, desc: Option<String>) -> &mut Self {
+        self.title = title;
+        self.desc = desc;
+        self
+    }
+
+    pub fn get_title(&self) -> Option<&str> {
+        self.title.as_deref()
+    }
+
+    pub fn get_desc(&self) -> Option<&str> {
+        self.desc.as_deref()
+    }
+
+    pub fn set_columns(&mut self, aligns: Option<Vec<ColumnAlign>>) -> &mut Self {
+        self.columns = aligns;
+        self
+    }
+
+    pub fn get_columns(&self) -> Option<&[ColumnAlign]> {
+        self.columns.as_deref()
+    }
+
+    pub fn set_tile(&mut self, tile: Option<Tile>) -> &mut Self {
+        self.tile = tile;
+        self
+    }
+
+    pub fn get_tile(&self) -> Option<Tile> {
+        self.tile
+    }
+
+    pub fn set_warn_overlap(&mut self, threshold: Option<f32>) -> &mut Self {
+        self.warn_overlap = threshold;
+        self
+    }
+
+    pub fn get_warn_overlap(&self) -> Option<f32> {
+        self.warn_overlap
+    }
+
+    pub fn set_jobs(&mut self, jobs: usize) -> &mut Self {
+        self.jobs = jobs;
+        self
+    }
+
+    pub fn get_jobs(&self) -> usize {
+        self.jobs
+    }
+
+    pub fn set_def_mode(&mut self, def_mode: DefMode) -> &mut Self {
+        self.def_mode = def_mode;
+        self
+    }
+
+    pub fn get_def_mode(&self) -> DefMode {
+        self.def_mode
+    }
+
+    pub fn set_glyph_cache(&mut self, glyph_cache: bool) -> &mut Self {
+        self.glyph_cache = glyph_cache;
+        self
+    }
+
+    /// Counter of occurrences defined on their own, None while the glyph cache is on
+    pub fn get_glyph_occurrences(&self) -> Option<&Cell<usize>> {
+        (!self.glyph_cache).then_some(&self.glyph_occurrences)
+    }
+
+    pub fn set_max_glyph_defs(&mut self, max: Option<usize>) -> &mut Self {
+        self.max_glyph_defs = max;
+        self
+    }
+
+    pub fn get_max_glyph_defs(&self) -> Option<usize> {
+        self.max_glyph_defs
+    }
+
+    pub fn set_missing(&mut self, missing: MissingGlyph) -> &mut Self {
+        self.missing = missing;
+        self
+    }
+
+    pub fn get_missing(&self) -> MissingGlyph {
+        self.missing
+    }
+
+    pub fn set_fill_rule(&mut self, fill_rule: Option<FillRule>) -> &mut Self {
+        self.fill_rule = fill_rule;
+        self
+    }
+
+    pub fn get_fill_rule(&self) -> Option<FillRule> {
+        self.fill_rule
+    }
+
+    pub fn set_circle(&mut self, radius: Option<f32>) -> &mut Self {
+        self.circle = radius;
+        self
+    }
+
+    pub fn get_circle(&self) -> Option<f32> {
+        self.circle
+    }
+
+    pub fn set_decorations(&mut self, decorations: Vec<Decoration>) -> &mut Self {
+        self.decorations = decorations;
+        self
+    }
+
+    pub fn get_decorations(&self) -> &[Decoration] {
+        &self.decorations
+    }
+
+    pub fn set_layers(&mut self, layers: Option<Layers>) -> &mut Self {
+        self.layers = layers;
+        self
+    }
+
+    pub fn get_layers(&self) -> Option<Layers> {
+        self.layers
+    }
+
+    pub fn set_metrics(&mut self, ascent: Option<f32>, descent: Option<f32>) -> &mut Self {
+        self.ascent = ascent;
+        self.descent = descent;
+        self
+    }
+
+    pub fn get_ascent(&self) -> Option<f32> {
+        self.ascent
+    }
+
+    pub fn get_descent(&self) -> Option<f32> {
+        self.descent
+    }
+
+    pub fn set_href(&mut self, href: HrefAttr) -> &mut Self {
+        self.href = href;
+        self
+    }
+
+    pub fn get_href(&self) -> HrefAttr {
+        self.href
+    }
+
+    /// Height of a line of given font size, the size unless the metrics are overridden
+    pub fn line_height(&self, size: u32) -> u32 {
+        if self.ascent.is_none() && self.descent.is_none() {
+            return size;
+        }
+        (self.ascent.unwrap_or(size as f32) + self.descent.unwrap_or(0.0)).ceil() as u32
+    }
+
+    /// Baseline below the top of a line of given font size
+    pub fn baseline(&self, size: u32) -> f32 {
+        self.ascent.unwrap_or(size as f32)
+    }
+
+    pub fn set_normalization(&mut self, normalization: Normalization) -> &mut Self {
+        self.normalization = normalization;
+        self
+    }
+
+    pub fn get_normalization(&self) -> Normalization {
+        self.normalization
+    }
+
+    pub fn set_stats(&mut self, stats: bool) -> &mut Self {
+        self.stats = stats;
+        self
+    }
+
+    pub fn get_stats(&self) -> bool {
+        self.stats
+    }
+
+    pub fn set_guides(&mut self, guides: bool) -> &mut Self {
+        self.guides = guides;
+        self
+    }
+
+    pub fn get_guides(&self) -> bool {
+        self.guides
+    }
+
+    pub fn set_reflow(&mut self, reflow: bool) -> &mut Self {
+        self.reflow = reflow;
+        self
+    }
+
+    pub fn get_reflow(&self) -> bool {
+        self.reflow
+    }
+
+    pub fn set_on_shape_failure(&mut self, on_shape_failure: OnShapeFailure) -> &mut Self {
+        self.on_shape_failure = on_shape_failure;
+        self
+    }
+
+    pub fn get_on_shape_failure(&self) -> OnShapeFailure {
+        self.on_shape_failure
+    }
+
+    pub fn set_padding(&mut self, padding: Padding) -> &mut Self {
+        self.padding = padding;
+        self
+    }
+
+    pub fn get_padding(&self) -> Padding {
+        self.padding
+    }
+
+    pub fn set_background(&mut self, color: Option<String>) -> &mut Self {
+        self.background = color;
+        self
+    }
+
+    pub fn get_background(&self) -> Option<&str> {
+        self.background.as_deref()
+    }
+
+    pub fn set_background_image(&mut self, href: Option<String>) -> &mut Self {
+        self.background_image = href;
+        self
+    }
+
+    pub fn get_background_image(&self) -> Option<&str> {
+        self.background_image.as_deref()
+    }
+
+    pub fn set_grid(&mut self, spacing: Option<f32>) -> &mut Self {
+        self.grid = spacing;
+        self
+    }
+
+    pub fn get_grid(&self) -> Option<f32> {
+        self.grid
+    }
+
+    pub fn set_trim(&mut self, trim: bool) -> &mut Self {
+        self.trim = trim;
+        self
+    }
+
+    pub fn get_trim(&self) -> bool {
+        self.trim
+    }
+
+    pub fn set_height_px(&mut self, height: Option<f32>, valign: VAlign) -> &mut Self {
+        self.height_px = height;
+        self.valign = valign;
+        self
+    }
+
+    pub fn set_max_width_px(&mut self, width: Option<f32>, overflow: Overflow) -> &mut Self {
+        self.max_width_px = width;
+        self.overflow = overflow;
+        self
+    }
+
+    pub fn get_max_width_px(&self) -> Option<f32> {
+        self.max_width_px
+    }
+
+    pub fn get_overflow(&self) -> Overflow {
+        self.overflow
+    }
+
+    /// Content box placed in the fixed output height, the free space goes below, around or above it
+    pub fn fit_height(&self, content: (f32, f32, f32, f32)) -> (f32, f32, f32, f32) {
+        let (x, y, width, height) = content;
+        match self.height_px {
+            Some(target) => {
+                let free = target - height;
+                let offset = match self.valign {
+                    VAlign::Top => 0.0,
+                    VAlign::Center => free / 2.0,
+                    VAlign::Bottom => free,
+                };
+                (x, y - offset, width, target)
+            }
+            None => content,
+        }
+    }
+
+    pub fn set_physical_size(&mut self, dpi: Option<f32>, width_mm: Option<f32>, height_mm: Option<f32>) -> &mut Self {
+        self.dpi = dpi;
+        self.width_mm = width_mm;
+        self.height_mm = height_mm;
+        self
+    }
+
+    /// Physical size in millimeters of a document with given size in px.
+    /// A single given dimension keeps the aspect ratio of the content.
+    pub fn get_physical_size(&self, width: f32, height: f32) -> Option<(f32, f32)> {
+        match (self.width_mm, self.height_mm, self.dpi) {
+            (Some(width_mm), Some(height_mm), _) => Some((width_mm, height_mm)),
+            (Some(width_mm), None, _) => Some((width_mm, width_mm * height / width.max(1.0))),
+            (None, Some(height_mm), _) => Some((height_mm * width / height.max(1.0), height_mm)),
+            (None, None, Some(dpi)) => Some((width / dpi * MM_PER_INCH, height / dpi * MM_PER_INCH)),
+            (None, None, None) => None,
+        }
+    }
+
     pub fn get_font_style(&self) -> &FontStyle {
         &self.font_style
     }
@@ -45,6 +622,15 @@ impl RenderConfig {
     pub fn get_animate(&self) -> bool {
         self.animate
     }
+
+    pub fn set_animate_length(&mut self, length: AnimateLength) -> &mut Self {
+        self.animate_length = length;
+        self
+    }
+
+    pub fn get_animate_length(&self) -> AnimateLength {
+        self.animate_length
+    }
 }
 
 
@@ -52,62 +638,150 @@ pub fn render_file_highlight(
     file: &PathBuf,
     font_config: &mut FontConfig,
     highlight_setting: &HighlightSetting,
+    render_config: &RenderConfig,
     output: PathBuf,
-) {
+) -> Result<()> {
     let mut width: u32 = 0;
     let mut height: u32 = 0;
     let syntax_set = &highlight_setting.syntax_set;
     let theme_set = &highlight_setting.theme_set;
 
     let mut doc = Document::new();
+    let mut glyph_defs = GlyphDefinitions::default();
+    let mut guides = MetricGuides::default();
+    let mut stats = RenderStats::default();
 
     if let Some(theme) = theme_set.themes.get(&highlight_setting.theme) {
-        let mut highlighter = HighlightFile::new(file, syntax_set, theme).unwrap();
-        for l in highlighter.reader.lines() {
-            // render each line in a group tag
-            let line = l.unwrap();
-
-            if !line.is_empty() {
+        let syntax = highlight_setting.find_syntax(file);
+        let mut highlighter = ScopedHighlighter::new(syntax_set, syntax, theme);
+        let mut lines = open_file_by_lines(file).context(Failure::InputNotFound)?;
+        render_config.get_normalization().apply_lines(&mut lines);
+        if render_config.get_trim_trailing_lines() {
+            lines.truncate(lines.iter().rposition(|line| !line.trim().is_empty()).map_or(0, |last| last + 1));
+        }
+        // before highlighting, so the syntax sees the lines as rendered
+        if render_config.get_dedent() {
+            dedent(&mut lines);
+        }
+        let mut progress = Progress::new(lines.len(), render_config.get_progress());
+        // top and color of the diff bands, drawn once the width is known
+        let mut bands = vec![];
+        // numbers are right aligned to the widest one, which lines up in monospace fonts
+        let gutter = highlight_setting.first_line.map(|first| (first, (first + lines.len().max(1) - 1).to_string().len()));
+        let gutter_style = TokenStyle {
+            foreground: theme.settings.gutter_foreground.or(theme.settings.foreground).unwrap_or(Color::WHITE),
+            ..TokenStyle::default()
+        };
+        for (index, line) in lines.iter().enumerate() {
+            progress.inc();
+            let mut x: f32 = 0.0;
+            if let Some((first, digits)) = gutter {
+                let number = format!("{:>digits$} ", first + index);
+                if let Some(mut text) = render_token_to_path(x, height as f32, &number, font_config, render_config, gutter_style) {
+                    x += text.width() as f32;
+                    width = width.max(x.ceil() as u32);
+                    text = text
+                        .set("class", format!("{}line-number", render_config.get_id_prefix()))
+                        .set("data-line", first + index);
+                    glyph_defs.add(&mut text);
+                    guides.add(&mut text);
+                    doc = doc.add(text.into_node());
+                }
+            }
+            let mut line = line.as_str();
+            if let Some(markers) = highlight_setting.diff {
+                let kind = DiffLine::classify(line);
+                if let Some(color) = kind.band_color() {
+                    bands.push((height, color));
+                }
+                line = kind.text(line, markers);
+            }
+            if line.trim().is_empty() {
+                // whitespace-only lines only move down like empty lines, the syntax still sees them to keep its state
+                if !line.is_empty() {
+                    highlighter.highlight_line(line).unwrap();
+                }
+            } else {
+                // render each line in a group tag
+                stats.lines += 1;
                 let mut group = Group::new();
-                let regions = highlighter
-                    .highlight_lines
-                    .highlight_line(line.as_str(), syntax_set)
-                    .unwrap();
-                let mut x: f32 = 0.0;
-                for region in regions.iter() {
-                    let style = region.0;
-                    let token = region.1;
-                    if let Some(text) =
-                        render_token_to_path(x, height as f32, token, font_config, style)
-                    {
-                        x += text.width() as f32;
-                        width = width.max(x as u32);
-                        group = group.add(text.path);
+                let tokens = highlighter.highlight_line(line).unwrap();
+                // the line is shaped once per face and tokens take their glyphs by cluster,
+                // so kerning and ligatures across tokens are the same as in plain rendering
+                let mut shaped_line = HashMap::new();
+                let mut after_glyph = false;
+                let mut start = 0;
+                for (style, scope, token) in tokens.into_iter() {
+                    let range = start..start + token.len();
+                    start = range.end;
+                    let font_style = HighlightFontStyle::new(style.font_style).get_style();
+                    let shaped = shaped_line
+                        .entry(font_style.clone())
+                        .or_insert_with(|| bidi_shape(line, font_config, &font_style));
+                    let Some((shaped_runs, used_style)) = shaped else {
+                        continue;
+                    };
+                    let runs: Vec<GlyphRun> = shaped_runs
+                        .iter()
+                        .map(|shaped| shaped.glyph_run().slice(&range))
+                        .filter(|run| !run.is_empty())
+                        .collect();
+                    if !runs.is_empty() {
+                        let mut text = render_token_runs_to_path(
+                            Point { x, y: height as f32 }, runs, used_style, after_glyph, font_config, render_config, style);
+                        x = text.end_x;
+                        after_glyph = text.after_glyph;
+                        width = width.max(text.bounding_box.x_max.max(0) as u32);
+                        if highlight_setting.emit_scopes {
+                            text = text.set("data-scope", scope);
+                        }
+                        stats.add(&text);
+                        glyph_defs.add(&mut text);
+                        guides.add(&mut text);
+                        group = group.add(text.into_node());
                     }
                 }
                 doc = doc.add(group);
             }
-            height += font_config.get_size();
+            height += render_config.line_height(font_config.get_size());
 
         }
+        progress.finish();
 
         let background_color = HighlightColor::new(theme.settings.background.unwrap());
 
+        let content = (0.0, 0.0, width as f32, height as f32);
+        // the background fills a fixed output height as well
+        let (x, y, width, height) = render_config.fit_height(render_config.get_padding().apply(content));
         let background_rect = Rectangle::new()
+            .set("x", x)
+            .set("y", y)
             .set("width", width)
             .set("height", height)
             .set("fill", background_color.to_string());
 
         let children = doc.get_children_mut();
+        // bands span the padded width, between the background and the text
+        for (index, (top, color)) in bands.into_iter().enumerate() {
+            let band = Rectangle::new()
+                .set("x", x)
+                .set("y", top)
+                .set("width", width)
+                .set("height", render_config.line_height(font_config.get_size()))
+                .set("fill", color);
+            children.insert(index, Box::new(band));
+        }
         children.insert(0, Box::new(background_rect));
 
-        doc = doc
-            .set("height", height)
-            .set("width", width)
-            .set("viewBox", format!("0 0 {} {}", width, height));
+        doc = set_view_box(doc, content, render_config);
+        glyph_defs.check(render_config)?;
+        stats.report(&glyph_defs, render_config);
+        doc = glyph_defs.add_to(doc);
+        doc = guides.add_to(doc, render_config);
 
-        svg::save(output, &doc).unwrap();
+        save_document(output, doc, render_config)?;
     }
+    Ok(())
 }
 
 pub fn render_token_to_path(
@@ -115,159 +789,3137 @@ pub fn render_token_to_path(
     y: f32,
     token: &str,
     font_config: &mut FontConfig,
+    render_config: &RenderConfig,
     style: TokenStyle,
 ) -> Option<Text> {
-    let foreground_color = HighlightColor::new(style.foreground).to_string();
     let font_style = HighlightFontStyle::new(style.font_style).get_style();
 
-    if font_config.get_debug() {
-        println!("font style: {:?}",font_style);
-    }
+    log::debug!("font style: {:?}", font_style);
 
     // shape with harfbuzz algorithm
-    if let Some(glyph_buffer) = text_shape(token, font_config, &font_style) {
-        let mut svg_builder = Text::builder();
-        svg_builder
-            .set_origin(Point { x, y })
-            .set_color(&foreground_color)
-            .set_fill_color(&foreground_color);
+    let (glyph_buffer, font_style) = text_shape(token, font_config, &font_style)?;
+    Some(render_token_runs_to_path(Point { x, y }, vec![GlyphRun::new(0, &glyph_buffer)], &font_style, false, font_config, render_config, style))
+}
 
-        return Some(svg_builder.build(font_config,&font_style, &glyph_buffer));
-    }
-    None
+/// Outline glyphs of a token shaped as part of its line, in the color of the token.
+/// A token continuing the line after a glyph is letter spaced from it.
+fn render_token_runs_to_path(
+    origin: Point,
+    runs: Vec<GlyphRun>,
+    font_style: &FontStyle,
+    after_glyph: bool,
+    font_config: &FontConfig,
+    render_config: &RenderConfig,
+    style: TokenStyle,
+) -> Text {
+    let foreground_color = HighlightColor::new(style.foreground).to_string();
+    let mut svg_builder = Text::builder();
+    svg_builder
+        .set_origin(origin)
+        .set_color(&foreground_color)
+        .set_fill_color(&foreground_color)
+        .set_style_attr(render_config.get_style_attr())
+        .set_jobs(render_config.get_jobs())
+        .set_def_mode(render_config.get_def_mode())
+        .set_glyph_occurrences(render_config.get_glyph_occurrences())
+        .set_id_prefix(render_config.get_id_prefix())
+        .set_guides(render_config.get_guides())
+        .set_missing(render_config.get_missing())
+        .set_fill_rule(render_config.get_fill_rule())
+        .set_href(render_config.get_href())
+        .set_metrics(render_config.get_ascent(), render_config.get_descent())
+        .set_after_glyph(after_glyph);
+    svg_builder.build_runs(font_config, font_style, runs)
 }
 
 pub fn render_text_to_path(x: f32, y: f32, line: &str, font_config: &mut FontConfig, render_config: &RenderConfig) -> Option<Text> {
+    render_continued_text_to_path(x, y, false, line, font_config, render_config)
+}
+
+/// Outline text continuing a line, letter spaced from the glyph before it when after_glyph
+fn render_continued_text_to_path(x: f32, y: f32, after_glyph: bool, line: &str, font_config: &mut FontConfig, render_config: &RenderConfig) -> Option<Text> {
     let style = render_config.get_font_style();
 
     // shape with harfbuzz algorithm
-    if let Some(glyph_buffer) = text_shape(line, font_config, style) {
-        if font_config.get_debug() {
-            println!("shape line: {:?}", line);
+    if let Some((shaped_runs, style)) = bidi_shape(line, font_config, style) {
+        log::debug!("shape line: {:?}", line);
+        let mut svg_builder = line_builder(Point { x, y }, font_config, render_config);
+        svg_builder.set_after_glyph(after_glyph);
+        let text = svg_builder.build_runs(font_config, &style, shaped_runs.iter().map(ShapedRun::glyph_run));
+        if let Some(threshold) = render_config.get_warn_overlap() {
+            for (left, right, overlap) in glyph_overlaps(line, &text.glyph_extents, threshold) {
+                warning::warn(
+                    Warning::new("glyph_overlap", format!("glyphs of {:?} and {:?} overlap by {:.1}px in {:?}", left, right, overlap, line))
+                        .field("left", left.to_string())
+                        .field("right", right.to_string())
+                        .field("overlap", overlap)
+                        .field("line", line),
+                );
+            }
         }
-        let mut svg_builder = Text::builder();
-        let color = font_config.get_color().as_str();
-        let fill_color = font_config.get_fill_color().as_str();
-        svg_builder
-            .set_origin(Point { x, y })
-            .set_color(color)
-            .set_fill_color(fill_color);
-
-        return Some(svg_builder.build(font_config, style, &glyph_buffer));
+        return Some(text);
     }
 
-    if font_config.get_debug() {
-        eprintln!("failed to shape with harfbuzz:\n{:?}", line);
-    }
+    log::debug!("failed to shape with harfbuzz: {:?}", line);
     None
 }
 
-fn get_animation_style() -> Style {
-    Style::new("
-  @keyframes draw {
-    to {
-      stroke-dashoffset: 0;
-    }
-  }
+// builder of a line in the colors of the font
+fn line_builder<'a>(origin: Point, font_config: &'a FontConfig, render_config: &'a RenderConfig) -> TextBuilder<'a> {
+    let mut svg_builder = Text::builder();
+    svg_builder
+        .set_origin(origin)
+        .set_color(font_config.get_color())
+        .set_fill_color(font_config.get_fill_color())
+        .set_style_attr(render_config.get_style_attr())
+        .set_jobs(render_config.get_jobs())
+        .set_def_mode(render_config.get_def_mode())
+        .set_glyph_occurrences(render_config.get_glyph_occurrences())
+        .set_id_prefix(render_config.get_id_prefix())
+        .set_guides(render_config.get_guides())
+        .set_missing(render_config.get_missing())
+        .set_fill_rule(render_config.get_fill_rule())
+        .set_circle(render_config.get_circle())
+        .set_decorations(render_config.get_decorations())
+        .set_href(render_config.get_href())
+        .set_metrics(render_config.get_ascent(), render_config.get_descent());
+    svg_builder
+}
 
-  .text {
-    stroke-dasharray: 450 450;
-    stroke-dashoffset: 450;
-    animation: draw 2.3s ease forwards infinite;
-  }")
+/// Outline the units of a line apart, each with its text as label.
+/// The line is shaped once and units take their glyphs by cluster, so they are placed as in the whole line.
+fn render_line_layers(y: f32, line: &str, layers: Layers, font_config: &mut FontConfig, render_config: &RenderConfig) -> Option<Vec<(String, Text)>> {
+    let (shaped_runs, style) = bidi_shape(line, font_config, render_config.get_font_style())?;
+    let mut units = vec![];
+    let mut x = 0.0;
+    let mut after_glyph = false;
+    for range in layers.units(line) {
+        let runs: Vec<GlyphRun> = shaped_runs
+            .iter()
+            .map(|shaped| shaped.glyph_run().slice(&range))
+            .filter(|run| !run.is_empty())
+            .collect();
+        if runs.is_empty() {
+            continue;
+        }
+        let mut svg_builder = line_builder(Point { x, y }, font_config, render_config);
+        svg_builder.set_after_glyph(after_glyph);
+        let text = svg_builder.build_runs(font_config, &style, runs);
+        x = text.end_x;
+        after_glyph = text.after_glyph;
+        // whitespace only moves the pen
+        let label = line[range].trim();
+        if !label.is_empty() {
+            units.push((label.to_string(), text));
+        }
+    }
+    Some(units)
 }
 
-pub fn render_text_file_to_svg(file: &PathBuf, font_config: &mut FontConfig, render_config: &RenderConfig, output: PathBuf) {
-    let mut width: u32 = 0;
-    let mut height: u32 = 0;
+/// Group edited as a layer named label in Inkscape and Illustrator
+fn layer(label: &str) -> Group {
+    Group::new().set("inkscape:groupmode", "layer").set("inkscape:label", label)
+}
 
-    let file_lines = if render_config.max_width.is_none() {
-        open_file_by_lines(file)
+/// Outline a run of terminal output in its face and color.
+/// The color replaces the fill and stroke of the font, except paint set to none.
+fn render_ansi_runs_to_path(
+    origin: Point,
+    runs: Vec<GlyphRun>,
+    font_style: &FontStyle,
+    after_glyph: bool,
+    run: &AnsiRun,
+    font_config: &FontConfig,
+    render_config: &RenderConfig,
+) -> Text {
+    let paint = |default: &String| match run.color {
+        Some(color) if default != "none" => color.to_string(),
+        _ => default.clone(),
+    };
+    let color = paint(font_config.get_color());
+    let fill_color = paint(font_config.get_fill_color());
+    let mut svg_builder = Text::builder();
+    svg_builder
+        .set_origin(origin)
+        .set_color(&color)
+        .set_fill_color(&fill_color)
+        .set_style_attr(render_config.get_style_attr())
+        .set_jobs(render_config.get_jobs())
+        .set_def_mode(render_config.get_def_mode())
+        .set_glyph_occurrences(render_config.get_glyph_occurrences())
+        .set_id_prefix(render_config.get_id_prefix())
+        .set_guides(render_config.get_guides())
+        .set_missing(render_config.get_missing())
+        .set_fill_rule(render_config.get_fill_rule())
+        .set_decorations(render_config.get_decorations())
+        .set_href(render_config.get_href())
+        .set_metrics(render_config.get_ascent(), render_config.get_descent())
+        .set_after_glyph(after_glyph);
+    svg_builder.build_runs(font_config, font_style, runs)
+}
+
+/// Outline the SGR runs of a line, each in its color and face.
+/// The line is shaped once per face and runs take their glyphs by cluster, so kerning across runs is kept.
+fn render_ansi_line_to_paths(y: f32, runs: Vec<AnsiRun>, font_config: &mut FontConfig, render_config: &RenderConfig) -> Vec<Text> {
+    let line: String = runs.iter().map(|run| run.text.as_str()).collect();
+    let mut shaped_line = HashMap::new();
+    let mut texts = vec![];
+    let mut x = 0.0;
+    let mut after_glyph = false;
+    let mut start = 0;
+    for run in runs.iter() {
+        let range = start..start + run.text.len();
+        start = range.end;
+        let font_style = run.font_style(render_config.get_font_style());
+        let shaped = shaped_line
+            .entry(font_style.clone())
+            .or_insert_with(|| bidi_shape(&line, font_config, &font_style));
+        let Some((shaped_runs, used_style)) = shaped else {
+            continue;
+        };
+        let glyph_runs: Vec<GlyphRun> = shaped_runs
+            .iter()
+            .map(|shaped| shaped.glyph_run().slice(&range))
+            .filter(|glyph_run| !glyph_run.is_empty())
+            .collect();
+        if glyph_runs.is_empty() {
+            continue;
+        }
+        let text = render_ansi_runs_to_path(Point { x, y }, glyph_runs, used_style, after_glyph, run, font_config, render_config);
+        x = text.end_x;
+        after_glyph = text.after_glyph;
+        texts.push(text);
+    }
+    texts
+}
+
+/// Dash length of the drawing animation
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum AnimateLength {
+    /// longest glyph contour of each path
+    Auto,
+    Fixed(f32),
+}
+
+impl FromStr for AnimateLength {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s == "auto" {
+            return Ok(AnimateLength::Auto);
+        }
+        match s.parse::<f32>() {
+            Ok(length) if length > 0.0 && length.is_finite() => Ok(AnimateLength::Fixed(length)),
+            _ => Err(format!("expect auto or a positive length, got {:?}", s)),
+        }
+    }
+}
+
+fn get_animation_style(prefix: &str, length: AnimateLength) -> Style {
+    // with auto length the dash pattern is set on each path instead
+    let dash = match length {
+        AnimateLength::Fixed(length) => format!("
+    stroke-dasharray: {length} {length};
+    stroke-dashoffset: {length};"),
+        AnimateLength::Auto => String::new(),
+    };
+    Style::new(format!("
+  @keyframes {prefix}draw {{
+    to {{
+      stroke-dashoffset: 0;
+    }}
+  }}
+
+  .{prefix}text {{{dash}
+    animation: {prefix}draw 2.3s ease forwards infinite;
+  }}"))
+}
+
+// Dash pattern covering the whole path so that the drawing animation completes
+fn set_animation_length(text: Text, render_config: &RenderConfig) -> Text {
+    if !render_config.get_animate() || render_config.get_animate_length() != AnimateLength::Auto {
+        return text;
+    }
+    let length = text.max_contour_length.ceil();
+    text.set("stroke-dasharray", format!("{} {}", length, length))
+        .set("stroke-dashoffset", length)
+}
+
+/// Glyph definitions of all texts in a document, each glyph defined once
+#[derive(Default)]
+struct GlyphDefinitions {
+    ids: HashSet<String>,
+    definitions: Vec<Box<dyn svg::Node>>,
+}
+
+impl GlyphDefinitions {
+    // take over the definitions of text not defined yet
+    fn add(&mut self, text: &mut Text) {
+        for glyph_def in text.glyph_defs.drain(..) {
+            if self.ids.insert(glyph_def.id.clone()) {
+                self.definitions.push(glyph_def.definition());
+            }
+        }
+    }
+
+    fn len(&self) -> usize {
+        self.definitions.len()
+    }
+
+    // fails when there are more definitions than --max-glyph-defs allows
+    fn check(&self, render_config: &RenderConfig) -> Result<()> {
+        match render_config.get_max_glyph_defs() {
+            Some(max) if self.len() > max => Err(TooManyGlyphDefs { count: self.len(), max }.into()),
+            _ => Ok(()),
+        }
+    }
+
+    fn add_to(self, doc: Document) -> Document {
+        if self.definitions.is_empty() {
+            return doc;
+        }
+        let mut definitions = Definitions::new();
+        for definition in self.definitions {
+            definitions = definitions.add(definition);
+        }
+        doc.add(definitions)
+    }
+}
+
+/// Error of a document needing more glyph definitions than --max-glyph-defs
+#[derive(Debug, PartialEq)]
+pub struct TooManyGlyphDefs {
+    pub count: usize,
+    pub max: usize,
+}
+
+impl Display for TooManyGlyphDefs {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} glyph definitions, more than --max-glyph-defs {}", self.count, self.max)
+    }
+}
+
+impl std::error::Error for TooManyGlyphDefs {}
+
+/// What to do with a document needing more glyph definitions than --max-glyph-defs
+#[derive(ValueEnum, Debug, PartialEq, Clone, Copy, Eq)]
+#[value(rename_all="lower")]
+pub enum GlyphDefsOverflow {
+    /// render the document again with outlines inline
+    Inline,
+    /// abort
+    Error,
+}
+
+/// Metric guides of all texts in a document, drawn over the outlines in a group of their own
+#[derive(Default)]
+struct MetricGuides {
+    guides: Vec<Group>,
+}
+
+impl MetricGuides {
+    fn add(&mut self, text: &mut Text) {
+        if let Some(guides) = text.guides.take() {
+            self.guides.push(guides);
+        }
+    }
+
+    fn add_to(self, doc: Document, render_config: &RenderConfig) -> Document {
+        if self.guides.is_empty() {
+            return doc;
+        }
+        let mut group = Group::new().set("class", format!("{}guides", render_config.get_id_prefix()));
+        for guides in self.guides {
+            group = group.add(guides);
+        }
+        doc.add(group)
+    }
+}
+
+/// Summary of a rendered document printed with --stats
+#[derive(Debug, Default, PartialEq)]
+struct RenderStats {
+    lines: usize,
+    // outlined glyph occurrences
+    glyphs: usize,
+    // distinct glyphs outlined for each text, other occurrences reuse an outline
+    outlined: usize,
+    glyph_defs: usize,
+}
+
+impl RenderStats {
+    fn add(&mut self, text: &Text) {
+        self.glyphs += text.glyph_extents.len();
+        self.outlined += text.outlined_glyphs;
+    }
+
+    fn cache_hit_rate(&self) -> f32 {
+        if self.glyphs == 0 {
+            return 0.0;
+        }
+        1.0 - self.outlined as f32 / self.glyphs as f32
+    }
+
+    fn report(&mut self, glyph_defs: &GlyphDefinitions, render_config: &RenderConfig) {
+        self.glyph_defs = glyph_defs.len();
+        if render_config.get_stats() {
+            eprintln!("{}", self);
+        }
+    }
+}
+
+impl Display for RenderStats {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "lines: {}, glyphs: {}, outlined: {} ({:.0}% cache hits), glyph definitions: {}",
+            self.lines,
+            self.glyphs,
+            self.outlined,
+            self.cache_hit_rate() * 100.0,
+            self.glyph_defs
+        )
+    }
+}
+
+/// Characters of the input the font has no glyph for, found by shaping without building svg
+#[derive(Debug, Default, PartialEq)]
+pub struct Coverage {
+    // distinct characters checked, whitespace and control characters are left out
+    chars: HashSet<char>,
+    // distinct characters shaped to .notdef, in order of appearance
+    pub missing: Vec<char>,
+}
+
+impl Coverage {
+    pub fn check(&mut self, line: &str, font_config: &mut FontConfig, render_config: &RenderConfig) {
+        let line = render_config.get_normalization().apply(line);
+        let counted = |ch: &char| !ch.is_whitespace() && !ch.is_control();
+        self.chars.extend(line.chars().filter(counted));
+        let Some((shaped_runs, style)) = bidi_shape(&line, font_config, render_config.get_font_style()) else {
+            return;
+        };
+        // byte offsets where clusters start, a cluster spans up to the next one
+        let glyph_runs: Vec<GlyphRun> = shaped_runs.iter().map(ShapedRun::glyph_run).collect();
+        let mut clusters: Vec<usize> = glyph_runs
+            .iter()
+            .flat_map(|run| run.infos.iter().map(move |info| run.offset + info.cluster as usize))
+            .collect();
+        clusters.sort_unstable();
+        clusters.dedup();
+        // a character is missing when no font of the fallback chain has it
+        let has_char = |ch: char| {
+            (0..font_config.chain_len()).any(|face| {
+                font_config
+                    .chain_style(face, &style)
+                    .and_then(|face_style| font_config.chain(face).get_font_by_style(&face_style))
+                    .and_then(|face| face.glyph_for_char(ch))
+                    .is_some()
+            })
+        };
+        for run in glyph_runs.iter() {
+            for info in run.infos.iter().filter(|info| info.glyph_id == 0) {
+                let start = run.offset + info.cluster as usize;
+                let end = clusters.iter().find(|cluster| **cluster > start).copied().unwrap_or(line.len());
+                // a cluster with a missing mark also holds the base the font has
+                for ch in line[start..end].chars().filter(counted) {
+                    if !has_char(ch) && !self.missing.contains(&ch) {
+                        self.missing.push(ch);
+                    }
+                }
+            }
+        }
+    }
+
+    pub fn covered_percent(&self) -> f32 {
+        if self.chars.is_empty() {
+            return 100.0;
+        }
+        (self.chars.len() - self.missing.len()) as f32 * 100.0 / self.chars.len() as f32
+    }
+}
+
+impl Display for Coverage {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for ch in self.missing.iter() {
+            writeln!(f, "missing U+{:04X} {}", *ch as u32, ch)?;
+        }
+        write!(
+            f,
+            "coverage: {}/{} characters ({:.1}%)",
+            self.chars.len() - self.missing.len(),
+            self.chars.len(),
+            self.covered_percent()
+        )
+    }
+}
+
+/// What to do with a line of file that fails to shape
+#[derive(ValueEnum, Debug, PartialEq, Clone, Copy, Eq)]
+#[value(rename_all="kebab-case")]
+pub enum OnShapeFailure {
+    /// leave the line out
+    Skip,
+    /// leave a blank line
+    SkipBlank,
+    /// draw a gray bar as long as the line
+    Placeholder,
+    /// abort naming the line
+    Error,
+}
+
+impl OnShapeFailure {
+    // height taken by the failed line with given 1-based number, or an error naming it
+    fn line_height(&self, number: usize, line: &str, size: u32) -> Result<u32> {
+        if *self == OnShapeFailure::Error {
+            return Err(anyhow!("line {}: failed to shape {:?}", number, line).context(Failure::Shaping));
+        }
+        warning::warn(
+            Warning::new("shape_failed", format!("line {}: failed to shape {:?}", number, line))
+                .field("line", number)
+                .field("text", line),
+        );
+        Ok(if *self == OnShapeFailure::Skip { 0 } else { size })
+    }
+}
+
+/// Gray bar at y standing in for a line that failed to shape, about as long as the line in a monospace font
+fn placeholder_bar(line: &str, y: f32, size: u32) -> (Rectangle, Bounds) {
+    let width = line.chars().count() as f32 * size as f32 / 2.0;
+    let bar = Rectangle::new()
+        .set("x", 0)
+        .set("y", y + size as f32 / 4.0)
+        .set("width", width)
+        .set("height", size as f32 / 2.0)
+        .set("fill", "#ccc");
+    let bbox = Bounds {
+        x_min: 0,
+        y_min: y.floor() as i32,
+        x_max: width.ceil() as i32,
+        y_max: (y + size as f32).ceil() as i32,
+    };
+    (bar, bbox)
+}
+
+pub fn render_text_file_to_svg(file: &PathBuf, font_config: &mut FontConfig, render_config: &RenderConfig, output: PathBuf) -> Result<()> {
+    // Lines are read as they are rendered so that huge files aren't held in memory,
+    // unless dedent, columns, reflow or progress need all lines up front.
+    let buffered = render_config.get_dedent()
+        || render_config.get_columns().is_some()
+        || render_config.get_reflow()
+        || render_config.get_progress();
+    let file_lines = if !buffered {
+        stream_file_lines(file, render_config.max_width)
+    } else if render_config.max_width.is_none() {
+        open_file_by_lines(file).map(into_boxed_iter)
+    } else {
+        open_file_by_lines_width(file, render_config.max_width.unwrap(), render_config.get_reflow()).map(into_boxed_iter)
+    };
+
+    let lines = file_lines.context(Failure::InputNotFound)?;
+    render_lines_to_svg(lines, buffered, font_config, render_config, output)
+}
+
+/// Render lines given in memory like the lines of a file, wrapped at max width when set
+pub fn render_text_lines_to_svg(lines: &[String], font_config: &mut FontConfig, render_config: &RenderConfig, output: PathBuf) -> Result<()> {
+    let lines = match render_config.max_width {
+        Some(width) => wrap_lines(lines, width, render_config.get_reflow()),
+        None => lines.to_vec(),
+    };
+    render_lines_to_svg(into_boxed_iter(lines), true, font_config, render_config, output)
+}
+
+// Lay out lines top to bottom, buffered lines are all read before the first is rendered
+fn render_lines_to_svg(
+    lines: Box<dyn Iterator<Item = String>>,
+    buffered: bool,
+    font_config: &mut FontConfig,
+    render_config: &RenderConfig,
+    output: PathBuf,
+) -> Result<()> {
+    let mut height: u32 = 0;
+    let normalization = render_config.get_normalization();
+    let mut lines: Box<dyn Iterator<Item = String>> = Box::new(lines.map(move |line| normalization.apply(&line)));
+    if render_config.get_trim_trailing_lines() {
+        lines = Box::new(trim_trailing_blank_lines(lines));
+    }
+    let mut column_x = None;
+    let mut total = 0;
+    if buffered {
+        let mut all_lines: Vec<String> = lines.collect();
+        log::trace!("lines: {:?}", all_lines);
+        if render_config.get_dedent() {
+            dedent(&mut all_lines);
+        }
+        // x of each cell of tab separated columns
+        column_x = render_config.get_columns().map(|aligns| {
+            let gap = font_config.get_size() as f32 / 2.0;
+            column_positions(&measure_cells(&all_lines, font_config, render_config), aligns, gap)
+        });
+        total = all_lines.len();
+        lines = Box::new(all_lines.into_iter());
+    }
+    let mut group = Group::new().set("class", render_config.get_class_name());
+    // union of all line boxes, lines may extend beyond the origin (e.g. negative left bearing)
+    let mut bbox = Bounds::default();
+    let mut definitions = Definitions::new();
+    let mut glyph_defs = GlyphDefinitions::default();
+    let mut guides = MetricGuides::default();
+    let mut stats = RenderStats::default();
+    let mut text_layer = Group::new();
+    let mut ansi_parser = AnsiParser::default();
+    let mut progress = Progress::new(total, render_config.get_progress());
+    for (index, line) in lines.enumerate() {
+        let line = line.as_str();
+        progress.inc();
+        if line.is_empty() {
+            height += render_config.line_height(font_config.get_size());
+        } else if let Some(positions) = column_x.as_ref() {
+            stats.lines += 1;
+            let mut line_height = render_config.line_height(font_config.get_size());
+            for (cell, x) in line.split('\t').zip(positions[index].iter()) {
+                if let Some(mut text) = render_text_to_path(*x, height as f32, cell, font_config, render_config) {
+                    bbox = union_rect(&bbox, &text.bounding_box);
+                    line_height = line_height.max(text.height());
+                    stats.add(&text);
+                    glyph_defs.add(&mut text);
+                    guides.add(&mut text);
+                    group = group.add(set_animation_length(text, render_config).into_node());
+                }
+            }
+            height += line_height;
+        } else if render_config.get_ansi() {
+            stats.lines += 1;
+            let mut line_height = render_config.line_height(font_config.get_size());
+            let runs = ansi_parser.parse_line(line);
+            for mut text in render_ansi_line_to_paths(height as f32, runs, font_config, render_config) {
+                bbox = union_rect(&bbox, &text.bounding_box);
+                line_height = line_height.max(text.height());
+                stats.add(&text);
+                glyph_defs.add(&mut text);
+                guides.add(&mut text);
+                group = group.add(set_animation_length(text, render_config).into_node());
+            }
+            height += line_height;
+        } else if let Some(units) = render_config
+            .get_layers()
+            .filter(|layers| *layers != Layers::Line)
+            .and_then(|layers| render_line_layers(height as f32, line, layers, font_config, render_config))
+        {
+            stats.lines += 1;
+            let mut line_layer = layer(line);
+            let mut line_height = render_config.line_height(font_config.get_size());
+            for (label, mut text) in units {
+                bbox = union_rect(&bbox, &text.bounding_box);
+                line_height = line_height.max(text.height());
+                stats.add(&text);
+                glyph_defs.add(&mut text);
+                guides.add(&mut text);
+                line_layer = line_layer.add(layer(&label).add(set_animation_length(text, render_config).into_node()));
+            }
+            height += line_height;
+            group = group.add(line_layer);
+        } else if let Some(mut path_line) =
+            render_text_to_path(0.0, height as f32, line, font_config, render_config)
+        {
+            let line_bbox = path_line.bounding_box;
+            bbox = union_rect(&bbox, &line_bbox);
+            if render_config.get_hybrid() {
+                let baseline = height as f32 + render_config.baseline(font_config.get_size());
+                text_layer = text_layer.add(hidden_text(line, baseline, line_bbox.x_max as f32, font_config));
+            }
+            height += path_line.height();
+            stats.lines += 1;
+            stats.add(&path_line);
+            glyph_defs.add(&mut path_line);
+            guides.add(&mut path_line);
+            let text = set_animation_length(path_line, render_config);
+            if render_config.get_symbols() {
+                let id = format!("{}line-{}", render_config.get_id_prefix(), index + 1);
+                let (symbol, line_use) = line_symbol(&id, text.path, &line_bbox, render_config.get_href());
+                definitions = definitions.add(symbol);
+                group = group.add(line_use);
+            } else if render_config.get_layers().is_some() {
+                group = group.add(layer(line).add(text.into_node()));
+            } else {
+                group = group.add(text.into_node());
+            }
+        } else {
+            let on_shape_failure = render_config.get_on_shape_failure();
+            let line_height = on_shape_failure.line_height(index + 1, line, render_config.line_height(font_config.get_size()))?;
+            if on_shape_failure == OnShapeFailure::Placeholder {
+                let (bar, bar_bbox) = placeholder_bar(line, height as f32, font_config.get_size());
+                bbox = union_rect(&bbox, &bar_bbox);
+                group = group.add(bar);
+            }
+            height += line_height;
+        }
+    }
+    progress.finish();
+    bbox.y_max = bbox.y_max.max(height as i32);
+
+    let width = bbox.width();
+    let height = bbox.height();
+    let view_box = (bbox.x_min as f32, bbox.y_min as f32, width as f32, height as f32);
+    let (doc, group, view_box) = tile_group(Document::new(), group, view_box, render_config);
+    let mut doc = set_view_box(doc, view_box, render_config);
+    doc = add_background(doc, view_box, render_config);
+    if render_config.get_symbols() {
+        doc = doc.add(definitions);
+    }
+    glyph_defs.check(render_config)?;
+    stats.report(&glyph_defs, render_config);
+    doc = glyph_defs.add_to(doc);
+    doc = add_cropped(doc, group, render_config);
+    if render_config.get_hybrid() {
+        doc = doc.add(text_layer);
+    }
+    if render_config.get_animate() {
+        doc = doc.add(get_animation_style(render_config.get_id_prefix(), render_config.get_animate_length()));
+    }
+    doc = guides.add_to(doc, render_config);
+
+    save_document(output, doc, render_config)
+}
+
+fn into_boxed_iter(lines: Vec<String>) -> Box<dyn Iterator<Item = String>> {
+    Box::new(lines.into_iter())
+}
+
+/// Invisible text placed over the outlines of a line so that it can be selected and copied.
+/// The glyphs of the fallback font are stretched to the advance of the outlined line.
+fn hidden_text(content: &str, baseline: f32, width: f32, font_config: &FontConfig) -> TextElement {
+    hidden_text_with_font(content, baseline, width, font_config.get_font_name(), font_config.get_size())
+}
+
+fn hidden_text_with_font(content: &str, baseline: f32, width: f32, font_name: &str, size: u32) -> TextElement {
+    TextElement::new()
+        .set("x", 0)
+        .set("y", baseline)
+        .set("font-family", font_name)
+        .set("font-size", size)
+        .set("textLength", width)
+        .set("lengthAdjust", "spacingAndGlyphs")
+        .set("fill-opacity", 0)
+        .add(svg::node::Text::new(content))
+}
+
+/// Horizontal alignment of cells in a column
+#[derive(ValueEnum, Debug, PartialEq, Clone, Copy, Eq)]
+#[value(rename_all="lower")]
+pub enum ColumnAlign {
+    Left,
+    Right,
+    Center,
+}
+
+// advance of each tab separated cell of lines
+fn measure_cells(lines: &[String], font_config: &mut FontConfig, render_config: &RenderConfig) -> Vec<Vec<f32>> {
+    lines
+        .iter()
+        .map(|line| {
+            line.split('\t')
+                .map(|cell| {
+                    render_text_to_path(0.0, 0.0, cell, font_config, render_config)
+                        .map_or(0.0, |text| text.bounding_box.x_max as f32)
+                })
+                .collect()
+        })
+        .collect()
+}
+
+/// x of each cell laid out on a grid like `column -t`: columns are as wide as their widest cell plus gap,
+/// cells are aligned within their column, columns without alignment are left aligned
+pub fn column_positions(cell_widths: &[Vec<f32>], aligns: &[ColumnAlign], gap: f32) -> Vec<Vec<f32>> {
+    let mut column_widths: Vec<f32> = vec![];
+    for widths in cell_widths {
+        for (column, width) in widths.iter().enumerate() {
+            match column_widths.get_mut(column) {
+                Some(column_width) => *column_width = column_width.max(*width),
+                None => column_widths.push(*width),
+            }
+        }
+    }
+    let mut column_starts = vec![0.0];
+    for width in column_widths.iter() {
+        column_starts.push(column_starts.last().unwrap() + width + gap);
+    }
+    cell_widths
+        .iter()
+        .map(|widths| {
+            widths
+                .iter()
+                .enumerate()
+                .map(|(column, width)| {
+                    let free = column_widths[column] - width;
+                    column_starts[column] + match aligns.get(column).unwrap_or(&ColumnAlign::Left) {
+                        ColumnAlign::Left => 0.0,
+                        ColumnAlign::Right => free,
+                        ColumnAlign::Center => free / 2.0,
+                    }
+                })
+                .collect()
+        })
+        .collect()
+}
+
+/// Wrap a line in a symbol with the line box as viewBox, and the use element placing it back
+fn line_symbol(id: &str, path: Path, bbox: &Bounds, href: HrefAttr) -> (Symbol, Use) {
+    let view_box = (bbox.x_min, bbox.y_min, bbox.width(), bbox.height());
+    let symbol = Symbol::new()
+        .set("id", id)
+        .set("viewBox", view_box)
+        .add(path);
+    let line_use = href
+        .set(Use::new(), format!("#{}", id))
+        .set("x", bbox.x_min)
+        .set("y", bbox.y_min)
+        .set("width", bbox.width())
+        .set("height", bbox.height());
+    (symbol, line_use)
+}
+
+pub fn render_text_to_svg_file(text: &str, font_config: &mut FontConfig, alt_fonts: &mut HashMap<String, FontConfig>, render_config: &RenderConfig, output: PathBuf) -> Result<()> {
+    let text = &render_config.get_normalization().apply(text);
+    let text = &match render_config.get_max_width_px() {
+        Some(max_width) if render_config.get_overflow() == Overflow::Ellipsis => ellipsize(text, max_width, font_config, render_config),
+        _ => text.to_string(),
+    };
+    let runs = if render_config.get_markup() {
+        parse_markup(text)
+    } else {
+        vec![TextRun::new(text, RunPosition::Baseline)]
+    };
+
+    let mut group = Group::new().set("class", render_config.get_class_name());
+    let mut bbox: Option<Bounds> = None;
+    let mut glyph_defs = GlyphDefinitions::default();
+    let mut guides = MetricGuides::default();
+    let mut stats = RenderStats { lines: 1, ..Default::default() };
+    let mut x = 0.0;
+    let mut after_glyph = false;
+    for run in runs.iter() {
+        // shape with harfbuzz algorithm
+        let run_font_config = match &run.font {
+            Some(name) => match alt_fonts.get_mut(name) {
+                Some(alt_font) => alt_font,
+                None => {
+                    warning::warn(
+                        Warning::new("unknown_alt_font", format!("Unknown alternate font {:?}, use --font-alt {}:<font>", name, name))
+                            .field("name", name.as_str()),
+                    );
+                    &mut *font_config
+                }
+            },
+            None => &mut *font_config,
+        };
+        // fonts share the baseline at the font size, so runs line up whatever their metrics
+        if let Some(mut text_path) = render_run_to_path(x, after_glyph, run, run_font_config, render_config) {
+            x = text_path.end_x;
+            after_glyph = text_path.after_glyph;
+            bbox = Some(match bbox {
+                Some(bbox) => union_rect(&bbox, &text_path.bounding_box),
+                None => text_path.bounding_box,
+            });
+            stats.add(&text_path);
+            glyph_defs.add(&mut text_path);
+            guides.add(&mut text_path);
+            group = group.add(set_animation_length(text_path, render_config).into_node());
+        }
+    }
+
+    if let Some(bbox) = bbox {
+        let height = bbox.height();
+        let width = bbox.width();
+
+        let view_box = (bbox.x_min as f32, bbox.y_min as f32, width as f32, height as f32);
+        let (doc, group, view_box) = match render_config.get_max_width_px() {
+            Some(max_width) if view_box.2 > max_width => overflow_group(group, (view_box.0, view_box.1, max_width, view_box.3), font_config, render_config),
+            _ => (Document::new(), group, view_box),
+        };
+        let (doc, group, view_box) = tile_group(doc, group, view_box, render_config);
+        let doc = set_view_box(doc, view_box, render_config);
+        let doc = add_background(doc, view_box, render_config);
+        glyph_defs.check(render_config)?;
+        stats.report(&glyph_defs, render_config);
+        let doc = glyph_defs.add_to(doc);
+        let mut doc = add_cropped(doc, group, render_config);
+        if render_config.get_hybrid() {
+            // markup is dropped, the selection is the text as read
+            let content: String = runs.iter().map(|run| run.text.as_str()).collect();
+            let baseline = render_config.baseline(font_config.get_size());
+            doc = doc.add(hidden_text(&content, baseline, x, font_config));
+        }
+        if render_config.get_animate() {
+            doc = doc.add(get_animation_style(render_config.get_id_prefix(), render_config.get_animate_length()));
+        }
+        doc = guides.add_to(doc, render_config);
+
+        save_document(output, doc, render_config)?;
+    }
+    Ok(())
+}
+
+// Longest start of text that fits in max_width px with an ellipsis after it, the text as is when it fits.
+// Fonts without a glyph for the ellipsis get three dots.
+fn ellipsize(text: &str, max_width: f32, font_config: &mut FontConfig, render_config: &RenderConfig) -> String {
+    let has_ellipsis = font_config
+        .get_font_by_style(render_config.get_font_style())
+        .and_then(|font| font.glyph_for_char('…'))
+        .is_some_and(|glyph_id| glyph_id != 0);
+    let ellipsis = if has_ellipsis { "…" } else { "..." };
+    let mut width = |text: &str| {
+        render_text_to_path(0.0, 0.0, text, font_config, render_config).map_or(0.0, |text| text.bounding_box.width() as f32)
+    };
+    if width(text) <= max_width {
+        return text.to_string();
+    }
+    let cut = |end: usize| format!("{}{}", text[..end].trim_end(), ellipsis);
+    let ends: Vec<usize> = text.char_indices().map(|(index, _)| index).collect();
+    // starts get wider as they get longer, so the ones that fit come first
+    let fitting = ends.partition_point(|&end| width(&cut(end)) <= max_width);
+    cut(ends[fitting.saturating_sub(1)])
+}
+
+// Content cut or faded out at the right edge of the box, defined in the document returned with the group
+fn overflow_group(group: Group, view_box: (f32, f32, f32, f32), font_config: &FontConfig, render_config: &RenderConfig) -> (Document, Group, (f32, f32, f32, f32)) {
+    let (x, y, width, height) = view_box;
+    let id = format!("{}overflow", render_config.get_id_prefix());
+    let box_rect = Rectangle::new().set("x", x).set("y", y).set("width", width).set("height", height);
+    let (definition, group): (Box<dyn svg::Node>, Group) = match render_config.get_overflow() {
+        Overflow::Fade => {
+            // over the last em, at most half the box
+            let fade = (font_config.get_size() as f32).min(width / 2.0);
+            let gradient = LinearGradient::new()
+                .set("id", format!("{}-gradient", id))
+                .set("gradientUnits", "userSpaceOnUse")
+                .set("x1", x + width - fade)
+                .set("y1", 0)
+                .set("x2", x + width)
+                .set("y2", 0)
+                .add(Stop::new().set("offset", 0).set("stop-color", "#fff"))
+                .add(Stop::new().set("offset", 1).set("stop-color", "#fff").set("stop-opacity", 0));
+            let mask = Mask::new()
+                .set("id", id.as_str())
+                .add(gradient)
+                .add(box_rect.set("fill", format!("url(#{}-gradient)", id)));
+            (Box::new(mask), group.set("mask", format!("url(#{})", id)))
+        }
+        _ => (Box::new(ClipPath::new().set("id", id.as_str()).add(box_rect)), group.set("clip-path", format!("url(#{})", id))),
+    };
+    (Document::new().add(Definitions::new().add(definition)), group, view_box)
+}
+
+/// Compose text blocks into one svg, each block placed and rotated at its top left corner.
+/// Blocks naming a font are drawn with the font of that name in fonts.
+pub fn render_blocks_to_svg(
+    blocks: &[TextBlock],
+    font_config: &mut FontConfig,
+    fonts: &mut HashMap<String, FontConfig>,
+    render_config: &RenderConfig,
+    output: PathBuf,
+) -> Result<()> {
+    let mut group = Group::new().set("class", render_config.get_class_name());
+    // x_min, y_min, x_max, y_max of the placed blocks
+    let mut bbox: Option<(f32, f32, f32, f32)> = None;
+    let mut glyph_defs = GlyphDefinitions::default();
+    let mut stats = RenderStats::default();
+    for (index, block) in blocks.iter().enumerate() {
+        let block_font = match &block.font {
+            Some(name) => fonts.get_mut(name).ok_or_else(|| anyhow!("block {}: font {:?} is not loaded", index + 1, name))?,
+            None => &mut *font_config,
+        };
+        // ids of glyph definitions stay apart between blocks
+        let id_prefix = format!("{}block-{}-", render_config.get_id_prefix(), index + 1);
+        // the loaded font takes the size and colors of the block, and gets its own back after the block
+        let (font_size, font_color, font_fill_color) =
+            (block_font.get_size(), block_font.get_color().clone(), block_font.get_fill_color().clone());
+        let size = block.size.unwrap_or(font_size);
+        block_font.set_size(size);
+        if let Some(color) = &block.color {
+            block_font.set_color(color);
+        }
+        if let Some(fill) = &block.fill {
+            block_font.set_fill_color(fill);
+        }
+        let mut block_group = Group::new().set(
+            "transform",
+            format!("translate({} {}) rotate({})", block.x, block.y, block.rotate),
+        );
+        for (number, line) in block.text.lines().enumerate() {
+            if line.is_empty() {
+                continue;
+            }
+            let y = number as f32 * render_config.line_height(size) as f32;
+            let mut text = match render_block_line_to_path(y, line, &id_prefix, block_font, render_config) {
+                Some(text) => text,
+                None => {
+                    warning::warn(
+                        Warning::new("shape_failed", format!("block {}: failed to shape {:?}", index + 1, line))
+                            .field("block", index + 1)
+                            .field("text", line),
+                    );
+                    continue;
+                }
+            };
+            let (x_min, y_min, x_max, y_max) = placed_box(&text.bounding_box, block);
+            bbox = Some(match bbox {
+                Some(bbox) => (bbox.0.min(x_min), bbox.1.min(y_min), bbox.2.max(x_max), bbox.3.max(y_max)),
+                None => (x_min, y_min, x_max, y_max),
+            });
+            stats.lines += 1;
+            stats.add(&text);
+            glyph_defs.add(&mut text);
+            // guides turn along with the block
+            let text_guides = text.guides.take();
+            block_group = block_group.add(set_animation_length(text, render_config).into_node());
+            if let Some(text_guides) = text_guides {
+                block_group = block_group.add(text_guides);
+            }
+        }
+        group = group.add(block_group);
+        block_font.set_size(font_size).set_color(&font_color).set_fill_color(&font_fill_color);
+    }
+
+    let (x_min, y_min, x_max, y_max) = bbox.ok_or_else(|| anyhow!("no block to render"))?;
+    let view_box = (x_min.floor(), y_min.floor(), x_max.ceil() - x_min.floor(), y_max.ceil() - y_min.floor());
+    let (doc, group, view_box) = tile_group(Document::new(), group, view_box, render_config);
+    let doc = set_view_box(doc, view_box, render_config);
+    let doc = add_background(doc, view_box, render_config);
+    glyph_defs.check(render_config)?;
+    stats.report(&glyph_defs, render_config);
+    let doc = glyph_defs.add_to(doc);
+    let mut doc = add_cropped(doc, group, render_config);
+    if render_config.get_animate() {
+        doc = doc.add(get_animation_style(render_config.get_id_prefix(), render_config.get_animate_length()));
+    }
+
+    save_document(output, doc, render_config)
+}
+
+// Line of a block in the size and colors of its font, with its top at y in the block
+fn render_block_line_to_path(
+    y: f32,
+    line: &str,
+    id_prefix: &str,
+    font_config: &mut FontConfig,
+    render_config: &RenderConfig,
+) -> Option<Text> {
+    let (shaped_runs, style) = bidi_shape(line, font_config, render_config.get_font_style())?;
+    let mut svg_builder = Text::builder();
+    svg_builder
+        .set_origin(Point { x: 0.0, y })
+        .set_color(font_config.get_color())
+        .set_fill_color(font_config.get_fill_color())
+        .set_style_attr(render_config.get_style_attr())
+        .set_jobs(render_config.get_jobs())
+        .set_def_mode(render_config.get_def_mode())
+        .set_glyph_occurrences(render_config.get_glyph_occurrences())
+        .set_id_prefix(id_prefix)
+        .set_guides(render_config.get_guides())
+        .set_missing(render_config.get_missing())
+        .set_fill_rule(render_config.get_fill_rule())
+        .set_href(render_config.get_href())
+        .set_metrics(render_config.get_ascent(), render_config.get_descent());
+    Some(svg_builder.build_runs(font_config, &style, shaped_runs.iter().map(ShapedRun::glyph_run)))
+}
+
+/// Box x_min, y_min, x_max, y_max of a text box rotated and moved like its block
+fn placed_box(bbox: &Bounds, block: &TextBlock) -> (f32, f32, f32, f32) {
+    let (sin, cos) = block.rotate.to_radians().sin_cos();
+    let corners = [
+        (bbox.x_min, bbox.y_min),
+        (bbox.x_max, bbox.y_min),
+        (bbox.x_min, bbox.y_max),
+        (bbox.x_max, bbox.y_max),
+    ]
+    .map(|(x, y)| {
+        let (x, y) = (x as f32, y as f32);
+        (block.x + x * cos - y * sin, block.y + x * sin + y * cos)
+    });
+    corners.iter().fold(
+        (f32::INFINITY, f32::INFINITY, f32::NEG_INFINITY, f32::NEG_INFINITY),
+        |(x_min, y_min, x_max, y_max), (x, y)| (x_min.min(*x), y_min.min(*y), x_max.max(*x), y_max.max(*y)),
+    )
+}
+
+/// Vertical position of a text run
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum RunPosition {
+    Baseline,
+    Superscript,
+    Subscript,
+}
+
+impl RunPosition {
+    // OpenType feature providing designed glyphs for the position
+    fn feature(&self) -> Option<&'static str> {
+        match self {
+            RunPosition::Baseline => None,
+            RunPosition::Superscript => Some("sups"),
+            RunPosition::Subscript => Some("subs"),
+        }
+    }
+
+    // baseline shift in em when the font has no designed glyphs, positive is upward
+    fn baseline_shift(&self) -> f32 {
+        match self {
+            RunPosition::Baseline => 0.0,
+            RunPosition::Superscript => 0.35,
+            RunPosition::Subscript => -0.15,
+        }
+    }
+}
+
+/// A piece of text rendered at the same position with the same font
+#[derive(Debug, PartialEq, Eq)]
+pub struct TextRun {
+    pub text: String,
+    pub position: RunPosition,
+    // name of alternate font, None for the main font
+    pub font: Option<String>,
+}
+
+impl TextRun {
+    pub fn new(text: &str, position: RunPosition) -> Self {
+        Self {
+            text: text.to_string(),
+            position,
+            font: None,
+        }
+    }
+
+    pub fn with_font(mut self, font: &str) -> Self {
+        self.font = Some(font.to_string());
+        self
+    }
+}
+
+// scale of super/subscript without designed glyphs
+const SCRIPT_SCALE: f32 = 0.7;
+
+/// Split text marked with `^{superscript}`, `_{subscript}` and `@name{text in alternate font}` into runs.
+/// Markers without a closing brace are kept as plain text.
+pub fn parse_markup(text: &str) -> Vec<TextRun> {
+    let mut runs = vec![];
+    let mut plain = String::new();
+    let mut rest = text;
+    while !rest.is_empty() {
+        if let Some((run, len)) = parse_markup_run(rest) {
+            if !plain.is_empty() {
+                runs.push(TextRun::new(&plain, RunPosition::Baseline));
+                plain.clear();
+            }
+            runs.push(run);
+            rest = &rest[len..];
+            continue;
+        }
+        let ch = rest.chars().next().unwrap();
+        plain.push(ch);
+        rest = &rest[ch.len_utf8()..];
+    }
+    if !plain.is_empty() {
+        runs.push(TextRun::new(&plain, RunPosition::Baseline));
+    }
+    runs
+}
+
+// marked run at the start of text and its length in bytes
+fn parse_markup_run(text: &str) -> Option<(TextRun, usize)> {
+    let (marker, position) = if text.starts_with("^{") {
+        (None, RunPosition::Superscript)
+    } else if text.starts_with("_{") {
+        (None, RunPosition::Subscript)
+    } else if let Some(name) = text.strip_prefix('@') {
+        let len = name.find(|c: char| !(c.is_ascii_alphanumeric() || c == '_' || c == '-'))?;
+        if len == 0 || !name[len..].starts_with('{') {
+            return None;
+        }
+        (Some(&name[..len]), RunPosition::Baseline)
     } else {
-        open_file_by_lines_width(file, render_config.max_width.unwrap())
+        return None;
+    };
+    let start = text.find('{')? + 1;
+    let end = start + text[start..].find('}')?;
+    let run = TextRun::new(&text[start..end], position);
+    let run = match marker {
+        Some(name) => run.with_font(name),
+        None => run,
+    };
+    Some((run, end + 1))
+}
+
+/// Render a run, super/subscripts use the font sups/subs glyphs when available,
+/// otherwise they are scaled down and shifted from the baseline.
+/// A run continuing the text after a glyph is letter spaced from it.
+pub fn render_run_to_path(x: f32, after_glyph: bool, run: &TextRun, font_config: &mut FontConfig, render_config: &RenderConfig) -> Option<Text> {
+    let feature = match run.position.feature() {
+        Some(feature) => feature,
+        None => return render_continued_text_to_path(x, 0.0, after_glyph, &run.text, font_config, render_config),
+    };
+
+    let style = render_config.get_font_style().clone();
+    if font_config.has_gsub_feature(&style, feature.as_bytes().try_into().unwrap()) {
+        let has_feature = font_config.has_feature(feature);
+        // the superscript and subscript tags are valid settings
+        font_config.add_feature(feature).unwrap();
+        let text = render_continued_text_to_path(x, 0.0, after_glyph, &run.text, font_config, render_config);
+        if !has_feature {
+            font_config.remove_feature(feature);
+        }
+        return text;
+    }
+
+    let size = font_config.get_size() as f32;
+    let run_size = size * SCRIPT_SCALE;
+    // metrics set for the font size, scaled like the run
+    let run_ascent = render_config.get_ascent().map(|ascent| ascent * SCRIPT_SCALE);
+    let run_descent = render_config.get_descent().map(|descent| descent * SCRIPT_SCALE);
+    // keep the run baseline shifted from the text baseline
+    let y = render_config.baseline(font_config.get_size()) - run.position.baseline_shift() * size - run_ascent.unwrap_or(run_size);
+    let (glyph_buffer, style) = text_shape(&run.text, font_config, &style)?;
+    let mut svg_builder = Text::builder();
+    svg_builder
+        .set_origin(Point { x, y })
+        .set_color(font_config.get_color())
+        .set_fill_color(font_config.get_fill_color())
+        .set_style_attr(render_config.get_style_attr())
+        .set_size(run_size)
+        .set_jobs(render_config.get_jobs())
+        .set_def_mode(render_config.get_def_mode())
+        .set_glyph_occurrences(render_config.get_glyph_occurrences())
+        .set_id_prefix(render_config.get_id_prefix())
+        .set_guides(render_config.get_guides())
+        .set_missing(render_config.get_missing())
+        .set_fill_rule(render_config.get_fill_rule())
+        .set_circle(render_config.get_circle())
+        .set_href(render_config.get_href())
+        .set_metrics(run_ascent, run_descent)
+        .set_after_glyph(after_glyph);
+    Some(svg_builder.build(font_config, &style, &glyph_buffer))
+}
+
+/// Write each glyph of text, or of the whole font without text, to its own svg in dir,
+/// named by code point and glyph id. Returns the number of files written.
+pub fn export_glyphs(text: Option<&str>, font_config: &mut FontConfig, render_config: &RenderConfig, dir: &PathBuf) -> Result<usize> {
+    let style = font_config
+        .resolve_style(render_config.get_font_style())
+        .ok_or_else(|| anyhow!("no face of '{}' to export", font_config.get_font_name()))?;
+    let mut chars: Vec<char> = match text {
+        Some(text) => text.chars().collect(),
+        None => font_config.characters(&style),
     };
+    chars.sort_unstable();
+    chars.dedup();
+    let mut svg_builder = Text::builder();
+    svg_builder
+        .set_color(font_config.get_color())
+        .set_fill_color(font_config.get_fill_color())
+        .set_style_attr(render_config.get_style_attr())
+        .set_jobs(render_config.get_jobs());
+    let glyphs = svg_builder.build_glyphs(font_config, &style, &chars);
+    std::fs::create_dir_all(dir).map_err(|err| anyhow!("{}: {}", dir.display(), err))?;
+    for glyph in glyphs.iter() {
+        let doc = Document::new()
+            .set("viewBox", (0, 0, glyph.width, glyph.height))
+            .set("width", glyph.width)
+            .set("height", glyph.height)
+            .add(glyph.path.clone());
+        let output = dir.join(glyph.file_name());
+        write_document(&output, &doc)
+            .map_err(|err| anyhow!("{}: {}", output.display(), err))
+            .context(Failure::OutputWrite)?;
+    }
+    Ok(glyphs.len())
+}
+
+/// Only the glyph definitions of the lines, each line shaped as when it is rendered.
+/// Returns the number of glyphs defined.
+pub fn render_glyph_defs(lines: &[String], font_config: &mut FontConfig, render_config: &RenderConfig, output: PathBuf) -> Result<usize> {
+    let mut glyph_defs = GlyphDefinitions::default();
+    for line in lines.iter() {
+        let line = render_config.get_normalization().apply(line);
+        if let Some(mut text) = render_text_to_path(0.0, 0.0, &line, font_config, render_config) {
+            glyph_defs.add(&mut text);
+        }
+    }
+    glyph_defs.check(render_config)?;
+    let count = glyph_defs.len();
+    save_document(output, glyph_defs.add_to(Document::new()), render_config)?;
+    Ok(count)
+}
+
+/// Save svg, and the html preview with the same file stem when requested
+fn save_document(output: PathBuf, doc: Document, render_config: &RenderConfig) -> Result<()> {
+    timing::time(Phase::Serialization, || serialize_document(output, doc, render_config))
+}
+
+fn serialize_document(output: PathBuf, doc: Document, render_config: &RenderConfig) -> Result<()> {
+    let doc = if render_config.get_href().uses_xlink() { doc.set("xmlns:xlink", XLINK_NS) } else { doc };
+    let doc = if render_config.get_layers().is_some() { doc.set("xmlns:inkscape", INKSCAPE_NS) } else { doc };
+    let doc = if render_config.get_trim() { trim_document(doc, render_config) } else { doc };
+    let doc = &add_description(doc, render_config);
+    if render_config.get_html() {
+        let html_output = output.with_extension("html");
+        let title = output.file_name().map(|name| name.to_string_lossy().to_string()).unwrap_or_default();
+        if let Err(e) = std::fs::write(&html_output, html_preview(&title, &doc.to_string())) {
+            log::error!("Failed to write {}: {}", html_output.display(), e);
+        }
+    }
+    write_document(&output, doc)
+        .map_err(|err| anyhow!("{}: {}", output.display(), err))
+        .context(Failure::OutputWrite)
+}
+
+// Stream the markup to the file, svg::save builds it as a whole string first which doubles peak memory for large documents.
+// A .svgz file gets the gzip compressed markup.
+fn write_document(output: &PathBuf, doc: &Document) -> std::io::Result<()> {
+    let writer = std::io::BufWriter::new(std::fs::File::create(output)?);
+    if output.extension().is_some_and(|extension| extension.eq_ignore_ascii_case("svgz")) {
+        let mut encoder = GzEncoder::new(writer, Compression::best());
+        write!(encoder, "{}", doc)?;
+        return encoder.finish()?.flush();
+    }
+    let mut writer = writer;
+    write!(writer, "{}", doc)?;
+    writer.flush()
+}
+
+// px per user unit the document is rasterized at to find its ink, and the largest side of the pixmap
+const TRIM_SCALE: f32 = 4.0;
+const TRIM_MAX_SIZE: f32 = 4096.0;
+
+/// Fit size and viewBox of the document to its painted pixels, whatever the estimated boxes of the glyphs.
+/// Padding is added around the ink, a document without ink is left as is.
+fn trim_document(doc: Document, render_config: &RenderConfig) -> Document {
+    match ink_bounds(&doc.to_string()) {
+        Some(ink) => {
+            let (x, y, width, height) = render_config.get_padding().apply(ink);
+            set_document_size(doc, width, height, render_config).set("viewBox", (x, y, width, height))
+        }
+        None => {
+            warning::warn(Warning::new("nothing_to_trim", "nothing painted to trim to"));
+            doc
+        }
+    }
+}
+
+/// Box x,y,width,height in user units of the non-transparent pixels of a rendered svg
+pub fn ink_bounds(svg: &str) -> Option<(f32, f32, f32, f32)> {
+    use resvg::usvg::TreeParsing;
+    let tree = resvg::usvg::Tree::from_str(svg, &resvg::usvg::Options::default()).ok()?;
+    let view_box = tree.view_box.rect;
+    let scale = TRIM_SCALE.min(TRIM_MAX_SIZE / view_box.width().max(view_box.height()));
+    let width = (view_box.width() * scale).ceil() as u32;
+    let height = (view_box.height() * scale).ceil() as u32;
+    let mut pixmap = resvg::tiny_skia::Pixmap::new(width, height)?;
+    let transform = resvg::tiny_skia::Transform::from_scale(
+        width as f32 / tree.size.width(),
+        height as f32 / tree.size.height(),
+    );
+    resvg::Tree::from_usvg(&tree).render(transform, &mut pixmap.as_mut());
+
+    let mut ink: Option<(u32, u32, u32, u32)> = None;
+    for (index, pixel) in pixmap.pixels().iter().enumerate() {
+        if pixel.alpha() == 0 {
+            continue;
+        }
+        let (col, row) = (index as u32 % width, index as u32 / width);
+        ink = Some(match ink {
+            Some((x_min, y_min, x_max, y_max)) => (x_min.min(col), y_min.min(row), x_max.max(col), y_max.max(row)),
+            None => (col, row, col, row),
+        });
+    }
+    let (x_min, y_min, x_max, y_max) = ink?;
+    // user units per pixel
+    let (unit_x, unit_y) = (view_box.width() / width as f32, view_box.height() / height as f32);
+    Some((
+        view_box.x() + x_min as f32 * unit_x,
+        view_box.y() + y_min as f32 * unit_y,
+        (x_max - x_min + 1) as f32 * unit_x,
+        (y_max - y_min + 1) as f32 * unit_y,
+    ))
+}
+
+/// Put title and description first in the document, where assistive technology looks for them
+fn add_description(mut doc: Document, render_config: &RenderConfig) -> Document {
+    let mut nodes: Vec<Box<dyn svg::Node>> = vec![];
+    if let Some(title) = render_config.get_title() {
+        nodes.push(Box::new(Title::new().add(svg::node::Text::new(title))));
+    }
+    if let Some(desc) = render_config.get_desc() {
+        nodes.push(Box::new(Description::new().add(svg::node::Text::new(desc))));
+    }
+    let children = doc.get_children_mut();
+    for (index, node) in nodes.into_iter().enumerate() {
+        children.insert(index, node);
+    }
+    doc
+}
+
+/// Html page embedding the svg inline on a checkerboard showing transparency,
+/// with a toggle between the natural size and fitting the window.
+/// Inline svg is rendered by the browser as is, so `<use>` references and animation can be checked.
+pub fn html_preview(title: &str, svg: &str) -> String {
+    let title = title.replace('&', "&amp;").replace('<', "&lt;");
+    format!(r#"<!DOCTYPE html>
+<html>
+<head>
+<meta charset="utf-8">
+<title>{title}</title>
+<style>
+  body {{ margin: 0; font-family: sans-serif; }}
+  label {{ display: block; padding: 8px; }}
+  .canvas {{
+    display: inline-block;
+    margin: 8px;
+    background: repeating-conic-gradient(#ccc 0% 25%, #fff 0% 50%) 0 0 / 16px 16px;
+  }}
+  .canvas svg {{ display: block; }}
+  .fit .canvas svg {{ width: calc(100vw - 16px); height: auto; }}
+</style>
+</head>
+<body>
+<label><input type="checkbox" onchange="document.body.classList.toggle('fit', this.checked)"> fit to window</label>
+<div class="canvas">
+{svg}
+</div>
+</body>
+</html>
+"#)
+}
+
+/// Set root size in px, or in mm when a physical size is requested
+fn set_document_size(doc: Document, width: f32, height: f32, render_config: &RenderConfig) -> Document {
+    match render_config.get_physical_size(width, height) {
+        Some((width_mm, height_mm)) => doc
+            .set("width", format!("{}mm", width_mm))
+            .set("height", format!("{}mm", height_mm)),
+        None => doc.set("height", height).set("width", width),
+    }
+}
+
+/// Region of the render as x,y,width,height in px
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct Crop {
+    pub x: f32,
+    pub y: f32,
+    pub width: f32,
+    pub height: f32,
+}
+
+impl FromStr for Crop {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let values = s
+            .split(',')
+            .map(|value| value.trim().parse::<f32>())
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|e| format!("invalid crop {:?}: {}", s, e))?;
+        match values[..] {
+            [x, y, width, height] if width > 0.0 && height > 0.0 => Ok(Crop { x, y, width, height }),
+            [_, _, _, _] => Err(format!("crop width and height must be positive, got {:?}", s)),
+            _ => Err(format!("expect x,y,width,height, got {:?}", s)),
+        }
+    }
+}
+
+/// Space in px added to each side of the content box
+#[derive(Debug, PartialEq, Clone, Copy, Default)]
+pub struct Padding {
+    pub top: f32,
+    pub right: f32,
+    pub bottom: f32,
+    pub left: f32,
+}
+
+impl Padding {
+    pub fn uniform(padding: f32) -> Self {
+        Padding { top: padding, right: padding, bottom: padding, left: padding }
+    }
+
+    /// Content box x,y,width,height grown by the padding
+    pub fn apply(&self, content: (f32, f32, f32, f32)) -> (f32, f32, f32, f32) {
+        let (x, y, width, height) = content;
+        (x - self.left, y - self.top, width + self.left + self.right, height + self.top + self.bottom)
+    }
+}
+
+/// Vertical alignment of content in a fixed output height
+#[derive(ValueEnum, Debug, PartialEq, Clone, Copy, Eq)]
+#[value(rename_all="lower")]
+pub enum VAlign {
+    Top,
+    Center,
+    Bottom,
+}
+
+/// How a line wider than --max-width-px is shown
+#[derive(ValueEnum, Debug, PartialEq, Clone, Copy, Eq)]
+#[value(rename_all="lower")]
+pub enum Overflow {
+    /// cut at the right edge of the box
+    Clip,
+    /// fade out towards the right edge of the box
+    Fade,
+    /// cut the text to fit with an ellipsis after it
+    Ellipsis,
+}
 
-    if font_config.get_debug() {
-        println!("file lines : {:?}", file_lines);
-    }
+/// Units of text wrapped in layers of their own, so that design tools can edit them apart
+#[derive(ValueEnum, Debug, PartialEq, Clone, Copy, Eq)]
+#[value(rename_all="lower")]
+pub enum Layers {
+    Line,
+    /// words in layers of their line
+    Word,
+    /// characters in layers of their line
+    Glyph,
+}
 
-    if let Ok(lines) = file_lines {
-        let mut group = Group::new().set("class", "text");
-        for line in lines.iter() {
-            if line.is_empty() {
-                height += font_config.get_size();
-            } else if let Some(path_line) =
-                render_text_to_path(0.0, height as f32, line, font_config, render_config)
-            {
-                width = width.max(path_line.width());
-                height += path_line.height();
-                group = group.add(path_line.path);
+impl Layers {
+    /// Byte ranges of the units of a line, words take the whitespace around them
+    pub fn units(&self, line: &str) -> Vec<Range<usize>> {
+        match self {
+            Layers::Line => std::iter::once(0..line.len()).collect(),
+            Layers::Word => {
+                let mut units = vec![];
+                let mut start = 0;
+                let mut after_space = false;
+                for (index, ch) in line.char_indices() {
+                    // leading whitespace goes with the first word
+                    if after_space && !ch.is_whitespace() && !line[start..index].trim().is_empty() {
+                        units.push(start..index);
+                        start = index;
+                    }
+                    after_space = ch.is_whitespace();
+                }
+                units.push(start..line.len());
+                units
             }
+            Layers::Glyph => line.char_indices().map(|(index, ch)| index..index + ch.len_utf8()).collect(),
         }
+    }
+}
 
-        let mut doc = Document::new()
-            .set("height", height)
-            .set("width", width)
-            .set("viewBox", format!("0 0 {} {}", width, height))
-            .add(group);
-        if render_config.get_animate() {
-            doc = doc.add(get_animation_style());
+/// How the height of a line is computed
+#[derive(ValueEnum, Debug, PartialEq, Clone, Copy, Eq)]
+#[value(rename_all="lower")]
+pub enum LineHeight {
+    /// the font size with the baseline at its bottom, descenders below it
+    Size,
+    /// ascent, descent and line gap of the font, with half the gap above and below like css
+    Font,
+}
+
+impl LineHeight {
+    /// Baseline below the top of a line and depth of the line below it in px, none for the size
+    pub fn metrics(&self, font_config: &FontConfig, style: &FontStyle) -> Option<(f32, f32)> {
+        if *self == LineHeight::Size {
+            return None;
         }
+        let metrics = font_config.get_font_by_style(style)?.metrics();
+        let scale = font_config.get_size() as f32 / glyph_height_units(&metrics);
+        let half_gap = metrics.line_gap.max(0.0) / 2.0;
+        Some(((metrics.ascent + half_gap) * scale, (-metrics.descent + half_gap) * scale))
+    }
+}
+
+/// Trade-off of renderers between speed, crisp edges and geometric precision
+#[derive(ValueEnum, Debug, PartialEq, Clone, Copy, Eq)]
+#[value(rename_all="lower")]
+pub enum ShapeRendering {
+    Auto,
+    /// pixel-aligned edges without anti-aliasing, e.g. small UI glyphs
+    Crisp,
+    /// smooth anti-aliased edges
+    Geometric,
+}
 
-        svg::save(output, &doc).unwrap();
+impl ShapeRendering {
+    /// Value of the svg shape-rendering attribute
+    pub fn attribute(&self) -> &'static str {
+        match self {
+            ShapeRendering::Auto => "auto",
+            ShapeRendering::Crisp => "crispEdges",
+            ShapeRendering::Geometric => "geometricPrecision",
+        }
     }
 }
 
-pub fn render_text_to_svg_file(text: &str, font_config: &mut FontConfig,render_config: &RenderConfig, output: PathBuf) {
-    // shape with harfbuzz algorithm
-    if let Some(text_path) = render_text_to_path(0.0, 0.0, text, font_config, render_config) {
-        let height = text_path.height();
-        let width = text_path.width();
-        let view_box = text_path.get_viewbox();
+/// Grid of repeated content with the space between tiles in px
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct Tile {
+    pub cols: u32,
+    pub rows: u32,
+    pub gap: f32,
+}
 
-        let group = Group::new().set("class", "text").add(text_path.path);
+impl FromStr for Tile {
+    type Err = String;
 
-        let mut doc = Document::new()
-            .set("height", height)
-            .set("width", width)
-            .set("viewBox", view_box)
-            .add(group);
-        if render_config.get_animate() {
-            doc = doc.add(get_animation_style());
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (cols, rows) = s.split_once(',').ok_or_else(|| format!("expect cols,rows, got {:?}", s))?;
+        match (cols.trim().parse::<u32>(), rows.trim().parse::<u32>()) {
+            (Ok(cols), Ok(rows)) if cols > 0 && rows > 0 => Ok(Tile { cols, rows, gap: 0.0 }),
+            _ => Err(format!("expect positive cols,rows, got {:?}", s)),
+        }
+    }
+}
+
+/// Move the content group into a pattern and return a group with a canvas of tiles filled by it.
+/// The viewBox of the pattern is the content box, so the content keeps its coordinates.
+fn tile_group(doc: Document, group: Group, content: (f32, f32, f32, f32), render_config: &RenderConfig) -> (Document, Group, (f32, f32, f32, f32)) {
+    let tile = match render_config.get_tile() {
+        Some(tile) => tile,
+        None => return (doc, group, content),
+    };
+    let (x, y, width, height) = content;
+    let tile_width = width + tile.gap;
+    let tile_height = height + tile.gap;
+    let id = format!("{}pattern", render_config.get_id_prefix());
+    let pattern = Pattern::new()
+        .set("id", id.as_str())
+        .set("patternUnits", "userSpaceOnUse")
+        .set("x", x)
+        .set("y", y)
+        .set("width", tile_width)
+        .set("height", tile_height)
+        .set("viewBox", (x, y, tile_width, tile_height))
+        .add(group);
+    // no gap after the last tile
+    let canvas = (x, y, tile_width * tile.cols as f32 - tile.gap, tile_height * tile.rows as f32 - tile.gap);
+    let rect = Rectangle::new()
+        .set("x", canvas.0)
+        .set("y", canvas.1)
+        .set("width", canvas.2)
+        .set("height", canvas.3)
+        .set("fill", format!("url(#{})", id));
+    (doc.add(Definitions::new().add(pattern)), Group::new().add(rect), canvas)
+}
+
+/// Box of the document: the padded content box fit to the output height, or the crop region when given
+fn document_box(content: (f32, f32, f32, f32), render_config: &RenderConfig) -> (f32, f32, f32, f32) {
+    match render_config.get_crop() {
+        Some(crop) => (crop.x, crop.y, crop.width, crop.height),
+        None => render_config.fit_height(render_config.get_padding().apply(content)),
+    }
+}
+
+/// Set size and viewBox of the document to the content box, or to the crop region when given
+fn set_view_box(doc: Document, content: (f32, f32, f32, f32), render_config: &RenderConfig) -> Document {
+    let (x, y, width, height) = document_box(content, render_config);
+    let doc = set_document_size(doc, width, height, render_config).set("viewBox", (x, y, width, height));
+    // auto is the initial value, no need to spell it out
+    match render_config.get_shape_rendering() {
+        ShapeRendering::Auto => doc,
+        shape_rendering => doc.set("shape-rendering", shape_rendering.attribute()),
+    }
+}
+
+/// Add the background color, image and grid covering the document box, before the content so that they are drawn behind
+fn add_background(doc: Document, content: (f32, f32, f32, f32), render_config: &RenderConfig) -> Document {
+    let (x, y, width, height) = document_box(content, render_config);
+    let mut doc = doc;
+    if let Some(color) = render_config.get_background() {
+        doc = doc.add(
+            Rectangle::new()
+                .set("x", x)
+                .set("y", y)
+                .set("width", width)
+                .set("height", height)
+                .set("fill", color),
+        );
+    }
+    if let Some(href) = render_config.get_background_image() {
+        doc = doc.add(
+            render_config
+                .get_href()
+                .set(Image::new(), href)
+                .set("x", x)
+                .set("y", y)
+                .set("width", width)
+                .set("height", height)
+                // fill the canvas keeping the aspect ratio, the image is cut instead of the canvas left bare
+                .set("preserveAspectRatio", "xMidYMid slice"),
+        );
+    }
+    match render_config.get_grid() {
+        Some(spacing) => doc.add(grid(spacing, (x, y, width, height), render_config)),
+        None => doc,
+    }
+}
+
+/// Light lines at multiples of spacing across the box, so that they line up with the origin of the text
+fn grid(spacing: f32, (x, y, width, height): (f32, f32, f32, f32), render_config: &RenderConfig) -> Group {
+    let multiples = |start: f32, length: f32| {
+        ((start / spacing).ceil() as i64..=((start + length) / spacing).floor() as i64).map(move |index| index as f32 * spacing)
+    };
+    let mut group = Group::new()
+        .set("class", format!("{}grid", render_config.get_id_prefix()))
+        .set("stroke", "#ddd")
+        .set("stroke-width", 0.5);
+    for at in multiples(x, width) {
+        group = group.add(Line::new().set("x1", at).set("y1", y).set("x2", at).set("y2", y + height));
+    }
+    for at in multiples(y, height) {
+        group = group.add(Line::new().set("x1", x).set("y1", at).set("x2", x + width).set("y2", at));
+    }
+    group
+}
+
+/// Reference to an image: urls and data uris as given, PNG and JPEG files embedded as data uri
+pub fn image_href(source: &str) -> Result<String> {
+    if ["data:", "http://", "https://"].iter().any(|scheme| source.starts_with(scheme)) {
+        return Ok(source.to_string());
+    }
+    let data = std::fs::read(source).map_err(|err| anyhow!("{}: {}", source, err))?;
+    let mime = if data.starts_with(b"\x89PNG\r\n\x1a\n") {
+        "image/png"
+    } else if data.starts_with(b"\xff\xd8\xff") {
+        "image/jpeg"
+    } else {
+        return Err(anyhow!("{}: unsupported image format, expect PNG or JPEG", source));
+    };
+    Ok(format!("data:{};base64,{}", mime, base64::engine::general_purpose::STANDARD.encode(data)))
+}
+
+/// Add content group, clipped to the crop region so nothing spills out when embedded with visible overflow
+fn add_cropped(doc: Document, group: Group, render_config: &RenderConfig) -> Document {
+    let crop = match render_config.get_crop() {
+        Some(crop) => crop,
+        None => return doc.add(group),
+    };
+    let id = format!("{}crop", render_config.get_id_prefix());
+    let clip_path = ClipPath::new().set("id", id.as_str()).add(
+        Rectangle::new()
+            .set("x", crop.x)
+            .set("y", crop.y)
+            .set("width", crop.width)
+            .set("height", crop.height),
+    );
+    doc.add(Definitions::new().add(clip_path))
+        .add(group.set("clip-path", format!("url(#{})", id)))
+}
+
+/// Smallest rectangle containing both rectangles
+fn union_rect(a: &Bounds, b: &Bounds) -> Bounds {
+    Bounds {
+        x_min: a.x_min.min(b.x_min),
+        y_min: a.y_min.min(b.y_min),
+        x_max: a.x_max.max(b.x_max),
+        y_max: a.y_max.max(b.y_max),
+    }
+}
+
+/// Directional runs of a line in visual order, the line is a single run when it has no right-to-left text
+pub fn bidi_runs(text: &str, direction: BaseDirection) -> Vec<(Range<usize>, Direction)> {
+    let level = match direction {
+        BaseDirection::Auto => None,
+        BaseDirection::Ltr => Some(Level::ltr()),
+        BaseDirection::Rtl => Some(Level::rtl()),
+    };
+    let bidi_info = BidiInfo::new(text, level);
+    if !bidi_info.has_rtl() {
+        return vec![(0..text.len(), Direction::LeftToRight)];
+    }
+    let mut runs = vec![];
+    for paragraph in bidi_info.paragraphs.iter() {
+        let (levels, visual_runs) = bidi_info.visual_runs(paragraph, paragraph.range.clone());
+        for run in visual_runs {
+            let direction = if levels[run.start].is_rtl() {
+                Direction::RightToLeft
+            } else {
+                Direction::LeftToRight
+            };
+            runs.push((run, direction));
+        }
+    }
+    runs
+}
+
+/// Glyphs of a font of the fallback chain shaping the text at offset, limited to the byte range of the text they render
+struct ShapedRun {
+    offset: usize,
+    glyphs: Rc<GlyphBuffer>,
+    face: usize,
+    range: Range<usize>,
+}
+
+impl ShapedRun {
+    fn glyph_run(&self) -> GlyphRun<'_> {
+        GlyphRun::new(self.offset, &self.glyphs).with_face(self.face).slice(&self.range)
+    }
+}
+
+/// Shape each directional run of text with its byte offset, runs are laid out left to right in the returned order.
+/// Returns the style of the face used as well.
+fn bidi_shape(text: &str, font_config: &mut FontConfig, font_style: &FontStyle) -> Option<(Vec<ShapedRun>, FontStyle)> {
+    let runs = bidi_runs(text, font_config.get_direction());
+    if let [(_, Direction::LeftToRight)] = runs[..] {
+        return fallback_shape(text, 0, 0, font_config, font_style, None);
+    }
+    let mut shaped_runs = vec![];
+    let mut used_style = font_style.clone();
+    for (range, direction) in runs {
+        let offset = range.start;
+        let (runs, style) = fallback_shape(&text[range], offset, 0, font_config, font_style, Some(direction))?;
+        shaped_runs.extend(runs);
+        used_style = style;
+    }
+    Some((shaped_runs, used_style))
+}
+
+/// Shape text at offset with font `face` of the fallback chain, clusters it has no glyph for with the next font.
+/// Runs are returned in visual order, with the style of the face used.
+fn fallback_shape(
+    text: &str,
+    offset: usize,
+    face: usize,
+    font_config: &mut FontConfig,
+    font_style: &FontStyle,
+    direction: Option<Direction>,
+) -> Option<(Vec<ShapedRun>, FontStyle)> {
+    let (glyphs, used_style) = shape(text, font_config.chain_mut(face), font_style, direction)?;
+    let whole = offset..offset + text.len();
+    if face + 1 == font_config.chain_len() || glyphs.glyph_infos().iter().all(|info| info.glyph_id != 0) {
+        return Some((vec![ShapedRun { offset, glyphs: Rc::new(glyphs), face, range: whole }], used_style));
+    }
+    // fallbacks shape in the direction and style of this font so their glyphs line up with its own,
+    // text without direction is a single left to right run
+    let direction = direction.unwrap_or(Direction::LeftToRight);
+    let glyphs = Rc::new(glyphs);
+    let mut parts = vec![];
+    for (range, missing) in missing_ranges(text, &glyphs) {
+        if missing {
+            let (runs, _) = fallback_shape(&text[range.clone()], offset + range.start, face + 1, font_config, &used_style, Some(direction))?;
+            parts.push(runs);
+        } else {
+            parts.push(vec![ShapedRun { offset, glyphs: glyphs.clone(), face, range: offset + range.start..offset + range.end }]);
         }
+    }
+    if direction == Direction::RightToLeft {
+        parts.reverse();
+    }
+    Some((parts.into_iter().flatten().collect(), used_style))
+}
 
-        svg::save(output, &doc).unwrap();
+/// Byte ranges of the text in logical order, with whether their clusters miss a glyph
+fn missing_ranges(text: &str, glyphs: &GlyphBuffer) -> Vec<(Range<usize>, bool)> {
+    let mut clusters: Vec<(usize, bool)> = glyphs
+        .glyph_infos()
+        .iter()
+        .map(|info| (info.cluster as usize, info.glyph_id == 0))
+        .collect();
+    clusters.sort_unstable();
+    // a cluster misses a glyph when any of its glyphs is missing
+    clusters.dedup_by(|next, prev| {
+        let same = next.0 == prev.0;
+        prev.1 |= same && next.1;
+        same
+    });
+    let mut ranges: Vec<(Range<usize>, bool)> = vec![];
+    for (i, &(start, missing)) in clusters.iter().enumerate() {
+        let end = clusters.get(i + 1).map_or(text.len(), |next| next.0);
+        match ranges.last_mut() {
+            Some((range, last_missing)) if *last_missing == missing => range.end = end,
+            _ => ranges.push((start..end, missing)),
+        }
     }
+    ranges
+}
+
+/// Characters of neighbouring glyphs whose ink overlaps by more than threshold px, with the overlap
+pub fn glyph_overlaps(text: &str, extents: &[GlyphExtent], threshold: f32) -> Vec<(char, char, f32)> {
+    let char_at = |offset: usize| text[offset..].chars().next().unwrap_or(char::REPLACEMENT_CHARACTER);
+    extents
+        .windows(2)
+        // glyphs of one cluster (e.g. base and mark) are meant to overlap
+        .filter(|pair| pair[0].cluster != pair[1].cluster)
+        .filter_map(|pair| {
+            let overlap = pair[0].x_max - pair[1].x_min;
+            (overlap > threshold).then(|| (char_at(pair[0].cluster), char_at(pair[1].cluster), overlap))
+        })
+        .collect()
 }
 
 /// Shape text with font default size (units_per_em)
-/// Therefore we need to scale these glyphs later according to the size
-fn text_shape(text: &str, font_config: &mut FontConfig, font_style: &FontStyle) -> Option<GlyphBuffer> {
+/// Therefore we need to scale these glyphs later according to the size.
+/// Returns the style of the face used, which differs from the requested one when the font lacks it.
+fn text_shape(text: &str, font_config: &mut FontConfig, font_style: &FontStyle) -> Option<(GlyphBuffer, FontStyle)> {
+    shape(text, font_config, font_style, None)
+}
+
+// Shape text in given direction, guessed from text when None
+fn shape(text: &str, font_config: &mut FontConfig, font_style: &FontStyle, direction: Option<Direction>) -> Option<(GlyphBuffer, FontStyle)> {
+    let font_style = &font_config.resolve_style(font_style).unwrap_or_else(|| font_style.clone());
     if let Some(ft_face) = font_config.get_font_by_style(font_style) {
         if let Some(font_data) = ft_face.copy_font_data() {
-            if let Some(hb_face) = Face::from_slice(&font_data, 0) {
+            if let Some(hb_face) = Face::from_slice(&font_data, font_config.get_face_index(font_style)) {
                 let mut buffer = rustybuzz::UnicodeBuffer::new();
                 buffer.push_str(text);
+                if let Some(direction) = direction {
+                    buffer.set_direction(direction);
+                }
+                if let Some(script) = font_config.get_script() {
+                    buffer.set_script(script);
+                }
+                if let Some(language) = font_config.get_language() {
+                    buffer.set_language(language.clone());
+                }
 
-                let glyph_buffer = rustybuzz::shape(&hb_face, font_config.get_features(), buffer);
+                let glyph_buffer = timing::time(Phase::Shaping, || rustybuzz::shape(&hb_face, font_config.get_features(), buffer));
 
-                if font_config.get_debug() {
+                if log::log_enabled!(log::Level::Trace) {
                     let format_flags = rustybuzz::SerializeFlags::default();
-                    println!("rustybuzz format_flags:\n {:?}", glyph_buffer.serialize(&hb_face, format_flags));
+                    log::trace!("shaped glyphs: {}", glyph_buffer.serialize(&hb_face, format_flags));
                 }
 
-                return Some(glyph_buffer);
+                return Some((glyph_buffer, font_style.clone()));
             } else {
-                eprintln!("Failed to load font data {:?}", font_config);
+                log::error!("Failed to load font data {:?}", font_config);
             }
         } else {
-            eprintln!("Failed to get font data {:?}", font_config);
+            log::error!("Failed to get font data {:?}", font_config);
         }
     } else {
-        eprintln!("Failed to get font style {:?}", font_config);
+        log::error!("Failed to get font style {:?}", font_config);
     }
 
-    eprintln!("Failed to shape with font config {:?}", font_config);
+    log::error!("Failed to shape with font config {:?}", font_config);
 
     None
 }
+
+#[cfg(test)]
+mod test_render {
+  use super::*;
+
+  #[test]
+  fn test_union_rect_negative_left_bearing() {
+      // first line starts with an italic glyph whose ink crosses the origin
+      let first = Bounds { x_min: -6, y_min: 0, x_max: 120, y_max: 64 };
+      let second = Bounds { x_min: 0, y_min: 64, x_max: 200, y_max: 128 };
+      let bbox = union_rect(&first, &second);
+      assert_eq!(bbox, Bounds { x_min: -6, y_min: 0, x_max: 200, y_max: 128 });
+      assert_eq!(bbox.width(), 206);
+  }
+
+  #[test]
+  fn test_parse_markup() {
+      assert_eq!(parse_markup("x^{2}"), vec![
+          TextRun::new("x", RunPosition::Baseline),
+          TextRun::new("2", RunPosition::Superscript),
+      ]);
+      assert_eq!(parse_markup("H_{2}O"), vec![
+          TextRun::new("H", RunPosition::Baseline),
+          TextRun::new("2", RunPosition::Subscript),
+          TextRun::new("O", RunPosition::Baseline),
+      ]);
+      assert_eq!(parse_markup("a^b_{c"), vec![TextRun::new("a^b_{c", RunPosition::Baseline)]);
+      assert_eq!(parse_markup("run @code{cargo build}"), vec![
+          TextRun::new("run ", RunPosition::Baseline),
+          TextRun::new("cargo build", RunPosition::Baseline).with_font("code"),
+      ]);
+      assert_eq!(parse_markup("a@b.c @{x}"), vec![TextRun::new("a@b.c @{x}", RunPosition::Baseline)]);
+  }
+
+  #[test]
+  fn test_line_symbol() {
+      let bbox = Bounds { x_min: -2, y_min: 64, x_max: 100, y_max: 140 };
+      let (symbol, line_use) = line_symbol("line-2", Path::new(), &bbox, HrefAttr::Href);
+      let symbol = symbol.to_string();
+      let line_use = line_use.to_string();
+      assert!(symbol.starts_with("<symbol"));
+      assert!(symbol.contains(r#"id="line-2""#) && symbol.contains(r#"viewBox="-2 64 102 76""#));
+      assert!(line_use.contains(r##"href="#line-2""##) && line_use.contains(r#"width="102""#));
+  }
+
+  #[test]
+  fn test_hidden_text() {
+      let text = hidden_text_with_font("a < b", 32.0, 80.0, "DejaVu Sans", 32).to_string();
+      assert!(text.starts_with("<text fill-opacity=\"0\" font-family=\"DejaVu Sans\" font-size=\"32\" lengthAdjust=\"spacingAndGlyphs\" textLength=\"80\" x=\"0\" y=\"32\">"));
+      assert!(text.contains("a &lt; b"));
+  }
+
+  #[test]
+  fn test_bidi_runs() {
+      let text = "say \u{645}\u{631}\u{62D}\u{628}\u{627} now";
+      let runs: Vec<_> = bidi_runs(text, BaseDirection::Auto)
+          .into_iter()
+          .map(|(range, direction)| (&text[range], direction))
+          .collect();
+      assert_eq!(runs, vec![
+          ("say ", Direction::LeftToRight),
+          ("\u{645}\u{631}\u{62D}\u{628}\u{627}", Direction::RightToLeft),
+          (" now", Direction::LeftToRight),
+      ]);
+
+      // right-to-left paragraph puts the first run on the right
+      let text = "\u{645}\u{631}\u{62D}\u{628}\u{627} abc";
+      let runs: Vec<_> = bidi_runs(text, BaseDirection::Auto)
+          .into_iter()
+          .map(|(range, direction)| (&text[range], direction))
+          .collect();
+      assert_eq!(runs, vec![
+          ("abc", Direction::LeftToRight),
+          ("\u{645}\u{631}\u{62D}\u{628}\u{627} ", Direction::RightToLeft),
+      ]);
+
+      assert_eq!(bidi_runs("plain", BaseDirection::Auto), vec![(0..5, Direction::LeftToRight)]);
+
+      // a leading RLM or --direction rtl makes a line starting with latin text right-to-left
+      let runs_of = |text: &'static str, direction| -> Vec<(&str, Direction)> {
+          bidi_runs(text, direction).into_iter().map(|(range, direction)| (&text[range], direction)).collect()
+      };
+      let text = "abc \u{5D0}\u{5D1} def";
+      assert_eq!(runs_of(text, BaseDirection::Auto), vec![
+          ("abc ", Direction::LeftToRight),
+          ("\u{5D0}\u{5D1}", Direction::RightToLeft),
+          (" def", Direction::LeftToRight),
+      ]);
+      let rtl = vec![
+          ("def", Direction::LeftToRight),
+          (" \u{5D0}\u{5D1} ", Direction::RightToLeft),
+          ("abc", Direction::LeftToRight),
+      ];
+      assert_eq!(runs_of(text, BaseDirection::Rtl), rtl);
+      let marked = "\u{200F}abc \u{5D0}\u{5D1} def";
+      assert_eq!(runs_of(marked, BaseDirection::Auto)[..3], rtl[..]);
+      // and --direction ltr keeps a line starting with hebrew left-to-right
+      let text = "\u{5D0}\u{5D1} abc";
+      assert_eq!(runs_of(text, BaseDirection::Ltr), vec![
+          ("\u{5D0}\u{5D1}", Direction::RightToLeft),
+          (" abc", Direction::LeftToRight),
+      ]);
+  }
+
+  #[test]
+  fn test_glyph_overlaps() {
+      let extent = |cluster, x_min, x_max| GlyphExtent { cluster, x_min, x_max };
+      let text = "AVé";
+      // e with combining accent shares a cluster
+      let extents = [extent(0, 0.0, 40.0), extent(1, 36.0, 76.0), extent(2, 70.0, 90.0), extent(2, 75.0, 85.0)];
+      assert_eq!(glyph_overlaps(text, &extents, 1.0), vec![('A', 'V', 4.0), ('V', 'é', 6.0)]);
+      assert_eq!(glyph_overlaps(text, &extents, 5.0), vec![('V', 'é', 6.0)]);
+  }
+
+  #[test]
+  fn test_column_positions() {
+      // name\tqty for "apple\t3", "fig\t12"
+      let widths = vec![vec![100.0, 20.0], vec![60.0, 40.0]];
+      let positions = column_positions(&widths, &[], 10.0);
+      assert_eq!(positions, vec![vec![0.0, 110.0], vec![0.0, 110.0]]);
+      let positions = column_positions(&widths, &[ColumnAlign::Right, ColumnAlign::Right], 10.0);
+      assert_eq!(positions, vec![vec![0.0, 130.0], vec![40.0, 110.0]]);
+      // ragged rows
+      let positions = column_positions(&[vec![50.0], vec![30.0, 10.0, 5.0]], &[ColumnAlign::Center], 0.0);
+      assert_eq!(positions, vec![vec![0.0], vec![10.0, 50.0, 60.0]]);
+  }
+
+  #[test]
+  fn test_add_description() {
+      let mut render_config = RenderConfig::new(false, FontStyle::Regular);
+      render_config.set_description(Some("Logo".to_string()), Some("Company name & slogan".to_string()));
+      let doc = Document::new().add(Group::new().set("class", "text"));
+      let doc = add_description(doc, &render_config).to_string();
+      let title = doc.find("<title>").unwrap();
+      let desc = doc.find("<desc>").unwrap();
+      assert!(title < desc && desc < doc.find("<g").unwrap());
+      assert!(doc.contains("Company name &amp; slogan"));
+  }
+
+  #[test]
+  fn test_tile() {
+      assert_eq!(Tile::from_str("3, 2"), Ok(Tile { cols: 3, rows: 2, gap: 0.0 }));
+      assert!(Tile::from_str("0,2").is_err());
+      assert!(Tile::from_str("3").is_err());
+
+      let mut render_config = RenderConfig::new(false, FontStyle::Regular);
+      render_config.set_tile(Some(Tile { cols: 3, rows: 2, gap: 10.0 }));
+      let (doc, group, view_box) = tile_group(Document::new(), Group::new().set("class", "text"), (0.0, -4.0, 100.0, 50.0), &render_config);
+      assert_eq!(view_box, (0.0, -4.0, 320.0, 110.0));
+      let doc = doc.add(group).to_string();
+      assert!(doc.contains(r#"<pattern height="60" id="pattern" patternUnits="userSpaceOnUse" viewBox="0 -4 110 60" width="110" x="0" y="-4">"#));
+      assert!(doc.contains(r#"<rect fill="url(#pattern)" height="110" width="320" x="0" y="-4"/>"#));
+  }
+
+  #[test]
+  fn test_crop() {
+      assert_eq!(Crop::from_str("-10, 5,100,50"), Ok(Crop { x: -10.0, y: 5.0, width: 100.0, height: 50.0 }));
+      assert!(Crop::from_str("0,0,0,50").is_err());
+      assert!(Crop::from_str("0,0,100").is_err());
+      assert!(Crop::from_str("0,0,a,50").is_err());
+
+      let mut render_config = RenderConfig::new(false, FontStyle::Regular);
+      render_config.set_crop(Some(Crop { x: 10.0, y: 20.0, width: 30.0, height: 40.0 }));
+      let doc = set_view_box(Document::new(), (0.0, 0.0, 200.0, 100.0), &render_config);
+      let doc = add_cropped(doc, Group::new(), &render_config).to_string();
+      assert!(doc.contains(r#"viewBox="10 20 30 40""#));
+      assert!(doc.contains(r#"width="30""#) && doc.contains(r#"height="40""#));
+      assert!(doc.contains(r#"<g clip-path="url(#crop)""#));
+  }
+
+  #[test]
+  fn test_shape_rendering() {
+      let mut render_config = RenderConfig::new(false, FontStyle::Regular);
+      let doc = set_view_box(Document::new(), (0.0, 0.0, 10.0, 10.0), &render_config).to_string();
+      assert!(!doc.contains("shape-rendering"));
+      render_config.set_shape_rendering(ShapeRendering::Crisp);
+      let doc = set_view_box(Document::new(), (0.0, 0.0, 10.0, 10.0), &render_config).to_string();
+      assert!(doc.contains(r#"shape-rendering="crispEdges""#));
+  }
+
+  #[test]
+  fn test_html_preview() {
+      let html = html_preview("a<b.svg", "<svg/>");
+      assert!(html.contains("<title>a&lt;b.svg</title>"));
+      assert!(html.contains("<div class=\"canvas\">\n<svg/>\n</div>"));
+  }
+
+  #[test]
+  fn test_fit_height() {
+      let mut render_config = RenderConfig::new(false, FontStyle::Regular);
+      let content = (0.0, 0.0, 120.0, 76.0);
+      assert_eq!(render_config.fit_height(content), content);
+      render_config.set_height_px(Some(200.0), VAlign::Top);
+      assert_eq!(render_config.fit_height(content), (0.0, 0.0, 120.0, 200.0));
+      render_config.set_height_px(Some(200.0), VAlign::Bottom);
+      assert_eq!(render_config.fit_height(content), (0.0, -124.0, 120.0, 200.0));
+      // texts of different sizes get the same height
+      assert_eq!(render_config.fit_height((0.0, -3.0, 300.0, 90.0)).3, 200.0);
+      render_config.set_height_px(Some(200.0), VAlign::Center);
+      let (_, y, _, height) = render_config.fit_height(content);
+      // equal margins above and below the content
+      let top = content.1 - y;
+      let bottom = (y + height) - (content.1 + content.3);
+      assert_eq!(top, 62.0);
+      assert_eq!(top, bottom);
+  }
+
+  #[test]
+  fn test_padding() {
+      let content = (0.0, -2.0, 100.0, 50.0);
+      assert_eq!(Padding::default().apply(content), content);
+      assert_eq!(Padding::uniform(4.0).apply(content), (-4.0, -6.0, 108.0, 58.0));
+      // each side moves only its own edge of the viewBox
+      let sides = [
+          (Padding { top: 10.0, ..Padding::uniform(4.0) }, (-4.0, -12.0, 108.0, 64.0)),
+          (Padding { right: 10.0, ..Padding::uniform(4.0) }, (-4.0, -6.0, 114.0, 58.0)),
+          (Padding { bottom: 10.0, ..Padding::uniform(4.0) }, (-4.0, -6.0, 108.0, 64.0)),
+          (Padding { left: 10.0, ..Padding::uniform(4.0) }, (-10.0, -6.0, 114.0, 58.0)),
+      ];
+      for (padding, view_box) in sides {
+          assert_eq!(padding.apply(content), view_box);
+      }
+
+      let mut render_config = RenderConfig::new(false, FontStyle::Regular);
+      render_config.set_padding(Padding { bottom: 10.0, ..Default::default() });
+      let doc = set_view_box(Document::new(), content, &render_config).to_string();
+      assert!(doc.contains(r#"viewBox="0 -2 100 60""#), "{}", doc);
+  }
+
+  #[test]
+  fn test_physical_size() {
+      let mut config = RenderConfig::new(false, FontStyle::Regular);
+      assert_eq!(config.get_physical_size(96.0, 48.0), None);
+      config.set_physical_size(Some(96.0), None, None);
+      assert_eq!(config.get_physical_size(96.0, 48.0), Some((25.4, 12.7)));
+      config.set_physical_size(None, Some(100.0), None);
+      assert_eq!(config.get_physical_size(200.0, 50.0), Some((100.0, 25.0)));
+      config.set_physical_size(None, None, Some(10.0));
+      assert_eq!(config.get_physical_size(200.0, 50.0), Some((40.0, 10.0)));
+  }
+
+  #[test]
+  fn test_animate_length() {
+      assert_eq!("auto".parse::<AnimateLength>(), Ok(AnimateLength::Auto));
+      assert_eq!("1000".parse::<AnimateLength>(), Ok(AnimateLength::Fixed(1000.0)));
+      assert!("0".parse::<AnimateLength>().is_err());
+      assert!(get_animation_style("", AnimateLength::Fixed(1000.0)).to_string().contains("stroke-dasharray: 1000 1000;"));
+      assert!(!get_animation_style("", AnimateLength::Auto).to_string().contains("stroke-dasharray"));
+  }
+
+  #[test]
+  fn test_animation_style_id_prefix() {
+      let first = get_animation_style("a-", AnimateLength::Fixed(450.0)).to_string();
+      let second = get_animation_style("b-", AnimateLength::Auto).to_string();
+      assert!(first.contains(".a-text") && first.contains("animation: a-draw"));
+      assert!(second.contains(".b-text") && second.contains("@keyframes b-draw"));
+      assert!(!first.contains("b-") && !second.contains("a-"));
+  }
+
+  #[test]
+  fn test_on_shape_failure() {
+      let line = "\u{E000}\u{E001}";
+      assert_eq!(OnShapeFailure::Skip.line_height(3, line, 64).unwrap(), 0);
+      assert_eq!(OnShapeFailure::SkipBlank.line_height(3, line, 64).unwrap(), 64);
+      assert_eq!(OnShapeFailure::Placeholder.line_height(3, line, 64).unwrap(), 64);
+      let error = OnShapeFailure::Error.line_height(3, line, 64).unwrap_err();
+      assert!(format!("{:#}", error).starts_with("shaping failed: line 3: failed to shape"));
+      assert_eq!(Failure::of(&error), Some(Failure::Shaping));
+
+      let (bar, bbox) = placeholder_bar(line, 128.0, 64);
+      assert_eq!(bbox, Bounds { x_min: 0, y_min: 128, x_max: 64, y_max: 192 });
+      assert!(bar.to_string().contains(r#"width="64""#));
+  }
+
+  #[test]
+  fn test_render_stats() {
+      use crate::svg::{GlyphDef, GlyphExtent, GlyphOutline};
+      use rustybuzz::ttf_parser::Rect;
+      let glyph_def = GlyphDef::new("", 3, &GlyphOutline::default(), &Rect { x_min: 0, y_min: 0, x_max: 10, y_max: 10 }, 1.0, DefMode::Path);
+      let extent = GlyphExtent { cluster: 0, x_min: 0.0, x_max: 1.0 };
+      let mut glyph_defs = GlyphDefinitions::default();
+      let mut stats = RenderStats::default();
+      // two lines of "aaa"
+      for _ in 0..2 {
+          let mut text = Text::new(Path::new(), Bounds { x_min: 0, y_min: 0, x_max: 3, y_max: 1 });
+          text.glyph_extents = vec![extent; 3];
+          text.outlined_glyphs = 1;
+          text.glyph_defs = vec![glyph_def.clone()];
+          stats.lines += 1;
+          stats.add(&text);
+          glyph_defs.add(&mut text);
+      }
+      stats.report(&glyph_defs, &RenderConfig::new(false, FontStyle::Regular));
+      assert_eq!(stats, RenderStats { lines: 2, glyphs: 6, outlined: 2, glyph_defs: 1 });
+      assert!(stats.glyph_defs < stats.glyphs);
+      assert_eq!(stats.to_string(), "lines: 2, glyphs: 6, outlined: 2 (67% cache hits), glyph definitions: 1");
+  }
+}
+
+// Layout of a bundled test font against expected metrics, see tests/fonts/README.md
+#[cfg(test)]
+mod test_metrics {
+  use super::*;
+  use crate::font::LetterSpaceUnit;
+
+  const TEST_FONT: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fonts/Text2svgTest-Regular.ttf");
+
+  fn test_font(size: u32, letter_space: f32) -> FontConfig {
+      let mut font_config = FontConfig::from_path(PathBuf::from(TEST_FONT), 0, size, "none".to_string(), "#000".to_string()).unwrap();
+      font_config.set_letter_space(letter_space);
+      font_config.set_letter_space_unit(LetterSpaceUnit::Em);
+      font_config
+  }
+
+  // bounding box and ink extents (x_min, x_max) of each glyph
+  fn layout(text: &str, font_config: &mut FontConfig) -> (Bounds, Vec<(f32, f32)>) {
+      let render_config = RenderConfig::new(false, FontStyle::Regular);
+      let text = render_text_to_path(0.0, 0.0, text, font_config, &render_config).unwrap();
+      let extents = text.glyph_extents.iter().map(|extent| (extent.x_min, extent.x_max)).collect();
+      (text.bounding_box, extents)
+  }
+
+  fn rect(x_min: i32, y_min: i32, x_max: i32, y_max: i32) -> Bounds {
+      Bounds { x_min, y_min, x_max, y_max }
+  }
+
+  fn assert_extents(actual: &[(f32, f32)], expected: &[(f32, f32)]) {
+      assert_eq!(actual.len(), expected.len(), "{:?}", actual);
+      for (actual, expected) in actual.iter().zip(expected) {
+          assert!((actual.0 - expected.0).abs() < 1e-3 && (actual.1 - expected.1).abs() < 1e-3, "{:?} != {:?}", actual, expected);
+      }
+  }
+
+  #[test]
+  fn test_metrics_advance() {
+      // 64px over 1000 units from descender to ascender
+      let (bbox, extents) = layout("A A", &mut test_font(64, 0.0));
+      assert_eq!(bbox, rect(0, 0, 93, 64));
+      assert_extents(&extents, &[(0.0, 38.4), (54.4, 92.8)]);
+
+      let (bbox, extents) = layout("A", &mut test_font(32, 0.0));
+      assert_eq!(bbox, rect(0, 0, 20, 32));
+      assert_extents(&extents, &[(0.0, 19.2)]);
+  }
+
+  #[test]
+  fn test_metrics_feature_range() {
+      // A and V kern by 6.4px, turning kerning off for the first two characters keeps the last pair kerned
+      let mut font_config = test_font(64, 0.0);
+      font_config.add_feature("-kern[0:2]").unwrap();
+      assert!(font_config.add_feature("bogus[[").is_err());
+      let (_, extents) = layout("AVAV", &mut font_config);
+      assert_extents(&extents, &[(0.0, 38.4), (38.4, 76.8), (76.8, 115.2), (108.8, 147.2)]);
+  }
+
+  #[test]
+  fn test_metrics_circle() {
+      // A and o advance 38.4px and 32px, the middles of their advances are spaced along the arc
+      let mut font_config = test_font(64, 0.0);
+      let mut render_config = RenderConfig::new(false, FontStyle::Regular);
+      render_config.set_def_mode(DefMode::Path).set_circle(Some(100.0));
+      let text = render_text_to_path(0.0, 0.0, "AoAoA", &mut font_config, &render_config).unwrap();
+      // circle and ascent fit the box, the center is at 164,164
+      assert_eq!(text.bounding_box, rect(0, 0, 328, 328));
+      let glyphs = text.glyphs.unwrap().to_string();
+      let rotations: Vec<Vec<f32>> = glyphs
+          .match_indices("rotate(")
+          .map(|(index, _)| {
+              let values = &glyphs[index + "rotate(".len()..];
+              values[..values.find(')').unwrap()].split(' ').map(|value| value.parse().unwrap()).collect()
+          })
+          .collect();
+      let angles: Vec<f32> = rotations.iter().map(|rotation| rotation[0].to_radians()).collect();
+      let expected = [0.192, 0.544, 0.896, 1.248, 1.6];
+      assert!(angles.iter().zip(expected).all(|(angle, expected)| (angle - expected).abs() < 1e-4), "{:?}", angles);
+      // the origin is half the advance back along the tangent from the circle
+      for (rotation, advance) in rotations.iter().zip([38.4f32, 32.0, 38.4, 32.0, 38.4]) {
+          let distance = ((rotation[1] - 164.0).powi(2) + (rotation[2] - 164.0).powi(2)).sqrt();
+          assert!((distance - (100f32.powi(2) + (advance / 2.0).powi(2)).sqrt()).abs() < 1e-3, "{:?}", rotation);
+      }
+  }
+
+  #[test]
+  fn test_metrics_cff() {
+      // cubic curves of CFF outlines are scaled and flipped like their end points
+      let font = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fonts/Text2svgTestCff-Regular.otf");
+      let mut font_config = FontConfig::from_path(PathBuf::from(font), 0, 64, "none".to_string(), "#000".to_string()).unwrap();
+      let render_config = RenderConfig::new(false, FontStyle::Regular);
+      let text = render_text_to_path(0.0, 0.0, "o", &mut font_config, &render_config).unwrap();
+      let d = text.path.get_attributes()["d"].to_string();
+      let golden = "M 28.8 48C 28.8 40.96 23.04 35.2 16 35.2C 8.96 35.2 3.2 40.96 3.2 48C 3.2 55.04 8.96 60.8 16 60.8C 23.04 60.8 28.8 55.04 28.8 48Z";
+      let numbers = |path: &str| -> Vec<f32> {
+          path.split(|ch: char| ch.is_ascii_alphabetic() || ch == ' ').filter_map(|value| value.parse().ok()).collect()
+      };
+      let commands = |path: &str| -> String { path.chars().filter(char::is_ascii_alphabetic).collect() };
+      assert_eq!(commands(&d), commands(golden), "{}", d);
+      assert_extents(&numbers(&d).chunks(2).map(|point| (point[0], point[1])).collect::<Vec<_>>(),
+          &numbers(golden).chunks(2).map(|point| (point[0], point[1])).collect::<Vec<_>>());
+      assert_eq!(text.bounding_box, rect(0, 0, 32, 64));
+  }
+
+  #[test]
+  fn test_metrics_kerning() {
+      // A V kern by -100 units
+      let (bbox, extents) = layout("AV", &mut test_font(64, 0.0));
+      assert_eq!(bbox, rect(0, 0, 71, 64));
+      assert_extents(&extents, &[(0.0, 38.4), (32.0, 70.4)]);
+  }
+
+  #[test]
+  fn test_metrics_fractional_ink() {
+      // f ends its ink at 9.1 past its advance of 6.5, the box rounds up to keep the last pixel
+      let (bbox, extents) = layout("f", &mut test_font(13, 0.0));
+      assert_extents(&extents, &[(1.3, 9.1)]);
+      assert_eq!(bbox, rect(0, 0, 10, 13));
+      let output = std::env::temp_dir().join("text2svg-test-fractional.svg");
+      let render_config = RenderConfig::new(false, FontStyle::Regular);
+      render_text_lines_to_svg(&["f".to_string()], &mut test_font(13, 0.0), &render_config, output.clone()).unwrap();
+      let svg = std::fs::read_to_string(&output).unwrap();
+      std::fs::remove_file(&output).unwrap();
+      assert!(svg.contains(r#"viewBox="0 0 10 13""#), "{}", svg);
+  }
+
+  #[test]
+  fn test_metrics_missing_box() {
+      // the font has none of x y z, .notdef advances by 500 units and draws 50..450
+      let mut render_config = RenderConfig::new(false, FontStyle::Regular);
+      render_config.set_missing(MissingGlyph::Box);
+      let text = render_text_to_path(0.0, 0.0, "xyz", &mut test_font(64, 0.0), &render_config).unwrap();
+      let extents: Vec<(f32, f32)> = text.glyph_extents.iter().map(|extent| (extent.x_min, extent.x_max)).collect();
+      assert_extents(&extents, &[(0.0, 32.0), (32.0, 64.0), (64.0, 96.0)]);
+      // from the ascent 51.2 above the baseline to the descent 12.8 below it
+      assert_eq!(text.bounding_box, rect(0, 0, 96, 77));
+      let d = text.path.get_attributes()["d"].to_string();
+      assert_eq!(d.matches('Z').count(), 3, "{}", d);
+      assert!(d.contains("L 64 76.8Z M 64 76.8L 64 "), "{}", d);
+      let (_, extents) = layout("xyz", &mut test_font(64, 0.0));
+      assert_extents(&extents, &[(3.2, 28.8), (35.2, 60.8), (67.2, 92.8)]);
+  }
+
+  #[test]
+  fn test_export_glyphs() {
+      let dir = std::env::temp_dir().join("text2svg-test-glyphs");
+      let _ = std::fs::remove_dir_all(&dir);
+      let render_config = RenderConfig::new(false, FontStyle::Regular);
+      // B is missing and space has no outline
+      let count = export_glyphs(Some("AVB A"), &mut test_font(64, 0.0), &render_config, &dir).unwrap();
+      assert_eq!(count, 2);
+      let mut files: Vec<String> = std::fs::read_dir(&dir).unwrap().map(|entry| entry.unwrap().file_name().to_string_lossy().to_string()).collect();
+      files.sort();
+      assert_eq!(files, vec!["u0041-g2.svg", "u0056-g3.svg"]);
+      let svg = std::fs::read_to_string(dir.join("u0041-g2.svg")).unwrap();
+      std::fs::remove_dir_all(&dir).unwrap();
+      // 600 x 700 units at 0.064 px per unit
+      assert!(svg.contains(r#"viewBox="0 0 38.4 44.8"#), "{}", svg);
+      assert!(svg.contains(r#"<path d="M 0 44.8"#) && svg.contains("L 19.2 0L 38.4 44.8"), "{}", svg);
+  }
+
+  #[test]
+  fn test_metrics_override() {
+      let mut render_config = RenderConfig::new(false, FontStyle::Regular);
+      render_config.set_metrics(Some(80.0), None);
+      let text = render_text_to_path(0.0, 0.0, "Ap", &mut test_font(64, 0.0), &render_config).unwrap();
+      // the baseline moves from the size 64 to 80, p still descends 12.8 below it
+      assert_eq!(text.bounding_box, rect(0, 0, 71, 93));
+      let d = text.path.get_attributes()["d"].to_string();
+      assert!(d.starts_with("M 0 80L 19.2 35.") && d.contains(" 92.8Z"), "{}", d);
+      render_config.set_metrics(Some(40.0), Some(8.0));
+      let text = render_text_to_path(0.0, 0.0, "p", &mut test_font(64, 0.0), &render_config).unwrap();
+      assert_eq!(text.bounding_box, rect(0, 0, 32, 48));
+      assert_eq!(render_config.line_height(64), 48);
+  }
+
+  #[test]
+  fn test_line_height_font() {
+      // 800 ascent, 200 descent and 200 line gap at 0.064px per unit
+      let font_config = test_font(64, 0.0);
+      assert_eq!(LineHeight::Size.metrics(&font_config, &FontStyle::Regular), None);
+      let (ascent, descent) = LineHeight::Font.metrics(&font_config, &FontStyle::Regular).unwrap();
+      assert!((ascent - 57.6).abs() < 1e-3 && (descent - 19.2).abs() < 1e-3, "{} {}", ascent, descent);
+
+      let file = std::env::temp_dir().join("text2svg-test-line-height.txt");
+      std::fs::write(&file, "A\nA\nA").unwrap();
+      let output = std::env::temp_dir().join("text2svg-test-line-height.svg");
+      let mut render_config = RenderConfig::new(false, FontStyle::Regular);
+      render_config.set_metrics(Some(ascent), Some(descent));
+      render_text_file_to_svg(&file, &mut test_font(64, 0.0), &render_config, output.clone()).unwrap();
+      let svg = std::fs::read_to_string(&output).unwrap();
+      std::fs::remove_file(&file).unwrap();
+      std::fs::remove_file(&output).unwrap();
+      // lines are 76.8px rounded up apart instead of 64, the apex of A is 44.8px above the baseline
+      assert!(svg.starts_with(r#"<svg height="231""#), "{}", svg);
+      let apexes: Vec<f32> = svg
+          .match_indices("L 19.2 ")
+          .map(|(index, _)| svg[index + 7..].split('L').next().unwrap().parse().unwrap())
+          .collect();
+      assert_extents(&apexes.iter().map(|y| (*y, *y)).collect::<Vec<_>>(), &[(12.8, 12.8), (89.8, 89.8), (166.8, 166.8)]);
+  }
+
+  #[test]
+  fn test_reuse_font_config() {
+      let mut font_config = test_font(64, 0.0);
+      let render_config = RenderConfig::new(false, FontStyle::Regular);
+      let text = render_text_to_path(0.0, 0.0, "Ao", &mut font_config, &render_config).unwrap();
+      assert_eq!(text.bounding_box, rect(0, 0, 71, 64));
+      assert_eq!(text.path.get_attributes()["fill"].to_string(), "none");
+
+      font_config.set_size(32).set_fill_color("#ff0000").set_color("none");
+      let text = render_text_to_path(0.0, 0.0, "Ao", &mut font_config, &render_config).unwrap();
+      assert_eq!(text.bounding_box, rect(0, 0, 36, 32));
+      assert_eq!(text.path.get_attributes()["fill"].to_string(), "#ff0000");
+      assert_eq!(text.path.get_attributes()["stroke"].to_string(), "none");
+  }
+
+  #[test]
+  fn test_layers() {
+      assert_eq!(Layers::Word.units(" Ao  oA o"), vec![0..5, 5..8, 8..9]);
+      assert_eq!(Layers::Glyph.units("Ao\u{301}"), vec![0..1, 1..2, 2..4]);
+
+      let output = std::env::temp_dir().join("text2svg-test-layers.svg");
+      let mut render_config = RenderConfig::new(false, FontStyle::Regular);
+      let mut render = |layers| {
+          render_config.set_layers(Some(layers));
+          render_text_lines_to_svg(&["Ao Ao".to_string(), "A".to_string()], &mut test_font(64, 0.0), &render_config, output.clone()).unwrap();
+          std::fs::read_to_string(&output).unwrap()
+      };
+      let svg = render(Layers::Line);
+      assert!(svg.contains(r#"xmlns:inkscape="http://www.inkscape.org/namespaces/inkscape""#), "{}", svg);
+      let labels = |svg: &str| -> Vec<String> {
+          svg.split(r#"<g inkscape:groupmode="layer" inkscape:label=""#).skip(1).map(|rest| rest.split('"').next().unwrap().to_string()).collect()
+      };
+      assert_eq!(labels(&svg), vec!["Ao Ao", "A"]);
+      // words of a line are placed as in the whole line, the second A starts after A, o and a space
+      let svg = render(Layers::Word);
+      assert_eq!(labels(&svg), vec!["Ao Ao", "Ao", "Ao", "A", "A"]);
+      assert!(svg.contains(r#"<path d="M 86.4 64L"#), "{}", svg);
+      assert_eq!(labels(&render(Layers::Glyph)), vec!["Ao Ao", "A", "o", "A", "o", "A", "A"]);
+      std::fs::remove_file(&output).unwrap();
+  }
+
+  #[test]
+  fn test_metrics_zero_advance() {
+      // letter spacing goes around a base and its mark, not between them
+      let (_, extents) = layout("o\u{301}o", &mut test_font(64, 0.0));
+      let (_, spaced) = layout("o\u{301}o", &mut test_font(64, 0.1));
+      assert_eq!(extents.len(), 3);
+      assert!((spaced[1].0 - spaced[0].0 - (extents[1].0 - extents[0].0)).abs() < 1e-3, "{:?} {:?}", extents, spaced);
+      assert!((spaced[2].0 - extents[2].0 - 6.4).abs() < 1e-3, "{:?} {:?}", extents, spaced);
+      // an invisible joiner doesn't take away the spacing of the glyph after it
+      let (_, joined) = layout("o\u{200d}o", &mut test_font(64, 0.1));
+      let (_, plain) = layout("oo", &mut test_font(64, 0.1));
+      assert_extents(&joined, &plain);
+  }
+
+  #[test]
+  fn test_metrics_variation_selector() {
+      // the emoji heart is a rectangle 0..600, the text heart a diamond 100..500
+      let (_, emoji) = layout("\u{2764}\u{FE0F}o", &mut test_font(64, 0.1));
+      assert_extents(&emoji, &[(0.0, 38.4), (48.0, 73.6)]);
+      let (_, text) = layout("\u{2764}\u{FE0E}", &mut test_font(64, 0.1));
+      assert_extents(&text, &[(6.4, 32.0)]);
+      // a selector without a variation in the face is absorbed, neither a box nor spacing
+      let (bbox, absorbed) = layout("o\u{FE0F}o", &mut test_font(64, 0.1));
+      let (plain_bbox, plain) = layout("oo", &mut test_font(64, 0.1));
+      assert_extents(&absorbed, &plain);
+      assert_eq!(bbox, plain_bbox);
+  }
+
+  #[test]
+  fn test_no_glyph_cache() {
+      let output = std::env::temp_dir().join("text2svg-test-no-glyph-cache.svg");
+      let render = |glyph_cache: bool| {
+          let mut render_config = RenderConfig::new(false, FontStyle::Regular);
+          render_config.set_def_mode(DefMode::Path).set_glyph_cache(glyph_cache);
+          render_text_lines_to_svg(&["AoA".to_string(), "A".to_string()], &mut test_font(64, 0.0), &render_config, output.clone()).unwrap();
+          let svg = std::fs::read_to_string(&output).unwrap();
+          std::fs::remove_file(&output).unwrap();
+          svg
+      };
+      let ids = |svg: &str| -> Vec<String> {
+          svg.split(" id=\"").skip(1).map(|rest| rest.split('"').next().unwrap().to_string()).collect()
+      };
+      // A and o are defined once and used four times
+      let svg = render(true);
+      assert_eq!(ids(&svg).len(), 2, "{}", svg);
+      assert_eq!(svg.matches("<use ").count(), 4, "{}", svg);
+
+      // each occurrence has a definition of its own, numbered in the document
+      let svg = render(false);
+      let defs = ids(&svg);
+      assert_eq!(defs, ["g2-1", "g4-2", "g2-3", "g2-4"], "{}", svg);
+      for id in defs.iter() {
+          assert_eq!(svg.matches(&format!("href=\"#{}\"", id)).count(), 1, "{}", svg);
+      }
+
+      // blocks are built at the origin, the count goes on across blocks
+      let blocks = crate::blocks::parse_blocks(r#"[{"text": "A"}, {"text": "A", "x": 50}]"#).unwrap();
+      let mut render_config = RenderConfig::new(false, FontStyle::Regular);
+      render_config.set_def_mode(DefMode::Path).set_glyph_cache(false);
+      render_blocks_to_svg(&blocks, &mut test_font(64, 0.0), &mut HashMap::new(), &render_config, output.clone()).unwrap();
+      let svg = std::fs::read_to_string(&output).unwrap();
+      std::fs::remove_file(&output).unwrap();
+      assert_eq!(ids(&svg), ["block-1-g2-1", "block-2-g2-2"], "{}", svg);
+
+      // every occurrence is outlined
+      let text = render_text_to_path(0.0, 0.0, "AoA", &mut test_font(64, 0.0), &render_config).unwrap();
+      assert_eq!(text.outlined_glyphs, 3);
+      render_config.set_glyph_cache(true);
+      let text = render_text_to_path(0.0, 0.0, "AoA", &mut test_font(64, 0.0), &render_config).unwrap();
+      assert_eq!(text.outlined_glyphs, 2);
+  }
+
+  #[test]
+  fn test_glyph_defs() {
+      let output = std::env::temp_dir().join("text2svg-test-glyph-defs.svg");
+      let mut render_config = RenderConfig::new(false, FontStyle::Regular);
+      render_config.set_def_mode(DefMode::Path).set_id_prefix("lib-");
+      let lines = ["AoA".to_string(), "o A".to_string()];
+      assert_eq!(render_glyph_defs(&lines, &mut test_font(64, 0.0), &render_config, output.clone()).unwrap(), 2);
+      let svg = std::fs::read_to_string(&output).unwrap();
+      std::fs::remove_file(&output).unwrap();
+      // A and o defined once under the prefix, nothing placed
+      assert_eq!(svg.lines().collect::<Vec<_>>(), vec![
+          r#"<svg xmlns="http://www.w3.org/2000/svg">"#,
+          "<defs>",
+          r#"<path d="M 0 0L 19.2 -44.800003L 38.4 0L 0 0Z " id="lib-glyph-2-e328093d"/>"#,
+          r#"<path d="M 3.2 0L 3.2 -32L 28.800001 -32L 28.800001 0L 3.2 0Z " id="lib-glyph-4-e381438f"/>"#,
+          "</defs>",
+          "</svg>",
+      ]);
+      assert!(!svg.contains("<use"), "{}", svg);
+
+      // --max-glyph-defs applies as to a rendered document
+      render_config.set_max_glyph_defs(Some(1));
+      let err = render_glyph_defs(&lines, &mut test_font(64, 0.0), &render_config, output.clone()).unwrap_err();
+      assert!(err.is::<TooManyGlyphDefs>(), "{}", err);
+      assert!(!output.exists());
+  }
+
+  #[test]
+  fn test_xlink() {
+      let output = std::env::temp_dir().join("text2svg-test-xlink.svg");
+      let mut render_config = RenderConfig::new(false, FontStyle::Regular);
+      render_config.set_def_mode(DefMode::Path);
+      render_config.set_href(HrefAttr::Xlink);
+      render_text_lines_to_svg(&["oo".to_string()], &mut test_font(64, 0.0), &render_config, output.clone()).unwrap();
+      let svg = std::fs::read_to_string(&output).unwrap();
+      std::fs::remove_file(&output).unwrap();
+      assert!(svg.starts_with("<svg") && svg.lines().next().unwrap().contains(r#"xmlns:xlink="http://www.w3.org/1999/xlink""#), "{}", svg);
+      assert_eq!(svg.matches("<use ").count(), 2, "{}", svg);
+      assert_eq!(svg.matches(" xlink:href=\"#glyph-4-").count(), 2, "{}", svg);
+      assert!(!svg.contains(" href="), "{}", svg);
+
+      // both attributes point at the same definition
+      render_config.set_href(HrefAttr::Both);
+      render_text_lines_to_svg(&["oo".to_string()], &mut test_font(64, 0.0), &render_config, output.clone()).unwrap();
+      let svg = std::fs::read_to_string(&output).unwrap();
+      std::fs::remove_file(&output).unwrap();
+      assert!(svg.lines().next().unwrap().contains(r#"xmlns:xlink="http://www.w3.org/1999/xlink""#), "{}", svg);
+      let attr = |name: &str, element: &str| element.split(&format!(" {}=\"", name)).nth(1).and_then(|rest| rest.split('"').next()).map(String::from);
+      let uses: Vec<&str> = svg.lines().filter(|line| line.starts_with("<use ")).collect();
+      assert_eq!(uses.len(), 2, "{}", svg);
+      for element in uses {
+          assert!(attr("href", element).is_some_and(|href| href.starts_with("#glyph-4-")), "{}", element);
+          assert_eq!(attr("href", element), attr("xlink:href", element), "{}", element);
+      }
+  }
+
+  #[test]
+  fn test_coverage() {
+      let render_config = RenderConfig::new(false, FontStyle::Regular);
+      let mut font_config = test_font(64, 0.0);
+      let mut coverage = Coverage::default();
+      coverage.check("Ao 中文", &mut font_config, &render_config);
+      coverage.check("文p", &mut font_config, &render_config);
+      assert_eq!(coverage.missing, vec!['中', '文']);
+      assert_eq!(coverage.covered_percent(), 60.0);
+      assert_eq!(coverage.to_string(), "missing U+4E2D 中\nmissing U+6587 文\ncoverage: 3/5 characters (60.0%)");
+      assert_eq!(Coverage::default().to_string(), "coverage: 0/0 characters (100.0%)");
+  }
+
+  #[test]
+  fn test_metrics_guides() {
+      let mut render_config = RenderConfig::new(false, FontStyle::Regular);
+      render_config.set_guides(true);
+      let text = render_text_to_path(0.0, 0.0, "A", &mut test_font(64, 0.0), &render_config).unwrap();
+      let guides = text.guides.unwrap().to_string();
+      // the baseline is at the font size below the origin, across the text box
+      assert!(guides.contains(r##"<line class="guide-baseline" stroke="#f00" x1="0" x2="39" y1="64" y2="64"/>"##), "{}", guides);
+      // 800 units above and 200 units below the baseline
+      assert!(guides.contains(r#"class="guide-ascent""#) && guides.contains(r#"y1="12.799999""#), "{}", guides);
+      assert!(guides.contains(r#"class="guide-descent""#) && guides.contains(r#"y1="76.8""#), "{}", guides);
+      assert!(guides.contains("guide-x-height") && guides.contains("guide-cap-height"));
+
+      render_config.set_guides(false);
+      assert!(render_text_to_path(0.0, 0.0, "A", &mut test_font(64, 0.0), &render_config).unwrap().guides.is_none());
+  }
+
+  #[test]
+  fn test_metrics_descender() {
+      // p goes 200 units below the baseline
+      let (bbox, extents) = layout("op", &mut test_font(64, 0.0));
+      assert_eq!(bbox, rect(0, 0, 64, 77));
+      assert_extents(&extents, &[(3.2, 28.8), (35.2, 60.8)]);
+  }
+
+  #[test]
+  fn test_metrics_lines() {
+      let output = std::env::temp_dir().join("text2svg-test-lines.svg");
+      let render_config = RenderConfig::new(false, FontStyle::Regular);
+      let lines = vec!["o".to_string(), "o".to_string()];
+      render_text_lines_to_svg(&lines, &mut test_font(64, 0.0), &render_config, output.clone()).unwrap();
+      let svg = std::fs::read_to_string(&output).unwrap();
+      std::fs::remove_file(&output).unwrap();
+      // each line takes the font size, the second baseline is one line below the first
+      assert!(svg.contains(r#"viewBox="0 0 32 128""#), "{}", svg);
+      assert!(svg.contains(r#"<path d="M 3.2 64L 3.2 32L"#), "{}", svg);
+      assert!(svg.contains(r#"<path d="M 3.2 128L 3.2 96L"#), "{}", svg);
+  }
+
+  #[test]
+  fn test_metrics_negative_left_bearing() {
+      let output = std::env::temp_dir().join("text2svg-test-negative-left-bearing.svg");
+      let render_config = RenderConfig::new(false, FontStyle::Regular);
+      // the acute at the start of the first line has its ink at -300..-100 units left of the pen
+      let lines = vec!["\u{301}o".to_string(), "o".to_string()];
+      render_text_lines_to_svg(&lines, &mut test_font(64, 0.0), &render_config, output.clone()).unwrap();
+      let svg = std::fs::read_to_string(&output).unwrap();
+      std::fs::remove_file(&output).unwrap();
+      assert!(svg.contains(r#"viewBox="-20 0 52 128""#), "{}", svg);
+  }
+
+  #[test]
+  fn test_metrics_many_lines() {
+      let output = std::env::temp_dir().join("text2svg-test-many-lines.svg");
+      let render_config = RenderConfig::new(false, FontStyle::Regular);
+      // 640 lines of 64px are 40960px tall, past what font units hold
+      let lines = vec!["o".to_string(); 640];
+      render_text_lines_to_svg(&lines, &mut test_font(64, 0.0), &render_config, output.clone()).unwrap();
+      let svg = std::fs::read_to_string(&output).unwrap();
+      std::fs::remove_file(&output).unwrap();
+      assert!(svg.contains(r#"viewBox="0 0 32 40960""#), "{}", &svg[..200]);
+      assert!(svg.contains(r#"<path d="M 3.2 40960L 3.2 40928L"#));
+  }
+
+  #[test]
+  fn test_ansi_colors() {
+      let output = std::env::temp_dir().join("text2svg-test-ansi.svg");
+      let mut render_config = RenderConfig::new(false, FontStyle::Regular);
+      render_config.set_ansi(true);
+      let lines = vec!["A\x1b[31mo\x1b[0mA".to_string()];
+      render_text_lines_to_svg(&lines, &mut test_font(64, 0.0), &render_config, output.clone()).unwrap();
+      let svg = std::fs::read_to_string(&output).unwrap();
+      std::fs::remove_file(&output).unwrap();
+      // the escape sequences split the line into runs, only the one after 31 is red,
+      // each run starts at the advance of the one before
+      assert_eq!(svg.matches(r##"stroke="#000""##).count(), 2, "{}", svg);
+      assert!(svg.contains(r##"<path d="M 41.600002 64L 41.600002 32L 67.200005 32L 67.200005 64L 41.600002 64Z " fill="none" stroke="#cd0000""##), "{}", svg);
+      assert!(svg.contains(r#"<path d="M 70.4 64L 89.600006 19.199997L 108.8 64L 70.4 64Z ""#), "{}", svg);
+
+      // A V is kerned by -100 units across the escape sequence
+      let lines = vec!["A\x1b[31mV".to_string()];
+      render_text_lines_to_svg(&lines, &mut test_font(64, 0.0), &render_config, output.clone()).unwrap();
+      let svg = std::fs::read_to_string(&output).unwrap();
+      std::fs::remove_file(&output).unwrap();
+      assert!(svg.contains(r#"<path d="M 32 19.199997L"#), "{}", svg);
+  }
+
+  #[test]
+  fn test_trim() {
+      let output = std::env::temp_dir().join("text2svg-test-trim.svg");
+      let mut render_config = RenderConfig::new(false, FontStyle::Regular);
+      render_config.set_trim(true);
+      render_text_lines_to_svg(&["o".to_string()], &mut test_font(64, 0.0), &render_config, output.clone()).unwrap();
+      let svg = std::fs::read_to_string(&output).unwrap();
+      std::fs::remove_file(&output).unwrap();
+      let view_box = svg.split("viewBox=\"").nth(1).unwrap().split('"').next().unwrap();
+      let view_box: Vec<f32> = view_box.split(' ').map(|value| value.parse().unwrap()).collect();
+      // the line box is 0 0 32 64, the ink of o is 3.2 32 25.6 32 grown by half the stroke width
+      let expected = [2.7, 31.5, 26.6, 33.0];
+      for (actual, expected) in view_box.iter().zip(expected) {
+          assert!((actual - expected).abs() <= 0.5, "{:?} != {:?}", view_box, expected);
+      }
+  }
+
+  // pixel at x, y of the svg rendered at its size
+  fn pixel_at(svg: &str, x: u32, y: u32) -> resvg::tiny_skia::PremultipliedColorU8 {
+      use resvg::usvg::TreeParsing;
+      let tree = resvg::usvg::Tree::from_str(svg, &resvg::usvg::Options::default()).unwrap();
+      let mut pixmap = resvg::tiny_skia::Pixmap::new(tree.size.width() as u32, tree.size.height() as u32).unwrap();
+      resvg::Tree::from_usvg(&tree).render(resvg::tiny_skia::Transform::default(), &mut pixmap.as_mut());
+      pixmap.pixel(x, y).unwrap()
+  }
+
+  #[test]
+  fn test_crop_raster() {
+      let output = std::env::temp_dir().join("text2svg-test-crop-raster.svg");
+      let render = |crop: Option<Crop>| {
+          let mut render_config = RenderConfig::new(false, FontStyle::Regular);
+          render_config.set_crop(crop);
+          let mut font_config = FontConfig::from_path(PathBuf::from(TEST_FONT), 0, 64, "#000".to_string(), "none".to_string()).unwrap();
+          render_text_lines_to_svg(&["oo".to_string()], &mut font_config, &render_config, output.clone()).unwrap();
+          let svg = std::fs::read_to_string(&output).unwrap();
+          std::fs::remove_file(&output).unwrap();
+          svg
+      };
+      // o are rectangles 3.2..28.8 and 35.2..60.8 x 32..64, the crop keeps the first one
+      let uncropped = render(None);
+      assert!(uncropped.contains(r#"viewBox="0 0 64 64""#), "{}", uncropped);
+      assert_eq!(pixel_at(&uncropped, 48, 48).alpha(), 255);
+      let svg = render(Some(Crop { x: 0.0, y: 0.0, width: 32.0, height: 64.0 }));
+      assert!(svg.contains(r#"height="64" viewBox="0 0 32 64" width="32""#), "{}", svg);
+      assert_eq!(pixel_at(&svg, 16, 48).alpha(), 255);
+      // shown on the canvas of the whole line, the second o is still clipped away
+      let svg = svg.replacen(r#"viewBox="0 0 32 64" width="32""#, r#"viewBox="0 0 64 64" width="64""#, 1);
+      assert_eq!(pixel_at(&svg, 16, 48).alpha(), 255);
+      assert_eq!(pixel_at(&svg, 48, 48).alpha(), 0);
+  }
+
+  #[test]
+  fn test_shape_rendering_raster() {
+      let output = std::env::temp_dir().join("text2svg-test-shape-rendering-raster.svg");
+      let render = |shape_rendering: ShapeRendering| {
+          let mut render_config = RenderConfig::new(false, FontStyle::Regular);
+          render_config.set_shape_rendering(shape_rendering);
+          let mut font_config = FontConfig::from_path(PathBuf::from(TEST_FONT), 0, 64, "#000".to_string(), "none".to_string()).unwrap();
+          render_text_lines_to_svg(&["A".to_string()], &mut font_config, &render_config, output.clone()).unwrap();
+          let svg = std::fs::read_to_string(&output).unwrap();
+          std::fs::remove_file(&output).unwrap();
+          svg
+      };
+      // the slanted edges of A cross pixels 10 and 28 of row 40, anti-aliased they are partly covered
+      let (crisp, geometric) = (render(ShapeRendering::Crisp), render(ShapeRendering::Geometric));
+      for x in [10, 28] {
+          let (crisp, geometric) = (pixel_at(&crisp, x, 40).alpha(), pixel_at(&geometric, x, 40).alpha());
+          assert!(crisp == 0 || crisp == 255, "{}", crisp);
+          assert!(geometric > 0 && geometric < 255, "{}", geometric);
+      }
+      // inside the ink both are solid
+      assert_eq!(pixel_at(&crisp, 15, 30).alpha(), 255);
+      assert_eq!(pixel_at(&geometric, 15, 30).alpha(), 255);
+  }
+
+  #[test]
+  fn test_paint_inheritance() {
+      // glyph definitions carry no paint, they get it from the group through <use>
+      let output = std::env::temp_dir().join("text2svg-test-paint.svg");
+      for def_mode in [DefMode::Inline, DefMode::Path, DefMode::Symbol, DefMode::None] {
+          for style_attr in [false, true] {
+              for (fill, rgb) in [("#ff0000", (255, 0, 0)), ("currentColor", (0, 0, 255))] {
+                  let mut render_config = RenderConfig::new(false, FontStyle::Regular);
+                  render_config.set_def_mode(def_mode);
+                  render_config.set_style_attr(style_attr);
+                  let mut font_config = FontConfig::from_path(PathBuf::from(TEST_FONT), 0, 64, fill.to_string(), "none".to_string()).unwrap();
+                  render_text_lines_to_svg(&["o".to_string()], &mut font_config, &render_config, output.clone()).unwrap();
+                  // currentColor is the color of the page embedding the svg
+                  let svg = std::fs::read_to_string(&output).unwrap().replacen("<svg ", r##"<svg color="#0000ff" "##, 1);
+                  // o is a rectangle 3.2..28.8 x 32..64
+                  let pixel = pixel_at(&svg, 16, 48);
+                  assert_eq!((pixel.red(), pixel.green(), pixel.blue(), pixel.alpha()), (rgb.0, rgb.1, rgb.2, 255), "{:?} {} {}", def_mode, style_attr, svg);
+              }
+          }
+      }
+      std::fs::remove_file(&output).unwrap();
+  }
+
+  #[test]
+  fn test_fill_rule() {
+      let alpha_at = |svg: &str, x: u32, y: u32| pixel_at(svg, x, y).alpha();
+      // # is two rectangles 50..300 and 200..450 wound the same way, overlapping at 12.8..19.2px
+      let output = std::env::temp_dir().join("text2svg-test-fill-rule.svg");
+      let mut render_config = RenderConfig::new(false, FontStyle::Regular);
+      for (fill_rule, overlap_alpha) in [(None, 255), (Some(FillRule::NonZero), 255), (Some(FillRule::EvenOdd), 0)] {
+          render_config.set_fill_rule(fill_rule);
+          let mut font_config = FontConfig::from_path(PathBuf::from(TEST_FONT), 0, 64, "#000".to_string(), "none".to_string()).unwrap();
+          render_text_lines_to_svg(&["#".to_string()], &mut font_config, &render_config, output.clone()).unwrap();
+          let svg = std::fs::read_to_string(&output).unwrap();
+          assert_eq!(svg.contains("fill-rule"), fill_rule.is_some(), "{}", svg);
+          assert_eq!(alpha_at(&svg, 16, 35), overlap_alpha, "{:?}", fill_rule);
+          assert_eq!(alpha_at(&svg, 8, 35), 255, "{:?}", fill_rule);
+      }
+      std::fs::remove_file(&output).unwrap();
+  }
+
+  #[test]
+  fn test_svgz() {
+      use std::io::Read;
+      let render_config = RenderConfig::new(false, FontStyle::Regular);
+      let mut outputs = vec![];
+      for name in ["text2svg-test-svgz.svg", "text2svg-test-svgz.SVGZ"] {
+          let output = std::env::temp_dir().join(name);
+          render_text_lines_to_svg(&["Ao".to_string()], &mut test_font(64, 0.0), &render_config, output.clone()).unwrap();
+          outputs.push(std::fs::read(&output).unwrap());
+          std::fs::remove_file(&output).unwrap();
+      }
+      let mut svg = String::new();
+      flate2::read::GzDecoder::new(&outputs[1][..]).read_to_string(&mut svg).unwrap();
+      assert_eq!(svg.as_bytes(), outputs[0]);
+      assert!(outputs[1].len() < outputs[0].len());
+  }
+
+  #[test]
+  fn test_blocks() {
+      let output = std::env::temp_dir().join("text2svg-test-blocks.svg");
+      let blocks = crate::blocks::parse_blocks(r#"[
+          {"text": "A", "x": 10, "y": 20},
+          {"text": "o", "size": 32, "color": "red", "x": 100, "y": 50, "rotate": 90}
+      ]"#).unwrap();
+      let render_config = RenderConfig::new(false, FontStyle::Regular);
+      render_blocks_to_svg(&blocks, &mut test_font(64, 0.0), &mut HashMap::new(), &render_config, output.clone()).unwrap();
+      let svg = std::fs::read_to_string(&output).unwrap();
+      std::fs::remove_file(&output).unwrap();
+      // each block is placed at its corner, the canvas covers both, the rotated o spans x 68..100 and y 50..66
+      assert!(svg.contains(r#"viewBox="10 20 90 64""#), "{}", svg);
+      assert!(svg.contains(r#"<g transform="translate(10 20) rotate(0)">
+<path d="M 0 64L 19.2 19.199997L 38.4 64L"#), "{}", svg);
+      assert!(svg.contains(r#"<g transform="translate(100 50) rotate(90)">
+<path d="M 1.6 32L 1.6 16L"#), "{}", svg);
+      assert!(svg.contains(r##"stroke="#ff0000""##));
+  }
+
+  #[test]
+  fn test_highlight_diff() {
+      let file = std::env::temp_dir().join("text2svg-test-highlight.diff");
+      std::fs::write(&file, "--- a/o\n+++ b/o\n@@ -1 +1 @@\n oo\n-o\n+oo\n").unwrap();
+      let output = std::env::temp_dir().join("text2svg-test-highlight-diff.svg");
+      let setting = HighlightSetting { diff: Some(crate::highlight::DiffMarkers::Strip), ..Default::default() };
+      let render_config = RenderConfig::new(false, FontStyle::Regular);
+      render_file_highlight(&file, &mut test_font(64, 0.0), &setting, &render_config, output.clone()).unwrap();
+      let svg = std::fs::read_to_string(&output).unwrap();
+      std::fs::remove_file(&file).unwrap();
+      std::fs::remove_file(&output).unwrap();
+      // a band behind the removed and the added line, as wide as the widest line of the headers
+      let removed = svg.find(r#"<rect fill="rgba(248,81,73,0.3)" height="64" width="304" x="0" y="256"/>"#);
+      let added = svg.find(r#"<rect fill="rgba(46,160,67,0.3)" height="64" width="304" x="0" y="320"/>"#);
+      let text = svg.find("<g>");
+      assert!(removed.is_some() && added.is_some() && added < text, "{}", svg);
+      assert_eq!(svg.matches("<rect").count(), 3);
+  }
+
+  #[test]
+  fn test_highlight_shaped_line() {
+      // the ink of f overhangs its advance and kerning applies across the tokens of A::V
+      let line = "let f = A::Vof;";
+      let file = std::env::temp_dir().join("text2svg-test-shaped-line.rs");
+      std::fs::write(&file, line).unwrap();
+      let output = std::env::temp_dir().join("text2svg-test-shaped-line.svg");
+      let setting = HighlightSetting::default();
+      let render_config = RenderConfig::new(false, FontStyle::Regular);
+      let mut font_config = test_font(64, 0.1);
+      render_file_highlight(&file, &mut font_config, &setting, &render_config, output.clone()).unwrap();
+      let svg = std::fs::read_to_string(&output).unwrap();
+      std::fs::remove_file(&file).unwrap();
+      std::fs::remove_file(&output).unwrap();
+      assert!(svg.matches("<path").count() > 3, "{}", svg);
+      let plain = render_text_to_path(0.0, 0.0, line, &mut font_config, &render_config).unwrap();
+      assert!(svg.contains(&format!(r#"height="64" width="{}" x="0" y="0"/>"#, plain.bounding_box.x_max)), "{}", svg);
+  }
+
+  #[test]
+  fn test_highlight_first_line() {
+      let file = std::env::temp_dir().join("text2svg-test-first-line.txt");
+      std::fs::write(&file, "o\n\nA\n").unwrap();
+      let output = std::env::temp_dir().join("text2svg-test-first-line.svg");
+      let setting = HighlightSetting { first_line: Some(42), ..Default::default() };
+      let render_config = RenderConfig::new(false, FontStyle::Regular);
+      let mut font_config = test_font(64, 0.0);
+      render_file_highlight(&file, &mut font_config, &setting, &render_config, output.clone()).unwrap();
+      let svg = std::fs::read_to_string(&output).unwrap();
+      std::fs::remove_file(&file).unwrap();
+      std::fs::remove_file(&output).unwrap();
+      // blank lines are numbered too, the code starts after the gutter of "42 " in .notdef boxes and a space
+      let numbers: Vec<&str> = svg.split(r#"data-line=""#).skip(1).map(|rest| rest.split('"').next().unwrap()).collect();
+      assert_eq!(numbers, vec!["42", "43", "44"]);
+      assert!(svg.contains(r#"<path class="line-number" d="M 3.2 64L"#), "{}", svg);
+      assert!(svg.contains(r#"<path d="M 83.2 64L 83.2 32L"#), "{}", svg);
+      assert!(svg.contains(r#"<rect fill="rgba(43,48,59,255)" height="192" width="119" x="0" y="0"/>"#), "{}", svg);
+  }
+
+  #[test]
+  fn test_highlight_whitespace_line() {
+      let file = std::env::temp_dir().join("text2svg-test-whitespace-line.txt");
+      std::fs::write(&file, "o\n   \t\nA\n").unwrap();
+      let output = std::env::temp_dir().join("text2svg-test-whitespace-line.svg");
+      let setting = HighlightSetting::default();
+      let render_config = RenderConfig::new(false, FontStyle::Regular);
+      render_file_highlight(&file, &mut test_font(64, 0.0), &setting, &render_config, output.clone()).unwrap();
+      let svg = std::fs::read_to_string(&output).unwrap();
+      std::fs::remove_file(&file).unwrap();
+      std::fs::remove_file(&output).unwrap();
+      // a group for o and for A, the blank line between them still takes its height
+      assert_eq!(svg.matches("<g>").count(), 2, "{}", svg);
+      assert!(svg.contains(r#"<path d="M 0 192L 19.2 147.2L"#), "{}", svg);
+  }
+
+  #[test]
+  fn test_strip_trailing_lines() {
+      let height = |content: &str| {
+          let file = std::env::temp_dir().join("text2svg-test-trailing-lines.txt");
+          std::fs::write(&file, content).unwrap();
+          let output = std::env::temp_dir().join("text2svg-test-trailing-lines.svg");
+          let mut render_config = RenderConfig::new(false, FontStyle::Regular);
+          render_config.set_trim_trailing_lines(true);
+          render_text_file_to_svg(&file, &mut test_font(64, 0.0), &render_config, output.clone()).unwrap();
+          let svg = std::fs::read_to_string(&output).unwrap();
+          std::fs::remove_file(&file).unwrap();
+          std::fs::remove_file(&output).unwrap();
+          svg.split(r#"height=""#).nth(1).unwrap().split('"').next().unwrap().to_string()
+      };
+      // blank lines between lines with content stay
+      assert_eq!(height("o\n\nA\n\n\n  \n\n\n"), "192");
+      assert_eq!(height("o\n\nA"), "192");
+  }
+
+  #[test]
+  fn test_background_image() {
+      let image = std::env::temp_dir().join("text2svg-test-background.png");
+      std::fs::write(&image, b"\x89PNG\r\n\x1a\n").unwrap();
+      let href = image_href(image.to_str().unwrap()).unwrap();
+      std::fs::write(&image, b"GIF89a").unwrap();
+      assert!(image_href(image.to_str().unwrap()).unwrap_err().to_string().contains("expect PNG or JPEG"));
+      std::fs::remove_file(&image).unwrap();
+      assert_eq!(href, "data:image/png;base64,iVBORw0KGgo=");
+      assert_eq!(image_href("https://example.com/a.png").unwrap(), "https://example.com/a.png");
+
+      let output = std::env::temp_dir().join("text2svg-test-background.svg");
+      let mut render_config = RenderConfig::new(false, FontStyle::Regular);
+      render_config.set_background_image(Some(href));
+      render_config.set_padding(Padding::uniform(8.0));
+      render_text_lines_to_svg(&["o".to_string()], &mut test_font(64, 0.0), &render_config, output.clone()).unwrap();
+      let svg = std::fs::read_to_string(&output).unwrap();
+      std::fs::remove_file(&output).unwrap();
+      // the image covers the padded canvas, before the text so that it is drawn behind
+      let image = svg.find(r#"<image height="80" href="data:image/png;base64,iVBORw0KGgo=" preserveAspectRatio="xMidYMid slice" width="48" x="-8" y="-8"/>"#);
+      let text = svg.find(r#"<g class="text">"#);
+      assert!(image.is_some() && image < text, "{}", svg);
+  }
+
+  #[test]
+  fn test_language() {
+      use rustybuzz::Language;
+      let path = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fonts/Text2svgTestTurkish-Regular.ttf");
+      let mut font_config = FontConfig::from_path(PathBuf::from(path), 0, 64, "none".to_string(), "#000".to_string()).unwrap();
+      let render_config = RenderConfig::new(false, FontStyle::Regular);
+      let d = |font_config: &mut FontConfig| {
+          let text = render_text_to_path(0.0, 0.0, "\u{131}i", font_config, &render_config).unwrap();
+          text.path.get_attributes()["d"].to_string()
+      };
+      // the dotless i is the same, the dot of the dotted i is 100 units wide unless locl substitutes the Turkish one
+      let dotless = "M 12.8 64L 12.8 32L 19.2 32L 19.2 64L 12.8 64Z ";
+      let default = d(&mut font_config);
+      assert!(default.starts_with(dotless) && default.contains("M 44.8 25.599998L 44.8 19.199997L 51.2 19.199997L"), "{}", default);
+      font_config.set_language(Some(Language::from_str("tr").unwrap()));
+      let turkish = d(&mut font_config);
+      assert!(turkish.starts_with(dotless) && turkish.contains("M 38.4 25.599998L 38.4 19.199997L 57.6 19.199997L"), "{}", turkish);
+  }
+
+  #[test]
+  fn test_superscript() {
+      let output = std::env::temp_dir().join("text2svg-test-superscript.svg");
+      let render = |ascent: Option<f32>| {
+          let mut render_config = RenderConfig::new(false, FontStyle::Regular);
+          render_config.set_markup(true).set_metrics(ascent, None);
+          render_text_to_svg_file("A^{A}", &mut test_font(64, 0.0), &mut HashMap::new(), &render_config, output.clone()).unwrap();
+          let svg = std::fs::read_to_string(&output).unwrap();
+          std::fs::remove_file(&output).unwrap();
+          svg
+      };
+      // the superscript A is 0.7 times as large, its baseline 0.35 em above the baseline at 64
+      let svg = render(None);
+      assert!(svg.contains(r#"d="M 0 64L 19.2 19.199997L 38.4 64L 0 64Z "#), "{}", svg);
+      assert!(svg.contains(r#"d="M 38.4 41.6L 51.84 10.24L 65.28 41.6L 38.4 41.6Z "#), "{}", svg);
+      // with the ascent set, both baselines move up and the superscript box is scaled like its glyphs
+      let svg = render(Some(50.0));
+      assert!(svg.contains(r#"viewBox="0 -8 66 58""#), "{}", svg);
+      assert!(svg.contains(r#"d="M 0 50L 19.2 5.199997L 38.4 50L 0 50Z "#), "{}", svg);
+      assert!(svg.contains(r#"d="M 38.4 27.6L 51.84 -3.7599983L 65.28 27.6L 38.4 27.6Z "#), "{}", svg);
+  }
+
+  #[test]
+  fn test_alt_font_baseline() {
+      // the wide face of the collection, its glyphs twice as wide
+      let path = PathBuf::from(concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fonts/Text2svgTest.ttc"));
+      let mut alt_fonts = HashMap::new();
+      alt_fonts.insert("wide".to_string(), FontConfig::from_path(path, 1, 64, "none".to_string(), "#000".to_string()).unwrap());
+      let mut render_config = RenderConfig::new(false, FontStyle::Regular);
+      render_config.set_markup(true);
+      let output = std::env::temp_dir().join("text2svg-test-alt-font-baseline.svg");
+      render_text_to_svg_file("A@wide{A}A", &mut test_font(64, 0.0), &mut alt_fonts, &render_config, output.clone()).unwrap();
+      let svg = std::fs::read_to_string(&output).unwrap();
+      std::fs::remove_file(&output).unwrap();
+      // all three triangles stand on the baseline at 64, the wide one in the middle
+      assert!(svg.contains(r#"viewBox="0 0 154 64""#), "{}", svg);
+      assert!(svg.contains(r#"d="M 0 64L 19.2 19.199997L 38.4 64L 0 64Z "#), "{}", svg);
+      assert!(svg.contains(r#"d="M 38.4 64L 76.8 19.199997L 115.200005 64L 38.4 64Z "#), "{}", svg);
+      assert!(svg.contains(r#"d="M 115.200005 64L 134.40001 19.199997L 153.6 64L 115.200005 64Z "#), "{}", svg);
+  }
+
+  #[test]
+  fn test_alt_font_units_per_em() {
+      // the same glyphs at 2048 units per em instead of 1000
+      let path = PathBuf::from(concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fonts/Text2svgTest2048-Regular.ttf"));
+      let mut alt_fonts = HashMap::new();
+      alt_fonts.insert("big".to_string(), FontConfig::from_path(path, 0, 64, "none".to_string(), "#000".to_string()).unwrap());
+      let mut render_config = RenderConfig::new(false, FontStyle::Regular);
+      render_config.set_markup(true);
+      let output = std::env::temp_dir().join("text2svg-test-alt-font-units-per-em.svg");
+      render_text_to_svg_file("A@big{A}", &mut test_font(64, 0.0), &mut alt_fonts, &render_config, output.clone()).unwrap();
+      let svg = std::fs::read_to_string(&output).unwrap();
+      std::fs::remove_file(&output).unwrap();
+      // each run is scaled by its own face: both triangles are 38.4 wide and 44.8 high on one baseline,
+      // up to the rounding of coordinates to 2048 units, the second run starts at the advance of the first
+      assert!(svg.contains(r#"viewBox="0 0 77 64""#), "{}", svg);
+      assert!(svg.contains(r#"d="M 0 64L 19.2 19.199997L 38.4 64L 0 64Z "#), "{}", svg);
+      assert!(svg.contains(r#"d="M 38.4 64L 57.5875 19.1875L 76.80625 64L 38.4 64Z "#), "{}", svg);
+  }
+
+  #[test]
+  fn test_font_fallback() {
+      // x is missing from the test font, the fallback has it at 2048 units per em
+      let path = PathBuf::from(concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fonts/Text2svgTestFallback-Regular.ttf"));
+      let mut font_config = test_font(64, 0.0);
+      font_config.add_fallback(FontConfig::from_path(path, 0, 64, "none".to_string(), "#000".to_string()).unwrap());
+      let render_config = RenderConfig::new(false, FontStyle::Regular);
+      let output = std::env::temp_dir().join("text2svg-test-font-fallback.svg");
+      render_text_to_svg_file("AxA", &mut font_config, &mut HashMap::new(), &render_config, output.clone()).unwrap();
+      let svg = std::fs::read_to_string(&output).unwrap();
+      std::fs::remove_file(&output).unwrap();
+      // the x is scaled by its own face: 25.6 wide and 32 high up to the rounding of coordinates to 2048 units,
+      // the A after it starts at its advance
+      assert!(svg.contains(r#"viewBox="0 0 116 64""#), "{}", svg);
+      assert!(svg.contains("M 0 64L 19.2 19.199997L 38.4 64L 0 64Z "), "{}", svg);
+      assert!(svg.contains("M 44.80625 64L 44.80625 32L 70.4 32L 70.4 64L 44.80625 64Z "), "{}", svg);
+      assert!(svg.contains("M 76.80625 64L 96.006256 19.199997L 115.20625 64L 76.80625 64Z"), "{}", svg);
+
+      // only characters no font of the chain has are missing
+      let mut coverage = Coverage::default();
+      coverage.check("Ax中", &mut font_config, &render_config);
+      assert_eq!(coverage.missing, vec!['中']);
+  }
+
+  #[test]
+  fn test_max_width_px() {
+      let output = std::env::temp_dir().join("text2svg-test-max-width-px.svg");
+      let render = |overflow: Overflow| {
+          let mut render_config = RenderConfig::new(false, FontStyle::Regular);
+          render_config.set_max_width_px(Some(100.0), overflow);
+          render_text_to_svg_file("AoAoAoAo", &mut test_font(64, 0.0), &mut HashMap::new(), &render_config, output.clone()).unwrap();
+          let svg = std::fs::read_to_string(&output).unwrap();
+          std::fs::remove_file(&output).unwrap();
+          svg
+      };
+      // the text runs on past the box of 100 px
+      let svg = render(Overflow::Clip);
+      assert!(svg.contains(r#"viewBox="0 0 100 64""#), "{}", svg);
+      assert!(svg.contains("<clipPath id=\"overflow\">\n<rect height=\"64\" width=\"100\" x=\"0\" y=\"0\"/>"), "{}", svg);
+      assert!(svg.contains(r#"<g class="text" clip-path="url(#overflow)">"#), "{}", svg);
+      assert_eq!(svg.matches("<path").count(), 1);
+
+      // the last em fades out, at most half the box
+      let svg = render(Overflow::Fade);
+      assert!(svg.contains(r#"viewBox="0 0 100 64""#), "{}", svg);
+      assert!(svg.contains(r#"<linearGradient gradientUnits="userSpaceOnUse" id="overflow-gradient" x1="50" x2="100" y1="0" y2="0">"#), "{}", svg);
+      assert!(svg.contains(r#"<rect fill="url(#overflow-gradient)" height="64" width="100" x="0" y="0"/>"#), "{}", svg);
+      assert!(svg.contains(r#"<g class="text" mask="url(#overflow)">"#), "{}", svg);
+
+      // the test font has no ellipsis glyph, three dots drawn as .notdef boxes take up the box
+      let svg = render(Overflow::Ellipsis);
+      assert!(svg.contains(r#"viewBox="0 0 96 64""#), "{}", svg);
+      assert!(!svg.contains("overflow"), "{}", svg);
+      let render_config = RenderConfig::new(false, FontStyle::Regular);
+      assert_eq!(ellipsize("AoAoAoAo", 150.0, &mut test_font(64, 0.0), &render_config), "A...");
+      assert_eq!(ellipsize("AoAoAoAo", 200.0, &mut test_font(64, 0.0), &render_config), "Ao...");
+      assert_eq!(ellipsize("AoAo", 200.0, &mut test_font(64, 0.0), &render_config), "AoAo");
+      assert_eq!(ellipsize("AoAo", 10.0, &mut test_font(64, 0.0), &render_config), "...");
+  }
+
+  #[test]
+  fn test_decoration() {
+      let output = std::env::temp_dir().join("text2svg-test-decoration.svg");
+      let mut render_config = RenderConfig::new(false, FontStyle::Regular);
+      render_config.set_decorations(vec![Decoration::Underline]);
+      render_text_lines_to_svg(&["A".to_string(), "AA".to_string()], &mut test_font(64, 0.0), &render_config, output.clone()).unwrap();
+      let svg = std::fs::read_to_string(&output).unwrap();
+      std::fs::remove_file(&output).unwrap();
+      // 100 units below the baseline and 50 units thick in the post table of the test font, across the advance of each line
+      assert!(svg.contains("L 0 64Z M 0 70.4L 38.4 70.4L 38.4 73.6L 0 73.6Z "), "{}", svg);
+      // the underline reaches below the first line, so the second one starts lower
+      assert!(svg.contains("L 76.8 138L 38.4 138Z M 0 144.4L 76.8 144.4L 76.8 147.6L 0 147.6Z "), "{}", svg);
+      assert!(svg.contains(r#"viewBox="0 0 77 148""#), "{}", svg);
+  }
+
+  #[test]
+  fn test_grid() {
+      let output = std::env::temp_dir().join("text2svg-test-grid.svg");
+      let mut render_config = RenderConfig::new(false, FontStyle::Regular);
+      render_config.set_grid(Some(10.0));
+      render_config.set_padding(Padding::uniform(5.0));
+      render_text_lines_to_svg(&["o".to_string()], &mut test_font(64, 0.0), &render_config, output.clone()).unwrap();
+      let svg = std::fs::read_to_string(&output).unwrap();
+      std::fs::remove_file(&output).unwrap();
+      // the canvas spans -5..37 by -5..69, lines are at the multiples of 10 within it
+      assert!(svg.contains(r#"viewBox="-5 -5 42 74""#), "{}", svg);
+      let x: Vec<&str> = svg.split(r#"<line x1=""#).skip(1).map(|rest| rest.split('"').next().unwrap()).collect();
+      assert_eq!(x, vec!["0", "10", "20", "30", "-5", "-5", "-5", "-5", "-5", "-5", "-5"]);
+      assert!(svg.contains(r#"<line x1="0" x2="0" y1="-5" y2="69"/>"#), "{}", svg);
+      assert!(svg.contains(r#"<line x1="-5" x2="37" y1="60" y2="60"/>"#), "{}", svg);
+      let grid = svg.find(r##"<g class="grid" stroke="#ddd" stroke-width="0.5">"##);
+      let text = svg.find(r#"<g class="text">"#);
+      assert!(grid.is_some() && grid < text, "{}", svg);
+  }
+
+  #[test]
+  fn test_metrics_letter_space() {
+      // 0.1em between glyphs, the box keeps the space after the last glyph
+      let (bbox, extents) = layout("oo", &mut test_font(64, 0.1));
+      assert_eq!(bbox, rect(0, 0, 77, 64));
+      assert_extents(&extents, &[(3.2, 28.8), (41.6, 67.2)]);
+  }
+
+  #[test]
+  fn test_metrics_face_index() {
+      let collection = PathBuf::from(concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fonts/Text2svgTest.ttc"));
+      let mut first = FontConfig::from_path(collection.clone(), 0, 64, "none".to_string(), "#000".to_string()).unwrap();
+      let mut second = FontConfig::from_path(collection.clone(), 1, 64, "none".to_string(), "#000".to_string()).unwrap();
+      assert_eq!(first.get_font_name(), "Text2svg Test");
+      assert_eq!(second.get_font_name(), "Text2svg Test Wide");
+      assert_extents(&layout("A", &mut first).1, &[(0.0, 38.4)]);
+      assert_extents(&layout("A", &mut second).1, &[(0.0, 76.8)]);
+      assert!(FontConfig::from_path(collection, 2, 64, "none".to_string(), "#000".to_string()).is_err());
+  }
+}
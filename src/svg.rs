@@ -4,15 +4,26 @@ use resvg::tiny_skia::LineCap as StrokeLineCap;
 use resvg::tiny_skia::LineJoin as StrokeLineJoin;
 use resvg::tiny_skia::Point;
 use resvg::usvg::StrokeWidth;
+use std::cell::Cell;
+use std::collections::HashMap;
 use std::fmt::Write;
+use std::ops::Range;
+use std::sync::Arc;
 
+use crate::cache;
 use crate::font::{FontConfig, FontStyle};
+use crate::timing::{self, Phase};
+use crate::utils::stable_hash;
+use clap::ValueEnum;
+use font_kit::metrics::Metrics;
 use rustybuzz::ttf_parser;
 use rustybuzz::ttf_parser::{GlyphId, Rect};
 use rustybuzz::Face;
 
-use rustybuzz::GlyphBuffer;
-use svg::node::element::Path;
+use rustybuzz::{GlyphBuffer, GlyphInfo, GlyphPosition};
+use svg::node::element::{Group, Line, Path, Symbol, Use};
+use svg::node::Value;
+use svg::Node;
 
 /// path configuration for SVG1.1 https://www.w3.org/TR/SVG11/painting.html
 pub struct PathConfig {
@@ -61,27 +72,282 @@ impl Default for PathConfig {
     }
 }
 
+/// Horizontal ink extent of an outlined glyph
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct GlyphExtent {
+    /// byte offset in the text of the character the glyph comes from
+    pub cluster: usize,
+    pub x_min: f32,
+    pub x_max: f32,
+}
+
+/// Box of laid out text in px. Unlike ttf_parser's Rect, meant for the font units of a glyph,
+/// it holds the box of a whole document, thousands of lines tall.
+#[derive(Debug, Default, PartialEq, Clone, Copy, Eq)]
+pub struct Bounds {
+    pub x_min: i32,
+    pub y_min: i32,
+    pub x_max: i32,
+    pub y_max: i32,
+}
+
+impl Bounds {
+    pub fn width(&self) -> i32 {
+        self.x_max - self.x_min
+    }
+
+    pub fn height(&self) -> i32 {
+        self.y_max - self.y_min
+    }
+}
+
+/// How glyph outlines are written to the svg
+#[derive(ValueEnum, Debug, PartialEq, Clone, Copy, Eq)]
+#[value(rename_all="lower")]
+pub enum DefMode {
+    /// outlines of a text flattened into one path
+    Inline,
+    /// each glyph defined once as a path in `<defs>` and placed with `<use>`
+    Path,
+    /// each glyph defined once as a `<symbol>` with a viewBox and placed with `<use>`
+    Symbol,
+    /// a standalone path for each glyph occurrence positioned with a transform, without `<defs>`/`<use>`
+    None,
+}
+
+/// How glyphs missing from the font are drawn
+#[derive(ValueEnum, Debug, PartialEq, Clone, Copy, Eq)]
+#[value(rename_all="lower")]
+pub enum MissingGlyph {
+    /// the `.notdef` glyph of the font
+    Notdef,
+    /// a rectangle as wide as the advance and as high as the ascent and descent of the font
+    Box,
+}
+
+/// Line drawn along the text in its colors
+#[derive(ValueEnum, Debug, PartialEq, Clone, Copy, Eq)]
+#[value(rename_all="lower")]
+pub enum Decoration {
+    /// at the underline position and thickness of the font
+    Underline,
+    /// at the ascent
+    Overline,
+    /// through the middle of the x-height
+    #[value(name = "line-through")]
+    LineThrough,
+}
+
+/// Top of the decoration line above the baseline and its thickness in font units.
+/// Fonts without underline metrics in their post table get a line of a twentieth em a tenth em below the baseline,
+/// fonts without x-height are struck at a quarter em.
+fn decoration_metrics(decoration: Decoration, metrics: &Metrics) -> (f32, f32) {
+    let units_per_em = units_per_em(metrics);
+    let thickness = if metrics.underline_thickness > 0.0 { metrics.underline_thickness } else { units_per_em / 20.0 };
+    let top = match decoration {
+        Decoration::Underline if metrics.underline_position != 0.0 => metrics.underline_position,
+        Decoration::Underline => -units_per_em / 10.0,
+        Decoration::Overline => metrics.ascent,
+        Decoration::LineThrough => {
+            let x_height = if metrics.x_height > 0.0 { metrics.x_height } else { units_per_em / 2.0 };
+            (x_height + thickness) / 2.0
+        }
+    };
+    (top, thickness)
+}
+
+/// How the inside of overlapping contours is determined
+#[derive(ValueEnum, Debug, PartialEq, Clone, Copy, Eq)]
+#[value(rename_all="lower")]
+pub enum FillRule {
+    /// the rule of TrueType and CFF outlines, overlapping contours of the same direction stay filled
+    NonZero,
+    /// overlaps are holes
+    EvenOdd,
+}
+
+impl FillRule {
+    pub fn attribute(&self) -> &'static str {
+        match self {
+            FillRule::NonZero => "nonzero",
+            FillRule::EvenOdd => "evenodd",
+        }
+    }
+}
+
+/// Namespace of `xlink:href`, declared on the root when used
+pub const XLINK_NS: &str = "http://www.w3.org/1999/xlink";
+
+/// Namespace of the `inkscape:` attributes of layers, declared on the root when used
+pub const INKSCAPE_NS: &str = "http://www.inkscape.org/namespaces/inkscape";
+
+/// Attribute referencing a glyph definition, a symbol or an image
+#[derive(Debug, PartialEq, Clone, Copy, Eq)]
+pub enum HrefAttr {
+    /// `href` of SVG 2
+    Href,
+    /// `xlink:href` of SVG 1.1, for renderers without bare `href`
+    Xlink,
+    /// both, for renderers reading only one of them
+    Both,
+}
+
+impl HrefAttr {
+    /// Set the reference on node
+    pub fn set<T: Node>(&self, mut node: T, href: impl Into<String>) -> T {
+        let href = href.into();
+        if *self != HrefAttr::Xlink {
+            node.assign("href", href.clone());
+        }
+        if self.uses_xlink() {
+            node.assign("xlink:href", href);
+        }
+        node
+    }
+
+    /// Whether the root has to declare the xlink namespace
+    pub fn uses_xlink(&self) -> bool {
+        matches!(self, HrefAttr::Xlink | HrefAttr::Both)
+    }
+}
+
+impl DefMode {
+    /// Whether glyphs are written as definitions referenced by `<use>`
+    pub fn has_defs(&self) -> bool {
+        matches!(self, DefMode::Path | DefMode::Symbol)
+    }
+}
+
+/// Glyph outline at the origin, defined once and referenced at each occurrence
+#[derive(Debug, Clone, PartialEq)]
+pub struct GlyphDef {
+    pub id: String,
+    pub d: String,
+    // ink box at the origin in px: x, y, width, height
+    view_box: (f32, f32, f32, f32),
+    mode: DefMode,
+}
+
+impl GlyphDef {
+    pub fn new(id_prefix: &str, glyph_id: u16, outline: &GlyphOutline, bbox: &Rect, scale: f32, mode: DefMode) -> Self {
+        let mut d = String::new();
+        outline.replay(&mut GlyphPathBuilder::new(scale, -scale, 0.0, 0.0, &mut d));
+        // the same glyph id differs between fonts and sizes
+        let id = format!("{}glyph-{}-{:08x}", id_prefix, glyph_id, stable_hash(&[d.as_bytes()]) as u32);
+        // y is flipped, a zero sized viewBox would disable rendering
+        let view_box = (
+            bbox.x_min as f32 * scale,
+            -bbox.y_max as f32 * scale,
+            (bbox.width() as f32 * scale).max(1.0),
+            (bbox.height() as f32 * scale).max(1.0),
+        );
+        Self { id, d, view_box, mode }
+    }
+
+    /// Definition of the nth glyph occurrence defined on its own in a document, with an id like `g72-3`
+    pub fn occurrence(mut self, id_prefix: &str, glyph_id: u16, n: usize) -> Self {
+        self.id = format!("{}g{}-{}", id_prefix, glyph_id, n);
+        self
+    }
+
+    /// Element to put in `<defs>`
+    pub fn definition(&self) -> Box<dyn Node> {
+        let path = Path::new().set("d", self.d.as_str());
+        match self.mode {
+            DefMode::Symbol => Box::new(
+                Symbol::new()
+                    .set("id", self.id.as_str())
+                    .set("viewBox", self.view_box)
+                    // strokes extend beyond the ink box
+                    .set("overflow", "visible")
+                    .add(path),
+            ),
+            _ => Box::new(path.set("id", self.id.as_str())),
+        }
+    }
+
+    /// Element placing the glyph with its origin at x on the baseline,
+    /// a reference to the definition or a standalone path
+    pub fn place_at(&self, x: f32, baseline: f32, href: HrefAttr) -> Box<dyn Node> {
+        let glyph_use = href.set(Use::new(), format!("#{}", self.id));
+        match self.mode {
+            DefMode::Symbol => {
+                let (x_min, y_min, width, height) = self.view_box;
+                Box::new(
+                    glyph_use
+                        .set("x", x + x_min)
+                        .set("y", baseline + y_min)
+                        .set("width", width)
+                        .set("height", height),
+                )
+            }
+            DefMode::None => Box::new(
+                Path::new()
+                    .set("d", self.d.as_str())
+                    .set("transform", format!("translate({} {})", x, baseline)),
+            ),
+            _ => Box::new(glyph_use.set("x", x).set("y", baseline)),
+        }
+    }
+}
+
 pub struct Text {
     pub path: Path,
-    pub bounding_box: Rect,
+    pub bounding_box: Bounds,
+    // length of the longest glyph contour, the dash pattern restarts at each contour
+    pub max_contour_length: f32,
+    // ink extents of glyphs in visual order
+    pub glyph_extents: Vec<GlyphExtent>,
+    // glyphs placed one by one drawing the text instead of the path, unless inlined
+    pub glyphs: Option<Group>,
+    // definitions of distinct glyphs referenced by glyphs
+    pub glyph_defs: Vec<GlyphDef>,
+    // distinct glyphs outlined to build the text
+    pub outlined_glyphs: usize,
+    // lines at the vertical metrics of the face, drawn over the text for debugging
+    pub guides: Option<Group>,
+    // pen position after the last glyph, without trailing letter spacing
+    pub end_x: f32,
+    // the last glyph is outlined, so a glyph continuing the line is letter spaced from it
+    pub after_glyph: bool,
 }
 
 impl Text {
-    pub fn new(path: Path, bounding_box: Rect) -> Self {
-        Self { path, bounding_box }
+    pub fn new(path: Path, bounding_box: Bounds) -> Self {
+        Self {
+            path,
+            bounding_box,
+            max_contour_length: 0.0,
+            glyph_extents: vec![],
+            glyphs: None,
+            glyph_defs: vec![],
+            outlined_glyphs: 0,
+            guides: None,
+            end_x: bounding_box.x_max as f32,
+            after_glyph: false,
+        }
     }
 
-    pub fn builder() -> TextBuilder<'static> {
-        TextBuilder::default()
+    /// Set an attribute on the element drawing the text
+    pub fn set<V: Into<Value>>(mut self, name: &str, value: V) -> Self {
+        let value = value.into();
+        if let Some(glyphs) = self.glyphs.as_mut() {
+            glyphs.assign(name, value.clone());
+        }
+        self.path.assign(name, value);
+        self
+    }
+
+    /// Element drawing the text: the outline path, or the glyphs placed one by one
+    pub fn into_node(self) -> Box<dyn Node> {
+        match self.glyphs {
+            Some(glyphs) => Box::new(glyphs),
+            None => Box::new(self.path),
+        }
     }
 
-    pub fn get_viewbox(&self) -> (u32, u32, u32, u32) {
-        (
-            self.bounding_box.x_min as u32,
-            self.bounding_box.y_min as u32,
-            self.bounding_box.width() as u32,
-            self.bounding_box.height() as u32,
-        )
+    pub fn builder() -> TextBuilder<'static> {
+        TextBuilder::default()
     }
 
     pub fn width(&self) -> u32 {
@@ -93,11 +359,99 @@ impl Text {
     }
 }
 
+/// A glyph outlined on its own, its path starts at the top left corner of the ink box
+pub struct GlyphSvg {
+    pub character: char,
+    pub glyph_id: u16,
+    pub path: Path,
+    pub width: f32,
+    pub height: f32,
+}
+
+impl GlyphSvg {
+    /// File name by code point and glyph id, e.g. `u0041-g36.svg`
+    pub fn file_name(&self) -> String {
+        format!("u{:04x}-g{}.svg", self.character as u32, self.glyph_id)
+    }
+}
+
+/// Shaped glyphs of a run with the byte offset of the run in the text, a whole glyph buffer or a part of it
+#[derive(Debug, Clone, Copy)]
+pub struct GlyphRun<'a> {
+    pub offset: usize,
+    pub infos: &'a [GlyphInfo],
+    pub positions: &'a [GlyphPosition],
+    // font of the fallback chain that shaped the glyphs, 0 is the font itself
+    pub face: usize,
+}
+
+impl<'a> GlyphRun<'a> {
+    pub fn new(offset: usize, glyphs: &'a GlyphBuffer) -> Self {
+        Self { offset, infos: glyphs.glyph_infos(), positions: glyphs.glyph_positions(), face: 0 }
+    }
+
+    pub fn with_face(mut self, face: usize) -> Self {
+        self.face = face;
+        self
+    }
+
+    /// Glyphs whose cluster starts in the byte range of the text.
+    /// Clusters are monotonic in either direction, so they are a contiguous part of the run.
+    pub fn slice(&self, range: &Range<usize>) -> Self {
+        let in_range = |info: &GlyphInfo| range.contains(&(self.offset + info.cluster as usize));
+        let start = self.infos.iter().position(in_range).unwrap_or(self.infos.len());
+        let end = self.infos.iter().rposition(in_range).map_or(start, |last| last + 1);
+        Self { infos: &self.infos[start..end], positions: &self.positions[start..end], ..*self }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.infos.is_empty()
+    }
+}
+
+// A font of the fallback chain as placed by build_runs
+struct RunFace {
+    metrics: Metrics,
+    // factor converting font units of the face to px
+    scale_factor: f32,
+    data: Arc<Vec<u8>>,
+    index: u32,
+    outlines: GlyphOutlines,
+}
+
 pub struct TextBuilder<'a> {
     pub origin: Point,
     pub color: &'a str,
     pub fill_color: &'a str,
     pub path_config: PathConfig,
+    // emit visual properties in a style attribute instead of presentation attributes
+    pub style_attr: bool,
+    // font size of this text, defaults to the size of font config
+    pub size: Option<f32>,
+    // threads outlining distinct glyphs
+    pub jobs: usize,
+    pub def_mode: DefMode,
+    // without the glyph cache each occurrence is outlined and defined on its own, numbered by the counter
+    pub glyph_occurrences: Option<&'a Cell<usize>>,
+    // prefix of glyph definition ids
+    pub id_prefix: &'a str,
+    // build metric guides along with the text
+    pub guides: bool,
+    pub missing: MissingGlyph,
+    // baseline below the origin and depth of the text box below the baseline in px,
+    // the size and the depth of the ink unless overridden
+    pub ascent: Option<f32>,
+    pub descent: Option<f32>,
+    // attribute of <use> referencing glyph definitions
+    pub href: HrefAttr,
+    // the text continues a line after an outlined glyph, so its first glyph is letter spaced
+    pub after_glyph: bool,
+    // fill-rule of the glyphs, left to the svg default (nonzero) when not set
+    pub fill_rule: Option<FillRule>,
+    // radius of the circle the baseline follows instead of a straight line
+    pub circle: Option<f32>,
+    // lines from the origin to the end of the text, not drawn along a circle
+    pub decorations: &'a [Decoration],
 }
 
 impl Default for TextBuilder<'_> {
@@ -107,6 +461,21 @@ impl Default for TextBuilder<'_> {
             color: "#000",
             fill_color: "#000",
             path_config: PathConfig::default(),
+            style_attr: false,
+            size: None,
+            jobs: 1,
+            def_mode: DefMode::Inline,
+            glyph_occurrences: None,
+            id_prefix: "",
+            guides: false,
+            missing: MissingGlyph::Notdef,
+            ascent: None,
+            descent: None,
+            href: HrefAttr::Href,
+            after_glyph: false,
+            fill_rule: None,
+            circle: None,
+            decorations: &[],
         }
     }
 }
@@ -127,45 +496,160 @@ impl<'a> TextBuilder<'a> {
         self
     }
 
+    pub fn set_style_attr(&mut self, style_attr: bool) -> &mut Self {
+        self.style_attr = style_attr;
+        self
+    }
+
+    pub fn set_size(&mut self, size: f32) -> &mut Self {
+        self.size = Some(size);
+        self
+    }
+
+    pub fn set_jobs(&mut self, jobs: usize) -> &mut Self {
+        self.jobs = jobs;
+        self
+    }
+
+    pub fn set_def_mode(&mut self, def_mode: DefMode) -> &mut Self {
+        self.def_mode = def_mode;
+        self
+    }
+
+    pub fn set_glyph_occurrences(&mut self, glyph_occurrences: Option<&'a Cell<usize>>) -> &mut Self {
+        self.glyph_occurrences = glyph_occurrences;
+        self
+    }
+
+    pub fn set_id_prefix(&mut self, id_prefix: &'a str) -> &mut Self {
+        self.id_prefix = id_prefix;
+        self
+    }
+
+    pub fn set_guides(&mut self, guides: bool) -> &mut Self {
+        self.guides = guides;
+        self
+    }
+
+    pub fn set_missing(&mut self, missing: MissingGlyph) -> &mut Self {
+        self.missing = missing;
+        self
+    }
+
+    pub fn set_metrics(&mut self, ascent: Option<f32>, descent: Option<f32>) -> &mut Self {
+        self.ascent = ascent;
+        self.descent = descent;
+        self
+    }
+
+    pub fn set_href(&mut self, href: HrefAttr) -> &mut Self {
+        self.href = href;
+        self
+    }
+
+    pub fn set_fill_rule(&mut self, fill_rule: Option<FillRule>) -> &mut Self {
+        self.fill_rule = fill_rule;
+        self
+    }
+
+    pub fn set_after_glyph(&mut self, after_glyph: bool) -> &mut Self {
+        self.after_glyph = after_glyph;
+        self
+    }
+
+    pub fn set_circle(&mut self, radius: Option<f32>) -> &mut Self {
+        self.circle = radius;
+        self
+    }
+
+    pub fn set_decorations(&mut self, decorations: &'a [Decoration]) -> &mut Self {
+        self.decorations = decorations;
+        self
+    }
+
     pub fn build(&self, font_config: &FontConfig, font_style: &FontStyle,glyphs: &GlyphBuffer) -> Text {
-        let ft_face = font_config.get_font_by_style(font_style).unwrap();
-        let metrics = ft_face.metrics();
+        self.build_runs(font_config, font_style, [GlyphRun::new(0, glyphs)])
+    }
 
-        let origin_glyph_height = metrics.ascent - metrics.descent;
+    /// Build text from runs shaped separately (e.g. with different directions), laid out in the given order.
+    pub fn build_runs<'b>(
+        &self,
+        font_config: &FontConfig,
+        font_style: &FontStyle,
+        runs: impl IntoIterator<Item = GlyphRun<'b>>,
+    ) -> Text {
         // target size
-        let glyph_height = font_config.get_size() as f32;
-        // factor used to convert origin size to given size
-        let scale_factor = glyph_height / origin_glyph_height;
-
-        if font_config.get_debug() {
-            println!(
-                "origin height: {:?} scaled height: {:?} scale_factor:{:?} units_per_em:{:?}",
-                origin_glyph_height, glyph_height, scale_factor, metrics.units_per_em
-            );
-        }
+        let glyph_height = self.size.unwrap_or(font_config.get_size() as f32);
+        let runs: Vec<GlyphRun> = runs.into_iter().collect();
+        // fonts of the fallback chain the runs take glyphs from, each scaled by its own units per em
+        let faces: Vec<Option<RunFace>> = (0..font_config.chain_len())
+            .map(|face| {
+                let face_runs: Vec<&GlyphRun> = runs.iter().filter(|run| run.face == face).collect();
+                (face == 0 || !face_runs.is_empty()).then(|| self.run_face(font_config, face, font_style, glyph_height, &face_runs))
+            })
+            .collect();
+        let RunFace { metrics, scale_factor, .. } = *faces[0].as_ref().unwrap();
+        let units_per_em = units_per_em(&metrics);
+        let ascent = self.ascent.unwrap_or(glyph_height);
 
-        let ft_face_data = &ft_face.copy_font_data().unwrap();
-        let hb_face = Face::from_slice(ft_face_data, 0).unwrap();
+        log::debug!(
+            "origin height: {:?} scaled height: {:?} scale_factor:{:?} units_per_em:{:?}",
+            glyph_height_units(&metrics), glyph_height, scale_factor, units_per_em
+        );
 
-        let glyph_num = glyphs.len();
-        let glyph_positions = glyphs.glyph_positions();
-        let glyph_infos = glyphs.glyph_infos();
+        // glyphs outlined again at each occurrence without the glyph cache
+        let mut outlined_occurrences = 0;
 
         let mut x = self.origin.x;
         let mut d = String::new();
+        // the circle touches the top and left of the text box with the ascent above the baseline
+        let center_offset = self.circle.unwrap_or(0.0) + ascent;
+        let center = Point { x: self.origin.x + center_offset, y: self.origin.y + center_offset };
 
-        let mut prev_space_glyph = true;
-        let letter_space =
-            scale_factor * font_config.get_letter_space() * metrics.units_per_em as f32;
-        let mut y_offset = i16::MAX;
+        let mut prev_space_glyph = !self.after_glyph;
+        let letter_space = font_config.get_letter_space_unit().to_px(
+            font_config.get_letter_space(),
+            scale_factor * units_per_em,
+        );
+        // px of the lowest ink edge below the baseline, for descenders like 'g' and 'y'
+        let mut ink_depth: f32 = 0.0;
+        // left-most ink edge, glyphs with negative left side bearing (e.g. italic) go beyond the origin
+        let mut min_x = self.origin.x;
+        let mut max_contour_length: f32 = 0.0;
+        let mut glyph_extents = vec![];
+        let mut placed_glyphs = Group::new();
+        let mut glyph_defs: Vec<GlyphDef> = vec![];
+        // keyed by font of the chain, glyph id and the advance of a missing glyph box,
+        // other glyphs have the same outline at any advance
+        let mut def_index: HashMap<(usize, u16, i32), usize> = HashMap::new();
 
         // convert glyph outlines to svg
-        for i in 0..glyph_num {
-            let glyph_id = glyph_infos[i].glyph_id;
-            let glyph_pos = glyph_positions[i];
+        for GlyphRun { offset, infos: glyph_infos, positions: glyph_positions, face } in runs {
+            let glyph_num = glyph_infos.len();
+            let RunFace { metrics, scale_factor, data: ref ft_face_data, index: face_index, ref outlines } = *faces[face].as_ref().unwrap();
+
+            // Glyphs sharing a cluster come from one grapheme (e.g. ZWJ emoji sequence, base with marks),
+            // so they are spaced as a single unit and show at most one missing glyph box.
+            let mut prev_cluster = None;
+            let mut cluster_has_notdef = false;
+
+            for i in 0..glyph_num {
+                let glyph_id = glyph_infos[i].glyph_id;
+                let glyph_pos = glyph_positions[i];
+                let cluster = glyph_infos[i].cluster;
+                let same_cluster = prev_cluster == Some(cluster);
+                prev_cluster = Some(cluster);
+                if !same_cluster {
+                    cluster_has_notdef = false;
+                }
+                if glyph_id == 0 {
+                    if cluster_has_notdef {
+                        continue;
+                    }
+                    cluster_has_notdef = true;
+                }
 
-            if font_config.get_debug() {
-                println!(
+                log::trace!(
                     "{:?}/{:?} x:{:?} glyph id: {:?} {:?} ",
                     i + 1,
                     glyph_num,
@@ -173,67 +657,509 @@ impl<'a> TextBuilder<'a> {
                     glyph_id,
                     glyph_positions[i]
                 );
-            }
 
-            x += if !prev_space_glyph { letter_space } else { 0.0 };
-
-            // uniform scale
-            // Note that the scale_y should be negative by adding a minus symbol to flip vertically to render correctly
-            let mut glyph_builder = GlyphPathBuilder::new(
-                scale_factor,
-                -scale_factor,
-                x,
-                self.origin.y + glyph_height,
-                &mut d,
-            );
-
-            let x_offset = if let Some(hb_bbox) =
-                hb_face.outline_glyph(GlyphId(glyph_id as u16), &mut glyph_builder)
-            {
-                prev_space_glyph = false;
-                if font_config.get_debug() {
-                    println!("bbox for glyph: {:?}", hb_bbox);
-                }
-                if hb_bbox.y_min < y_offset {
-                    y_offset = hb_bbox.y_min;
+                // zero advance glyphs (e.g. marks, joiners) stay attached to the glyph before them,
+                // letter spacing goes around the pair and they don't count as space
+                let zero_advance = glyph_pos.x_advance == 0;
+                x += if !prev_space_glyph && !same_cluster && !zero_advance { letter_space } else { 0.0 };
+
+                // shaping moves glyphs off the pen position, e.g. marks or pairs of the kern table
+                let glyph_x = x + glyph_pos.x_offset as f32 * scale_factor;
+                let baseline = self.origin.y + ascent - glyph_pos.y_offset as f32 * scale_factor;
+                // along a circle the pen distance is measured on the arc instead
+                let (glyph_x, baseline, angle) = match self.circle {
+                    Some(radius) => {
+                        let advance = glyph_pos.x_advance as f32 * scale_factor;
+                        let rise = glyph_pos.y_offset as f32 * scale_factor;
+                        let (point, angle) = circle_placement(center, radius, glyph_x - self.origin.x, advance, rise);
+                        (point.x, point.y, angle)
+                    }
+                    None => (glyph_x, baseline, 0.0),
+                };
+
+                // uniform scale
+                // Note that the scale_y should be negative by adding a minus symbol to flip vertically to render correctly
+                let mut glyph_builder = GlyphPathBuilder::new(
+                    scale_factor,
+                    -scale_factor,
+                    glyph_x,
+                    baseline,
+                    &mut d,
+                );
+                if self.circle.is_some() {
+                    glyph_builder.set_rotation(angle);
                 }
-                // TODO: non-monospace font
-                glyph_pos.x_advance as f32 * scale_factor
-            } else {
-                prev_space_glyph = true;
-                // For the space glyph, we use its advance as its width
-                glyph_pos.x_advance as f32 * scale_factor
-            };
 
-            // next glyph
-            x += x_offset;
+                // the box takes the place of a real glyph, so the advance stays the one shaping gave
+                let missing_box = (glyph_id == 0 && self.missing == MissingGlyph::Box)
+                    .then(|| missing_glyph_box(glyph_pos.x_advance, &metrics));
+                // without the glyph cache each occurrence is outlined again
+                let fresh_outlines = (self.glyph_occurrences.is_some() && missing_box.is_none())
+                    .then(|| outline_glyph_ids(ft_face_data, face_index, &[glyph_id as u16]));
+                let outline = match &missing_box {
+                    Some(missing_box) => Some(missing_box),
+                    None => fresh_outlines.as_ref().unwrap_or(outlines).get(&(glyph_id as u16)).and_then(|outline| outline.as_ref()),
+                };
+                let x_offset = if let Some((outline, hb_bbox)) = outline {
+                    let hb_bbox = *hb_bbox;
+                    outline.replay(&mut glyph_builder);
+                    if fresh_outlines.is_some() {
+                        outlined_occurrences += 1;
+                    }
+                    if self.def_mode != DefMode::Inline {
+                        let def_key = (face, glyph_id as u16, if missing_box.is_some() { glyph_pos.x_advance } else { 0 });
+                        let new_def = || GlyphDef::new(self.id_prefix, glyph_id as u16, outline, &hb_bbox, scale_factor, self.def_mode);
+                        let index = match self.glyph_occurrences {
+                            Some(occurrences) => {
+                                occurrences.set(occurrences.get() + 1);
+                                glyph_defs.push(new_def().occurrence(self.id_prefix, glyph_id as u16, occurrences.get()));
+                                glyph_defs.len() - 1
+                            }
+                            None => *def_index.entry(def_key).or_insert_with(|| {
+                                glyph_defs.push(new_def());
+                                glyph_defs.len() - 1
+                            }),
+                        };
+                        let placed = glyph_defs[index].place_at(glyph_x, baseline, self.href);
+                        placed_glyphs = match self.circle {
+                            Some(_) => placed_glyphs.add(
+                                Group::new().set("transform", format!("rotate({} {} {})", angle.to_degrees(), glyph_x, baseline)).add(placed),
+                            ),
+                            None => placed_glyphs.add(placed),
+                        };
+                    }
+                    prev_space_glyph &= zero_advance;
+                    log::trace!("bbox for glyph: {:?}", hb_bbox);
+                    let ink_bottom = (hb_bbox.y_min as i32 + glyph_pos.y_offset).clamp(i16::MIN as i32, i16::MAX as i32) as i16;
+                    ink_depth = ink_depth.max(descender_depth(Some(ink_bottom), scale_factor));
+                    min_x = min_x.min(glyph_x + hb_bbox.x_min as f32 * scale_factor);
+                    glyph_extents.push(GlyphExtent {
+                        cluster: offset + cluster as usize,
+                        x_min: glyph_x + hb_bbox.x_min as f32 * scale_factor,
+                        x_max: glyph_x + hb_bbox.x_max as f32 * scale_factor,
+                    });
+                    max_contour_length = max_contour_length.max(glyph_builder.max_contour_length());
+                    // TODO: non-monospace font
+                    glyph_pos.x_advance as f32 * scale_factor
+                } else {
+                    prev_space_glyph |= !zero_advance;
+                    // For the space glyph, we use its advance as its width
+                    glyph_pos.x_advance as f32 * scale_factor
+                };
+
+                // next glyph
+                x += x_offset;
+            }
+        }
+
+        // a rectangle for each decoration, filled and stroked like the glyphs
+        let mut decoration_bottom = self.origin.y;
+        if self.circle.is_none() && !self.decorations.is_empty() {
+            let baseline = self.origin.y + ascent;
+            let mut decoration_d = String::new();
+            for decoration in self.decorations {
+                let (top, thickness) = decoration_metrics(*decoration, &metrics);
+                let (y1, y2) = (baseline - top * scale_factor, baseline - (top - thickness) * scale_factor);
+                decoration_bottom = decoration_bottom.max(y2);
+                let (x1, x2) = (self.origin.x, x);
+                write!(decoration_d, "M {} {}L {} {}L {} {}L {} {}Z ", x1, y1, x2, y1, x2, y2, x1, y2).unwrap();
+            }
+            d += &decoration_d;
+            if self.def_mode != DefMode::Inline {
+                placed_glyphs = placed_glyphs.add(Path::new().set("d", decoration_d));
+            }
         }
 
-        let bbox = Rect {
-            x_min: self.origin.x.ceil() as i16,
-            y_min: self.origin.y.ceil() as i16,
-            x_max: (x + letter_space).ceil() as i16,
-            y_max: (self.origin.y + glyph_height + y_offset.abs() as f32 * scale_factor).ceil() as i16,
+        // round outwards so that neither the advance nor ink overhanging it is clipped
+        let max_x = glyph_extents.iter().fold(x + letter_space, |max_x, extent| max_x.max(extent.x_max));
+        let mut bbox = match self.circle {
+            // the square around the circle and the ascent of glyphs standing on it
+            Some(_) => Bounds {
+                x_min: self.origin.x.floor() as i32,
+                y_min: self.origin.y.floor() as i32,
+                x_max: (2.0 * center.x - self.origin.x).ceil() as i32,
+                y_max: (2.0 * center.y - self.origin.y).ceil() as i32,
+            },
+            None => Bounds {
+                x_min: min_x.floor() as i32,
+                y_min: self.origin.y.floor() as i32,
+                x_max: max_x.ceil() as i32,
+                y_max: (self.origin.y + ascent + self.descent.unwrap_or(ink_depth)).ceil() as i32,
+            },
         };
 
-        if font_config.get_debug() {
-            println!(
-                "x_min:{:?} y_min:{:?} x_max:{:?} y_max:{:?}",
-                bbox.x_min, bbox.y_min, bbox.x_max, bbox.y_max
-            );
+        // an underline may reach below the descent
+        bbox.y_max = bbox.y_max.max(decoration_bottom.ceil() as i32);
+
+        log::debug!(
+            "x_min:{:?} y_min:{:?} x_max:{:?} y_max:{:?}",
+            bbox.x_min, bbox.y_min, bbox.x_max, bbox.y_max
+        );
+
+        let mut text = Text::new(
+            self.set_presentation(Path::new().set("d", d)),
+            bbox
+        );
+        text.max_contour_length = max_contour_length;
+        text.glyph_extents = glyph_extents;
+        text.end_x = x;
+        text.after_glyph = !prev_space_glyph;
+        text.outlined_glyphs = faces
+            .iter()
+            .flatten()
+            .map(|face| face.outlines.values().filter(|outline| outline.is_some()).count())
+            .sum::<usize>()
+            + outlined_occurrences;
+        if self.def_mode != DefMode::Inline {
+            text.glyphs = Some(self.set_presentation(placed_glyphs));
+        }
+        if self.def_mode.has_defs() {
+            text.glyph_defs = glyph_defs;
+        }
+        if self.guides {
+            let baseline = self.origin.y + ascent;
+            text.guides = Some(metric_guides(&metrics, scale_factor, baseline, &bbox, self.id_prefix));
+        }
+        text
+    }
+
+    // Face of font `face` in the fallback chain with its scale to the target size, the distinct glyphs of its runs outlined
+    fn run_face(&self, font_config: &FontConfig, face: usize, font_style: &FontStyle, glyph_height: f32, runs: &[&GlyphRun]) -> RunFace {
+        let style = font_config.chain_style(face, font_style).unwrap_or_else(|| font_style.clone());
+        let font_config = font_config.chain(face);
+        let ft_face = font_config.get_font_by_style(&style).unwrap();
+        let metrics = ft_face.metrics();
+        let data = ft_face.copy_font_data().unwrap();
+        let index = font_config.get_face_index(&style);
+        // each distinct glyph is outlined once, occurrences replay its outline at their position
+        let mut glyph_ids: Vec<u16> = runs
+            .iter()
+            .flat_map(|run| run.infos.iter().map(|info| info.glyph_id as u16))
+            .collect();
+        glyph_ids.sort_unstable();
+        glyph_ids.dedup();
+        let outlines = match self.glyph_occurrences {
+            Some(_) => GlyphOutlines::new(),
+            None => outline_glyphs(&data, index, &glyph_ids, self.jobs),
+        };
+        RunFace { metrics, scale_factor: glyph_height / glyph_height_units(&metrics), data, index, outlines }
+    }
+
+    /// Outline each character as a single glyph, fit to the ink box of the glyph.
+    /// Characters missing from the face and glyphs without outline (e.g. space) are left out.
+    pub fn build_glyphs(&self, font_config: &FontConfig, font_style: &FontStyle, chars: &[char]) -> Vec<GlyphSvg> {
+        let Some(ft_face) = font_config.get_font_by_style(font_style) else {
+            return vec![];
+        };
+        let metrics = ft_face.metrics();
+        let scale = self.size.unwrap_or(font_config.get_size() as f32) / glyph_height_units(&metrics);
+        let Some(face_data) = ft_face.copy_font_data() else {
+            return vec![];
+        };
+        let face_index = font_config.get_face_index(font_style);
+        let Some(face) = Face::from_slice(&face_data, face_index) else {
+            return vec![];
+        };
+        let char_glyphs: Vec<(char, u16)> = chars
+            .iter()
+            .filter_map(|ch| face.glyph_index(*ch).map(|glyph_id| (*ch, glyph_id.0)))
+            .filter(|(_, glyph_id)| *glyph_id != 0)
+            .collect();
+        let mut glyph_ids: Vec<u16> = char_glyphs.iter().map(|(_, glyph_id)| *glyph_id).collect();
+        glyph_ids.sort_unstable();
+        glyph_ids.dedup();
+        let outlines = outline_glyphs(&face_data, face_index, &glyph_ids, self.jobs);
+        char_glyphs
+            .into_iter()
+            .filter_map(|(character, glyph_id)| {
+                let (outline, bbox) = outlines.get(&glyph_id)?.as_ref()?;
+                let mut d = String::new();
+                let (x, y) = (-bbox.x_min as f32 * scale, bbox.y_max as f32 * scale);
+                outline.replay(&mut GlyphPathBuilder::new(scale, -scale, x, y, &mut d));
+                Some(GlyphSvg {
+                    character,
+                    glyph_id,
+                    path: self.set_presentation(Path::new().set("d", d)),
+                    // a zero sized viewBox would disable rendering
+                    width: (bbox.width() as f32 * scale).max(1.0),
+                    height: (bbox.height() as f32 * scale).max(1.0),
+                })
+            })
+            .collect()
+    }
+
+    /// Visual properties of the glyph path
+    pub fn presentation_attributes(&self) -> Vec<(&'static str, String)> {
+        let mut attributes = vec![
+            ("fill", self.fill_color.to_string()),
+            ("stroke", self.color.to_string()),
+            ("stroke-width", self.path_config.stroke_width.get().to_string()),
+            ("stroke-linejoin", self.path_config.get_stroke_linejoin()),
+            ("stroke-linecap", self.path_config.get_stroke_linecap()),
+        ];
+        if let Some(fill_rule) = self.fill_rule {
+            attributes.insert(1, ("fill-rule", fill_rule.attribute().to_string()));
+        }
+        attributes
+    }
+
+    // Set visual properties as presentation attributes or a single style attribute
+    fn set_presentation<T: Node>(&self, mut node: T) -> T {
+        let attributes = self.presentation_attributes();
+        if self.style_attr {
+            node.assign("style", style_declarations(&attributes));
+            return node;
+        }
+        for (name, value) in attributes {
+            node.assign(name, value);
+        }
+        node
+    }
+}
+
+/// Origin of a glyph standing on a circle and its rotation in radians, for text starting at the top
+/// of the circle and running clockwise. The glyph is turned to the tangent at the middle of its advance,
+/// `distance` is the pen position along the arc and `rise` lifts the glyph off the circle.
+pub fn circle_placement(center: Point, radius: f32, distance: f32, advance: f32, rise: f32) -> (Point, f32) {
+    let angle = (distance + advance / 2.0) / radius;
+    let (sin, cos) = angle.sin_cos();
+    let middle = Point { x: center.x + (radius + rise) * sin, y: center.y - (radius + rise) * cos };
+    (Point { x: middle.x - advance / 2.0 * cos, y: middle.y - advance / 2.0 * sin }, angle)
+}
+
+/// Join properties into css declarations, e.g. `fill:none;stroke:#000`
+pub fn style_declarations(attributes: &[(&str, String)]) -> String {
+    attributes
+        .iter()
+        .map(|(name, value)| format!("{}:{}", name, value))
+        .collect::<Vec<_>>()
+        .join(";")
+}
+
+// CFF fonts commonly use 1000 units per em
+const DEFAULT_UNITS_PER_EM: f32 = 1000.0;
+
+/// Units per em, some subset fonts report zero
+pub fn units_per_em(metrics: &Metrics) -> f32 {
+    if metrics.units_per_em == 0 {
+        DEFAULT_UNITS_PER_EM
+    } else {
+        metrics.units_per_em as f32
+    }
+}
+
+/// Glyph height (ascent - descent) in font units,
+/// falling back to the font bounding box and then the em square for degenerate metrics
+pub fn glyph_height_units(metrics: &Metrics) -> f32 {
+    let height = metrics.ascent - metrics.descent;
+    if height.is_finite() && height > 0.0 {
+        return height;
+    }
+    let bbox_height = metrics.bounding_box.height();
+    if bbox_height.is_finite() && bbox_height > 0.0 {
+        return bbox_height;
+    }
+    units_per_em(metrics)
+}
+
+// metric, color and font units above the baseline of each guide
+fn guide_metrics(metrics: &Metrics) -> [(&'static str, &'static str, f32); 5] {
+    [
+        ("ascent", "#00f", metrics.ascent),
+        ("cap-height", "#f80", metrics.cap_height),
+        ("x-height", "#0a0", metrics.x_height),
+        ("baseline", "#f00", 0.0),
+        ("descent", "#00f", metrics.descent),
+    ]
+}
+
+/// Thin lines across the text box at the ascent, cap height, x-height, baseline and descent of the face.
+/// Cap height and x-height are left out when the face doesn't provide them.
+pub fn metric_guides(metrics: &Metrics, scale_factor: f32, baseline: f32, bbox: &Bounds, class_prefix: &str) -> Group {
+    let mut guides = Group::new().set("stroke-width", 0.5).set("fill", "none");
+    for (name, color, units) in guide_metrics(metrics) {
+        if units == 0.0 && name != "baseline" {
+            continue;
+        }
+        let y = baseline - units * scale_factor;
+        guides = guides.add(
+            Line::new()
+                .set("class", format!("{}guide-{}", class_prefix, name))
+                .set("x1", bbox.x_min)
+                .set("y1", y)
+                .set("x2", bbox.x_max)
+                .set("y2", y)
+                .set("stroke", color),
+        );
+    }
+    guides
+}
+
+/// Outline and bounding box in font units of the box drawn for a missing glyph with given advance
+pub fn missing_glyph_box(advance: i32, metrics: &Metrics) -> (GlyphOutline, Rect) {
+    let clamp = |value: f32| value.round().clamp(i16::MIN as f32, i16::MAX as f32) as i16;
+    let bbox = Rect {
+        x_min: 0,
+        y_min: clamp(metrics.descent.min(0.0)),
+        x_max: clamp(advance as f32),
+        y_max: clamp(metrics.ascent.max(0.0)),
+    };
+    let (x_min, y_min, x_max, y_max) = (bbox.x_min as f32, bbox.y_min as f32, bbox.x_max as f32, bbox.y_max as f32);
+    let outline = GlyphOutline {
+        segments: vec![
+            OutlineSegment::MoveTo(x_min, y_min),
+            OutlineSegment::LineTo(x_min, y_max),
+            OutlineSegment::LineTo(x_max, y_max),
+            OutlineSegment::LineTo(x_max, y_min),
+            OutlineSegment::Close,
+        ],
+    };
+    (outline, bbox)
+}
+
+/// Depth of ink below the baseline in target coordinates.
+/// Glyphs sitting on or above the baseline (e.g. `°`) and runs without outlines have no depth.
+pub fn descender_depth(y_min: Option<i16>, scale_factor: f32) -> f32 {
+    match y_min {
+        Some(y_min) if y_min < 0 => -(y_min as f32) * scale_factor,
+        _ => 0.0,
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum OutlineSegment {
+    MoveTo(f32, f32),
+    LineTo(f32, f32),
+    QuadTo(f32, f32, f32, f32),
+    CurveTo(f32, f32, f32, f32, f32, f32),
+    Close,
+}
+
+/// Outline of a glyph in font units, recorded once and replayed at each occurrence
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct GlyphOutline {
+    segments: Vec<OutlineSegment>,
+}
+
+impl GlyphOutline {
+    pub fn replay(&self, builder: &mut impl ttf_parser::OutlineBuilder) {
+        for segment in self.segments.iter() {
+            match *segment {
+                OutlineSegment::MoveTo(x, y) => builder.move_to(x, y),
+                OutlineSegment::LineTo(x, y) => builder.line_to(x, y),
+                OutlineSegment::QuadTo(x1, y1, x, y) => builder.quad_to(x1, y1, x, y),
+                OutlineSegment::CurveTo(x1, y1, x2, y2, x, y) => builder.curve_to(x1, y1, x2, y2, x, y),
+                OutlineSegment::Close => builder.close(),
+            }
         }
+    }
+}
+
+/// Segments as path commands in font units, e.g. `M 0 0 L 300 700 L 600 0 Z`
+impl std::fmt::Display for GlyphOutline {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for (index, segment) in self.segments.iter().enumerate() {
+            if index > 0 {
+                f.write_str(" ")?;
+            }
+            match *segment {
+                OutlineSegment::MoveTo(x, y) => write!(f, "M {} {}", x, y)?,
+                OutlineSegment::LineTo(x, y) => write!(f, "L {} {}", x, y)?,
+                OutlineSegment::QuadTo(x1, y1, x, y) => write!(f, "Q {} {} {} {}", x1, y1, x, y)?,
+                OutlineSegment::CurveTo(x1, y1, x2, y2, x, y) => write!(f, "C {} {} {} {} {} {}", x1, y1, x2, y2, x, y)?,
+                OutlineSegment::Close => f.write_str("Z")?,
+            }
+        }
+        Ok(())
+    }
+}
+
+impl std::str::FromStr for GlyphOutline {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut outline = GlyphOutline::default();
+        let mut tokens = s.split_whitespace();
+        while let Some(command) = tokens.next() {
+            let mut number = || -> Result<f32, String> {
+                let token = tokens.next().ok_or_else(|| format!("missing coordinate after {:?}", command))?;
+                token.parse().map_err(|_| format!("expect a number, got {:?}", token))
+            };
+            let segment = match command {
+                "M" => OutlineSegment::MoveTo(number()?, number()?),
+                "L" => OutlineSegment::LineTo(number()?, number()?),
+                "Q" => OutlineSegment::QuadTo(number()?, number()?, number()?, number()?),
+                "C" => OutlineSegment::CurveTo(number()?, number()?, number()?, number()?, number()?, number()?),
+                "Z" => OutlineSegment::Close,
+                _ => return Err(format!("unknown path command {:?}", command)),
+            };
+            outline.segments.push(segment);
+        }
+        Ok(outline)
+    }
+}
+
+impl ttf_parser::OutlineBuilder for GlyphOutline {
+    fn move_to(&mut self, x: f32, y: f32) {
+        self.segments.push(OutlineSegment::MoveTo(x, y));
+    }
+
+    fn line_to(&mut self, x: f32, y: f32) {
+        self.segments.push(OutlineSegment::LineTo(x, y));
+    }
+
+    fn quad_to(&mut self, x1: f32, y1: f32, x: f32, y: f32) {
+        self.segments.push(OutlineSegment::QuadTo(x1, y1, x, y));
+    }
+
+    fn curve_to(&mut self, x1: f32, y1: f32, x2: f32, y2: f32, x: f32, y: f32) {
+        self.segments.push(OutlineSegment::CurveTo(x1, y1, x2, y2, x, y));
+    }
+
+    fn close(&mut self) {
+        self.segments.push(OutlineSegment::Close);
+    }
+}
 
-        Text::new(
-            Path::new()
-                .set("fill", self.fill_color)
-                .set("stroke", self.color)
-                .set("stroke-width", self.path_config.stroke_width.get())
-                .set("stroke-linejoin", self.path_config.get_stroke_linejoin())
-                .set("stroke-linecap", self.path_config.get_stroke_linecap())
-                .set("d", d),
-                bbox
-        )
+// outline and bounding box of glyphs, None for glyphs without outline (e.g. space)
+pub type GlyphOutlines = HashMap<u16, Option<(GlyphOutline, Rect)>>;
+
+fn outline_glyph_ids(face_data: &[u8], face_index: u32, glyph_ids: &[u16]) -> GlyphOutlines {
+    let Some(face) = Face::from_slice(face_data, face_index) else {
+        return GlyphOutlines::new();
+    };
+    glyph_ids
+        .iter()
+        .map(|&glyph_id| {
+            let mut outline = GlyphOutline::default();
+            let bbox = face.outline_glyph(GlyphId(glyph_id), &mut outline);
+            (glyph_id, bbox.map(|bbox| (outline, bbox)))
+        })
+        .collect()
+}
+
+/// Outline glyphs of the font data, split across jobs threads each parsing its own face
+pub fn outline_glyphs(face_data: &Arc<Vec<u8>>, face_index: u32, glyph_ids: &[u16], jobs: usize) -> GlyphOutlines {
+    timing::time(Phase::Outlining, || {
+        cache::outlines(face_data, face_index, glyph_ids, |glyph_ids| outline_glyphs_in_jobs(face_data, face_index, glyph_ids, jobs))
+    })
+}
+
+fn outline_glyphs_in_jobs(face_data: &[u8], face_index: u32, glyph_ids: &[u16], jobs: usize) -> GlyphOutlines {
+    if jobs <= 1 || glyph_ids.len() <= 1 {
+        return outline_glyph_ids(face_data, face_index, glyph_ids);
     }
+    let chunk_size = glyph_ids.len().div_ceil(jobs);
+    std::thread::scope(|scope| {
+        let handles: Vec<_> = glyph_ids
+            .chunks(chunk_size)
+            .map(|chunk| scope.spawn(move || outline_glyph_ids(face_data, face_index, chunk)))
+            .collect();
+        handles
+            .into_iter()
+            .flat_map(|handle| handle.join().unwrap())
+            .collect()
+    })
 }
 
 pub struct GlyphPathBuilder<'a> {
@@ -242,6 +1168,13 @@ pub struct GlyphPathBuilder<'a> {
     pub x: f32,
     pub y: f32,
     pub d: &'a mut String,
+    // sine and cosine of the rotation around x, y
+    rotation: (f32, f32),
+    // estimated length of contours in target coordinates
+    current: (f32, f32),
+    contour_start: (f32, f32),
+    contour_length: f32,
+    max_contour_length: f32,
 }
 
 impl<'a> GlyphPathBuilder<'a> {
@@ -252,57 +1185,223 @@ impl<'a> GlyphPathBuilder<'a> {
             x,
             y,
             d,
+            rotation: (0.0, 1.0),
+            current: (x, y),
+            contour_start: (x, y),
+            contour_length: 0.0,
+            max_contour_length: 0.0,
         }
     }
+
+    /// Length of the longest contour outlined so far
+    pub fn max_contour_length(&self) -> f32 {
+        self.max_contour_length
+    }
+
+    /// Turn the outline clockwise by angle in radians around its origin
+    pub fn set_rotation(&mut self, angle: f32) {
+        self.rotation = angle.sin_cos();
+    }
+
+    // font units to target coordinates
+    fn transform(&self, x: f32, y: f32) -> (f32, f32) {
+        let (x, y) = (x * self.scale_x, y * self.scale_y);
+        let (sin, cos) = self.rotation;
+        (x * cos - y * sin + self.x, x * sin + y * cos + self.y)
+    }
+
+    // Curve length is estimated as the average of chord and control polygon lengths
+    fn add_segment(&mut self, points: &[(f32, f32)]) {
+        let end = *points.last().unwrap();
+        let mut polygon = 0.0;
+        let mut prev = self.current;
+        for point in points {
+            polygon += distance(prev, *point);
+            prev = *point;
+        }
+        let chord = distance(self.current, end);
+        self.contour_length += (chord + polygon) / 2.0;
+        self.max_contour_length = self.max_contour_length.max(self.contour_length);
+        self.current = end;
+    }
+}
+
+fn distance(a: (f32, f32), b: (f32, f32)) -> f32 {
+    ((a.0 - b.0).powi(2) + (a.1 - b.1).powi(2)).sqrt()
 }
 
 impl ttf_parser::OutlineBuilder for GlyphPathBuilder<'_> {
     fn move_to(&mut self, x: f32, y: f32) {
-        write!(
-            self.d,
-            "M {} {}",
-            self.x + x * self.scale_x,
-            self.y + y * self.scale_y
-        )
-        .unwrap();
+        let (x, y) = self.transform(x, y);
+        write!(self.d, "M {} {}", x, y).unwrap();
+        self.current = (x, y);
+        self.contour_start = (x, y);
+        self.contour_length = 0.0;
     }
 
     fn line_to(&mut self, x: f32, y: f32) {
-        write!(
-            self.d,
-            "L {} {}",
-            self.x + x * self.scale_x,
-            self.y + y * self.scale_y
-        )
-        .unwrap();
+        let (x, y) = self.transform(x, y);
+        write!(self.d, "L {} {}", x, y).unwrap();
+        self.add_segment(&[(x, y)]);
     }
 
     fn quad_to(&mut self, x1: f32, y1: f32, x: f32, y: f32) {
-        write!(
-            self.d,
-            "Q {} {} {} {}",
-            x1 * self.scale_x + self.x,
-            y1 * self.scale_y + self.y,
-            x * self.scale_x + self.x,
-            y * self.scale_y + self.y
-        )
-        .unwrap();
+        let (x1, y1) = self.transform(x1, y1);
+        let (x, y) = self.transform(x, y);
+        write!(self.d, "Q {} {} {} {}", x1, y1, x, y).unwrap();
+        self.add_segment(&[(x1, y1), (x, y)]);
     }
+
     fn curve_to(&mut self, x1: f32, y1: f32, x2: f32, y2: f32, x: f32, y: f32) {
-        write!(
-            self.d,
-            "C {} {} {} {} {} {}",
-            x1 * self.scale_x + self.x,
-            y1 * self.scale_y + self.y,
-            x2 * self.scale_x + self.x,
-            y2 * self.scale_y + self.y,
-            x * self.scale_x + self.x,
-            y * self.scale_y + self.y
-        )
-        .unwrap();
+        let (x1, y1) = self.transform(x1, y1);
+        let (x2, y2) = self.transform(x2, y2);
+        let (x, y) = self.transform(x, y);
+        write!(self.d, "C {} {} {} {} {} {}", x1, y1, x2, y2, x, y).unwrap();
+        self.add_segment(&[(x1, y1), (x2, y2), (x, y)]);
     }
 
     fn close(&mut self) {
         write!(self.d, "Z ").unwrap();
+        let start = self.contour_start;
+        self.add_segment(&[start]);
     }
 }
+
+#[cfg(test)]
+mod test_svg {
+  use super::*;
+  use pathfinder_geometry::rect::RectF;
+  use pathfinder_geometry::vector::Vector2F;
+
+  fn metrics(units_per_em: u32, ascent: f32, descent: f32, bbox_height: f32) -> Metrics {
+      Metrics {
+          units_per_em,
+          ascent,
+          descent,
+          line_gap: 0.0,
+          underline_position: 0.0,
+          underline_thickness: 0.0,
+          cap_height: 0.0,
+          x_height: 0.0,
+          bounding_box: RectF::new(Vector2F::new(0.0, -200.0), Vector2F::new(1000.0, bbox_height)),
+      }
+  }
+
+  #[test]
+  fn test_style_declarations() {
+      let mut builder = Text::builder();
+      builder.set_color("#000").set_fill_color("none");
+      assert_eq!(
+          style_declarations(&builder.presentation_attributes()),
+          "fill:none;stroke:#000;stroke-width:1;stroke-linejoin:round;stroke-linecap:round"
+      );
+  }
+
+  #[test]
+  fn test_contour_length() {
+      use ttf_parser::OutlineBuilder;
+      let mut d = String::new();
+      let mut builder = GlyphPathBuilder::new(1.0, -1.0, 10.0, 10.0, &mut d);
+      // right triangle 3-4-5
+      builder.move_to(0.0, 0.0);
+      builder.line_to(3.0, 0.0);
+      builder.line_to(3.0, 4.0);
+      builder.close();
+      assert_eq!(builder.max_contour_length(), 12.0);
+      // straight curve has the length of its chord
+      builder.move_to(0.0, 0.0);
+      builder.quad_to(5.0, 0.0, 10.0, 0.0);
+      builder.curve_to(12.0, 0.0, 14.0, 0.0, 20.0, 0.0);
+      assert_eq!(builder.max_contour_length(), 20.0);
+      assert_eq!(d, "M 10 10L 13 10L 13 6Z M 10 10Q 15 10 20 10C 22 10 24 10 30 10");
+  }
+
+  #[test]
+  fn test_descender_depth() {
+      // lowest point of "gypsy" in DejaVu Sans is 426 units below the baseline
+      assert_eq!(descender_depth(Some(-426), 0.5), 213.0);
+      assert_eq!(descender_depth(Some(0), 0.5), 0.0);
+      // raised glyphs like a degree sign don't extend the bottom edge
+      assert_eq!(descender_depth(Some(700), 0.5), 0.0);
+      assert_eq!(descender_depth(None, 0.5), 0.0);
+  }
+
+  fn rasterize(doc: &str) -> Vec<u8> {
+      use resvg::usvg::TreeParsing;
+      let tree = resvg::usvg::Tree::from_str(doc, &resvg::usvg::Options::default()).unwrap();
+      let mut pixmap = resvg::tiny_skia::Pixmap::new(40, 40).unwrap();
+      resvg::Tree::from_usvg(&tree).render(resvg::tiny_skia::Transform::default(), &mut pixmap.as_mut());
+      pixmap.take()
+  }
+
+  #[test]
+  fn test_def_modes() {
+      use ttf_parser::OutlineBuilder;
+      let mut outline = GlyphOutline::default();
+      outline.move_to(0.0, -4.0);
+      outline.line_to(20.0, -4.0);
+      outline.quad_to(30.0, 16.0, 10.0, 24.0);
+      outline.close();
+      let bbox = Rect { x_min: 0, y_min: -4, x_max: 30, y_max: 24 };
+      let (x, baseline, scale) = (6.0, 30.0, 0.5);
+      let svg = |defs: String, content: String| format!(
+          r##"<svg xmlns="http://www.w3.org/2000/svg" width="40" height="40"><defs>{}</defs><g fill="none" stroke="#000" stroke-width="2">{}</g></svg>"##,
+          defs, content
+      );
+
+      let mut d = String::new();
+      outline.replay(&mut GlyphPathBuilder::new(scale, -scale, x, baseline, &mut d));
+      let inline = rasterize(&svg(String::new(), Path::new().set("d", d).to_string()));
+      assert!(inline.iter().any(|&channel| channel != 0));
+
+      for mode in [DefMode::Path, DefMode::Symbol] {
+          let glyph_def = GlyphDef::new("", 7, &outline, &bbox, scale, mode);
+          assert!(glyph_def.id.starts_with("glyph-7-"));
+          let doc = svg(glyph_def.definition().to_string(), glyph_def.place_at(x, baseline, HrefAttr::Href).to_string());
+          assert_eq!(rasterize(&doc), inline, "{:?}", mode);
+      }
+
+      // standalone paths need no definitions
+      let glyph_def = GlyphDef::new("", 7, &outline, &bbox, scale, DefMode::None);
+      let placed = glyph_def.place_at(x, baseline, HrefAttr::Href).to_string();
+      assert!(placed.starts_with("<path") && !placed.contains("href"));
+      assert_eq!(rasterize(&svg(String::new(), placed)), inline);
+  }
+
+  #[test]
+  fn test_glyph_outline_replay() {
+      use ttf_parser::OutlineBuilder;
+      let mut outline = GlyphOutline::default();
+      outline.move_to(0.0, 0.0);
+      outline.line_to(3.0, 0.0);
+      outline.quad_to(3.0, 2.0, 3.0, 4.0);
+      outline.curve_to(2.0, 4.0, 1.0, 4.0, 0.0, 4.0);
+      outline.close();
+
+      // replay gives the same path as outlining directly at the position
+      let mut direct = String::new();
+      let mut builder = GlyphPathBuilder::new(2.0, -2.0, 10.0, 20.0, &mut direct);
+      builder.move_to(0.0, 0.0);
+      builder.line_to(3.0, 0.0);
+      builder.quad_to(3.0, 2.0, 3.0, 4.0);
+      builder.curve_to(2.0, 4.0, 1.0, 4.0, 0.0, 4.0);
+      builder.close();
+      let mut replayed = String::new();
+      outline.replay(&mut GlyphPathBuilder::new(2.0, -2.0, 10.0, 20.0, &mut replayed));
+      assert_eq!(replayed, direct);
+  }
+
+  #[test]
+  fn test_degenerate_metrics() {
+      let sane = metrics(2048, 1900.0, -500.0, 2600.0);
+      assert_eq!(units_per_em(&sane), 2048.0);
+      assert_eq!(glyph_height_units(&sane), 2400.0);
+
+      let zero = metrics(0, 0.0, 0.0, 1200.0);
+      assert_eq!(units_per_em(&zero), 1000.0);
+      assert_eq!(glyph_height_units(&zero), 1200.0);
+
+      let empty = metrics(0, 0.0, 0.0, 0.0);
+      assert_eq!(glyph_height_units(&empty), 1000.0);
+  }
+}
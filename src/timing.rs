@@ -0,0 +1,103 @@
+// wall-clock time spent in each phase of a run, printed with --timing
+use std::fmt::Display;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+/// Phases of rendering, a phase may run many times (e.g. shaping each line)
+#[derive(Debug, PartialEq, Clone, Copy, Eq)]
+pub enum Phase {
+    FontLoading,
+    Shaping,
+    Outlining,
+    Serialization,
+}
+
+impl Phase {
+    const ALL: [Phase; 4] = [Phase::FontLoading, Phase::Shaping, Phase::Outlining, Phase::Serialization];
+
+    fn name(&self) -> &'static str {
+        match self {
+            Phase::FontLoading => "font loading",
+            Phase::Shaping => "shaping",
+            Phase::Outlining => "outlining",
+            Phase::Serialization => "serialization",
+        }
+    }
+}
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+// nanoseconds spent in each phase, indexed by phase
+static TOTALS: [AtomicU64; 4] = [AtomicU64::new(0), AtomicU64::new(0), AtomicU64::new(0), AtomicU64::new(0)];
+
+pub fn enable() {
+    ENABLED.store(true, Ordering::Relaxed);
+}
+
+pub fn enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed)
+}
+
+/// Run f, adding its wall-clock time to the phase when timing is enabled
+pub fn time<T>(phase: Phase, f: impl FnOnce() -> T) -> T {
+    if !enabled() {
+        return f();
+    }
+    let start = Instant::now();
+    let result = f();
+    TOTALS[phase as usize].fetch_add(start.elapsed().as_nanos() as u64, Ordering::Relaxed);
+    result
+}
+
+/// Time of each phase so far and of the whole run
+pub fn report(total: Duration) -> TimingReport {
+    let phases = Phase::ALL
+        .iter()
+        .map(|phase| (*phase, Duration::from_nanos(TOTALS[*phase as usize].load(Ordering::Relaxed))))
+        .collect();
+    TimingReport { phases, total }
+}
+
+pub struct TimingReport {
+    phases: Vec<(Phase, Duration)>,
+    total: Duration,
+}
+
+impl TimingReport {
+    // time outside of the measured phases, e.g. parsing arguments and laying out
+    fn other(&self) -> Duration {
+        let measured: Duration = self.phases.iter().map(|(_, time)| *time).sum();
+        self.total.saturating_sub(measured)
+    }
+}
+
+impl Display for TimingReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let share = |time: Duration| time.as_secs_f64() / self.total.as_secs_f64().max(f64::EPSILON) * 100.0;
+        writeln!(f, "{:<14} {:>10} {:>6}", "phase", "time", "share")?;
+        let rows = self.phases.iter().map(|(phase, time)| (phase.name(), *time)).chain([("other", self.other())]);
+        for (name, time) in rows {
+            writeln!(f, "{:<14} {:>8.2}ms {:>5.1}%", name, time.as_secs_f64() * 1000.0, share(time))?;
+        }
+        writeln!(f, "{:<14} {:>8.2}ms {:>5.1}%", "total", self.total.as_secs_f64() * 1000.0, 100.0)
+    }
+}
+
+#[cfg(test)]
+mod test_timing {
+  use super::*;
+
+  #[test]
+  fn test_report() {
+      let report = TimingReport {
+          phases: vec![(Phase::FontLoading, Duration::from_millis(10)), (Phase::Shaping, Duration::from_millis(30))],
+          total: Duration::from_millis(100),
+      };
+      assert_eq!(report.other(), Duration::from_millis(60));
+      let table = report.to_string();
+      let lines: Vec<&str> = table.lines().collect();
+      assert_eq!(lines[0], "phase                time  share");
+      assert_eq!(lines[1], "font loading      10.00ms  10.0%");
+      assert_eq!(lines[3], "other             60.00ms  60.0%");
+      assert_eq!(lines[4], "total            100.00ms 100.0%");
+  }
+}
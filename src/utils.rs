@@ -1,15 +1,24 @@
 // some useful utility functions
 use anyhow::{Result, anyhow};
+use std::collections::VecDeque;
 use std::iter::Iterator;
 use std::path::Path;
 use std::fs::File;
-use std::io::{Read, BufRead, BufReader, Bytes};
+use std::io::{Read, BufRead, BufReader, Bytes, IsTerminal, Write};
+use log::{Level, LevelFilter};
+use clap::ValueEnum;
+use unicode_normalization::UnicodeNormalization;
 
 // the width is actually the max characters for a line
-pub fn open_file_by_lines_width<P: AsRef<Path>>(path: P, step: usize)  -> Result<Vec<String>> {
+// with reflow, lines of paragraphs are joined before wrapping
+pub fn open_file_by_lines_width<P: AsRef<Path>>(path: P, step: usize, reflow: bool)  -> Result<Vec<String>> {
+    if step == 0 {
+        return Err(anyhow!("width must be at least 1 character"));
+    }
     let path = path.as_ref();
     if path.exists() && path.is_file() {
         return match File::open(path) {
+            Ok(file) if reflow => Ok(wrap_lines(&read_file_by_lines(file), step, true)),
             Ok(file) => Ok(read_file_by_chars(file,step)),
             Err(err) => Err(anyhow!(format!("{}: {}",path.display(),err))),
         };
@@ -31,14 +40,180 @@ pub fn open_file_by_lines<P: AsRef<Path>>(path: P)  -> Result<Vec<String>> {
                 "{}: doesn't exist or is not a regular file", path.display())))
 }
 
+/// Lines of file read lazily as they are consumed, wrapped at width characters when given
+pub fn stream_file_lines<P: AsRef<Path>>(path: P, width: Option<usize>) -> Result<Box<dyn Iterator<Item = String>>> {
+    let path = path.as_ref();
+    if width == Some(0) {
+        return Err(anyhow!("width must be at least 1 character"));
+    }
+    if path.exists() && path.is_file() {
+        return match File::open(path) {
+            Ok(file) => Ok(match width {
+                Some(step) => Box::new(chars_of(file, step)),
+                None => Box::new(lines_of(file)),
+            }),
+            Err(err) => Err(anyhow!(format!("{}: {}",path.display(),err))),
+        };
+    }
+    Err(anyhow!(format!(
+                "{}: doesn't exist or is not a regular file", path.display())))
+}
+
+/// Lines without the blank (empty or whitespace-only) lines at the end.
+/// Blank lines are held back until a line with content follows, so lines can still be streamed.
+pub fn trim_trailing_blank_lines(lines: impl Iterator<Item = String>) -> impl Iterator<Item = String> {
+    let mut held: VecDeque<String> = VecDeque::new();
+    // lines of held to give out before reading on
+    let mut ready = 0;
+    let mut lines = lines.fuse();
+    std::iter::from_fn(move || {
+        if ready > 0 {
+            ready -= 1;
+            return held.pop_front();
+        }
+        for line in lines.by_ref() {
+            let blank = line.trim().is_empty();
+            held.push_back(line);
+            if !blank {
+                ready = held.len() - 1;
+                return held.pop_front();
+            }
+        }
+        None
+    })
+}
+
+/// Wrap lines at step characters, joining the lines of paragraphs first with reflow
+pub fn wrap_lines(lines: &[String], step: usize, reflow: bool) -> Vec<String> {
+    let text = lines.join("\n");
+    if reflow {
+        return read_file_by_chars(reflow_paragraphs(&text).as_bytes(), step);
+    }
+    read_file_by_chars(text.as_bytes(), step)
+}
+
+/// Join the lines of each paragraph into one line, ignoring hard line breaks in prose.
+/// Paragraphs are separated by blank lines, which are kept as a single blank line.
+pub fn reflow_paragraphs(text: &str) -> String {
+    let mut paragraphs: Vec<String> = vec![];
+    let mut paragraph: Vec<&str> = vec![];
+    for line in text.lines().chain([""]) {
+        if !line.trim().is_empty() {
+            paragraph.push(line.trim());
+        } else if !paragraph.is_empty() {
+            paragraphs.push(paragraph.join(" "));
+            paragraph.clear();
+        }
+    }
+    paragraphs.join("\n\n")
+}
+
+/// Remove whitespace common to the start of all non-blank lines, like Python's textwrap.dedent.
+/// Whitespace-only lines are emptied and don't limit the common indentation.
+pub fn dedent(lines: &mut [String]) {
+    let mut common: Option<&str> = None;
+    for line in lines.iter().filter(|line| !line.trim().is_empty()) {
+        let indent = &line[..line.len() - line.trim_start_matches([' ', '\t']).len()];
+        common = Some(match common {
+            None => indent,
+            Some(common) => {
+                // tabs and spaces don't mix, only the identical prefix is common
+                let len = common.bytes().zip(indent.bytes()).take_while(|(a, b)| a == b).count();
+                &common[..len]
+            }
+        });
+    }
+    let len = common.map_or(0, |common| common.len());
+    for line in lines.iter_mut() {
+        if line.trim().is_empty() {
+            line.clear();
+        } else {
+            line.drain(..len);
+        }
+    }
+}
+
+/// Unicode normalization form applied to the input before shaping
+#[derive(ValueEnum, Debug, PartialEq, Clone, Copy, Eq)]
+#[value(rename_all="lower")]
+pub enum Normalization {
+    /// composed, e.g. "é" as a single character, which most fonts expect
+    Nfc,
+    /// decomposed, e.g. "e" followed by a combining acute accent
+    Nfd,
+    None,
+}
+
+impl Normalization {
+    pub fn apply(&self, text: &str) -> String {
+        match self {
+            Normalization::Nfc => text.nfc().collect(),
+            Normalization::Nfd => text.nfd().collect(),
+            Normalization::None => text.to_string(),
+        }
+    }
+
+    pub fn apply_lines(&self, lines: &mut [String]) {
+        if *self == Normalization::None {
+            return;
+        }
+        for line in lines.iter_mut() {
+            *line = self.apply(line);
+        }
+    }
+}
+
+// Skip the UTF-8 byte order mark which would otherwise be rendered as a glyph
+fn skip_bom<R: Read>(reader: &mut BufReader<R>) {
+    if let Ok(buf) = reader.fill_buf() {
+        if buf.starts_with(b"\xEF\xBB\xBF") {
+            reader.consume(3);
+        }
+    }
+}
+
+/// First line of a file without byte order mark
+pub fn read_first_line<P: AsRef<Path>>(path: P) -> Option<String> {
+    let mut reader = BufReader::new(File::open(path).ok()?);
+    skip_bom(&mut reader);
+    let mut line = String::new();
+    reader.read_line(&mut line).ok()?;
+    Some(line)
+}
+
+/// Lines of standard input, read to the end
+pub fn read_stdin_lines() -> Vec<String> {
+    read_file_by_lines(std::io::stdin().lock())
+}
+
 fn read_file_by_lines<R: Read>(file: R) -> Vec<String> {
-    let reader = BufReader::new(file);
-    let mut lines = vec![];
-    reader.lines().for_each(|line| {
-        let line = line.unwrap();
-        lines.push(line);
-    });
-    lines
+    lines_of(file).collect()
+}
+
+fn lines_of<R: Read>(file: R) -> impl Iterator<Item = String> {
+    let mut reader = BufReader::new(file);
+    skip_bom(&mut reader);
+    // invalid UTF-8 is replaced with U+FFFD instead of failing the whole file
+    reader.split(b'\n').map(|line| {
+        let mut line = line.unwrap();
+        if line.last() == Some(&b'\r') {
+            line.pop();
+        }
+        String::from_utf8_lossy(&line).into_owned()
+    })
+}
+
+/// Invisible break opportunity within a word, shown as a hyphen when a line breaks there
+const SOFT_HYPHEN: char = '\u{AD}';
+
+/// Selectors of the presentation (e.g. emoji or text) of the character before them
+pub fn is_variation_selector(ch: char) -> bool {
+    matches!(ch, '\u{FE00}'..='\u{FE0F}' | '\u{E0100}'..='\u{E01EF}')
+}
+
+// characters taking up width, soft hyphens only do at a line break and variation selectors never do
+fn visible_chars(line: &str) -> usize {
+    line.chars().filter(|ch| *ch != SOFT_HYPHEN && !is_variation_selector(*ch)).count()
 }
 
 pub struct WidthIter<R> {
@@ -47,16 +222,50 @@ pub struct WidthIter<R> {
     buffer: Vec<u8>,
     eof: bool,
     last_word: Option<String>,
+    // bytes read ahead of the line, read again before the rest of the input
+    pending: Vec<u8>,
 }
 
 impl<R> WidthIter<R> {
     pub fn new(iter: Bytes<BufReader<R>>,step: usize) -> Self {
         Self {
             byte_iter: iter,
-            step,
+            // zero width would never consume any input
+            step: step.max(1),
             buffer: Vec::new(),
             last_word: None,
             eof: false,
+            pending: Vec::new(),
+        }
+    }
+}
+
+impl<R: Read> WidthIter<R> {
+    fn next_byte(&mut self) -> Option<u8> {
+        if !self.pending.is_empty() {
+            return Some(self.pending.remove(0));
+        }
+        self.byte_iter.next().and_then(|byte| byte.ok())
+    }
+
+    // variation selectors after a full line stay with the character they apply to
+    fn take_variation_selectors(&mut self) {
+        loop {
+            let mut bytes = vec![];
+            while bytes.len() < 4 && std::str::from_utf8(&bytes).ok().filter(|ch| !ch.is_empty()).is_none() {
+                match self.next_byte() {
+                    Some(byte) => bytes.push(byte),
+                    None => break,
+                }
+            }
+            match std::str::from_utf8(&bytes).ok().and_then(|ch| ch.chars().next()) {
+                Some(ch) if is_variation_selector(ch) => self.buffer.extend(bytes),
+                _ => {
+                    bytes.append(&mut self.pending);
+                    self.pending = bytes;
+                    return;
+                }
+            }
         }
     }
 }
@@ -72,14 +281,14 @@ impl<R: Read> Iterator for WidthIter<R> {
         let mut char_counter = 0;
         if let Some(last_word) = &self.last_word {
             self.buffer.extend(last_word.bytes());
-            char_counter += last_word.chars().count();
+            char_counter += visible_chars(last_word);
             self.last_word = None;
         }
 
         // Take at most step-length long string then append with line break character.
         // Then it falls back to the same logic for the line iterator.
         while char_counter < self.step {
-            if let Some(Ok(ch_u8)) = self.byte_iter.next() {
+            if let Some(ch_u8) = self.next_byte() {
 
                 if ch_u8 == b'\n' {
                     // When encounter line break, it means this line does not exceed max width.
@@ -88,7 +297,7 @@ impl<R: Read> Iterator for WidthIter<R> {
 
                 self.buffer.push(ch_u8);
                 if let Ok(line) = std::str::from_utf8(&self.buffer) {
-                    char_counter = line.chars().count();
+                    char_counter = visible_chars(line);
                     // println!("buffer:\n{:?}", line);
                 }
             } else {
@@ -97,6 +306,10 @@ impl<R: Read> Iterator for WidthIter<R> {
             }
         }
 
+        if char_counter >= self.step {
+            self.take_variation_selectors();
+        }
+
         if self.eof && self.buffer.is_empty() {
             return None;
         }
@@ -110,8 +323,16 @@ impl<R: Read> Iterator for WidthIter<R> {
         if char_counter >= self.step {
             if let Ok(cur_line) = std::str::from_utf8(&self.buffer.clone()) {
                 let mut last_word = String::new();
+                let space = cur_line.char_indices().rev().find(|(_,c)| c.is_ascii_whitespace());
+                let word_start = space.map_or(0, |(space_idx, _)| space_idx + 1);
+                let soft_hyphen = cur_line.rfind(SOFT_HYPHEN).filter(|idx| *idx >= word_start);
 
-                if let Some((space_idx,_)) = cur_line.char_indices().rev().find(|(_,c)| c.is_ascii_whitespace()) {
+                if let Some(hyphen_idx) = soft_hyphen {
+                    // break the word at its last soft hyphen, which becomes visible at the end of the line
+                    let (line, part_word) = cur_line.split_at(hyphen_idx);
+                    last_word.push_str(&part_word[SOFT_HYPHEN.len_utf8()..]);
+                    self.buffer = format!("{}-", line).into_bytes();
+                } else if let Some((space_idx,_)) = space {
                     // println!("space index {:?} in {:?}", space_idx, cur_line);
                     // For All ASCII text, if the whitespace is not the last character,
                     // then this means we have borken up a word.
@@ -131,28 +352,206 @@ impl<R: Read> Iterator for WidthIter<R> {
             }
         }
 
-        let line = String::from_utf8(self.buffer.clone()).unwrap();
+        // soft hyphens not at the end of the line don't break it, so they are left out
+        let line = String::from_utf8_lossy(&self.buffer).replace(SOFT_HYPHEN, "");
         self.buffer.clear();
 
         Some(line)
     }
 }
 
+/// Line progress printed to stderr, only shown on a terminal
+pub struct Progress {
+    total: usize,
+    current: usize,
+    percent: usize,
+    enabled: bool,
+}
+
+impl Progress {
+    pub fn new(total: usize, enabled: bool) -> Self {
+        Self {
+            total,
+            current: 0,
+            percent: 0,
+            enabled: enabled && std::io::stderr().is_terminal(),
+        }
+    }
+
+    pub fn inc(&mut self) {
+        self.current += 1;
+        if !self.enabled || self.total == 0 {
+            return;
+        }
+        // redraw only when percentage changes
+        let percent = self.current * 100 / self.total;
+        if percent != self.percent || self.current == 1 {
+            self.percent = percent;
+            eprint!("\rrendering {}/{} lines ({}%)", self.current, self.total, percent);
+            let _ = std::io::stderr().flush();
+        }
+    }
+
+    pub fn finish(&self) {
+        if self.enabled && self.total > 0 {
+            eprintln!();
+        }
+    }
+}
+
+/// environment variable filtering log output by module, e.g. `text2svg::svg=trace`
+pub const LOG_ENV: &str = "TEXT2SVG_LOG";
+
+/// Log level from command line flags, --debug traces everything and --quiet only reports errors
+pub fn log_level(verbose: u8, debug: bool, quiet: bool) -> LevelFilter {
+    if debug {
+        return LevelFilter::Trace;
+    }
+    if quiet {
+        return LevelFilter::Error;
+    }
+    match verbose {
+        0 => LevelFilter::Warn,
+        1 => LevelFilter::Info,
+        2 => LevelFilter::Debug,
+        _ => LevelFilter::Trace,
+    }
+}
+
+/// Log to stderr, warnings and errors read like the other messages of the command.
+/// Dependencies (e.g. font-kit warning about name tables) only report errors unless enabled by TEXT2SVG_LOG.
+pub fn init_logger(level: LevelFilter) {
+    env_logger::Builder::new()
+        .filter_level(LevelFilter::Error)
+        .filter_module(env!("CARGO_CRATE_NAME"), level)
+        .parse_env(LOG_ENV)
+        .format(|buf, record| match record.level() {
+            Level::Error => writeln!(buf, "error: {}", record.args()),
+            Level::Warn => writeln!(buf, "warning: {}", record.args()),
+            level => writeln!(buf, "[{} {}] {}", level, record.target(), record.args()),
+        })
+        .init();
+}
+
+/// Classes of failure told apart by the exit code, attached to errors as context
+#[derive(Debug, PartialEq, Clone, Copy, Eq)]
+pub enum Failure {
+    FontNotFound,
+    InputNotFound,
+    Shaping,
+    OutputWrite,
+}
+
+impl Failure {
+    /// Exit code of the command, 1 is left for any other error
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            Failure::FontNotFound => 2,
+            Failure::InputNotFound => 3,
+            Failure::Shaping => 4,
+            Failure::OutputWrite => 5,
+        }
+    }
+
+    /// Class of an error, from the failure attached anywhere in its context
+    pub fn of(error: &anyhow::Error) -> Option<Failure> {
+        error.downcast_ref::<Failure>().copied()
+    }
+}
+
+impl std::fmt::Display for Failure {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Failure::FontNotFound => "font not found",
+            Failure::InputNotFound => "input not found",
+            Failure::Shaping => "shaping failed",
+            Failure::OutputWrite => "output not written",
+        })
+    }
+}
+
+/// Short random identifier prefix, e.g. `t3f9a2c-`
+pub fn random_id_prefix() -> String {
+    use std::collections::hash_map::RandomState;
+    use std::hash::{BuildHasher, Hasher};
+    let hash = RandomState::new().build_hasher().finish();
+    format!("t{:06x}-", hash & 0xffffff)
+}
+
+/// FNV-1a hash of the bytes of all parts. Unlike DefaultHasher its values don't change between Rust releases,
+/// so glyph ids and cache file names made from it are the same whichever toolchain built the command.
+pub fn stable_hash(parts: &[&[u8]]) -> u64 {
+    parts.iter().flat_map(|part| part.iter()).fold(0xcbf29ce484222325, |hash, byte| {
+        (hash ^ *byte as u64).wrapping_mul(0x100000001b3)
+    })
+}
+
 fn read_file_by_chars<R: Read>(file: R, step: usize) ->  Vec<String> {
-    let reader = BufReader::new(file);
+    chars_of(file, step).collect()
+}
+
+fn chars_of<R: Read>(file: R, step: usize) -> WidthIter<R> {
+    let mut reader = BufReader::new(file);
+    skip_bom(&mut reader);
     let byte_iter = reader.bytes();
-    let width_iter= WidthIter::new(byte_iter, step);
-    let mut lines = vec![];
-    width_iter.for_each(|line| {
-        lines.push(line);
-    });
-    lines
+    WidthIter::new(byte_iter, step)
 }
 
 
 #[cfg(test)]
 mod test_utils{
   use super::*;
+  #[test]
+  fn test_dedent() {
+      let mut lines: Vec<String> = ["        if x {", "            y();", "", "   ", "        }"]
+          .iter().map(|line| line.to_string()).collect();
+      dedent(&mut lines);
+      assert_eq!(lines, vec!["if x {", "    y();", "", "", "}"]);
+
+      let mut lines = vec!["\t\ta".to_string(), "\t  b".to_string()];
+      dedent(&mut lines);
+      assert_eq!(lines, vec!["\ta", "  b"]);
+  }
+
+  #[test]
+  fn test_trim_trailing_blank_lines() {
+      let lines = ["a", "", " ", "b", "", "\t", ""].iter().map(|line| line.to_string());
+      assert_eq!(trim_trailing_blank_lines(lines).collect::<Vec<_>>(), vec!["a", "", " ", "b"]);
+      assert_eq!(trim_trailing_blank_lines(["", ""].iter().map(|line| line.to_string())).count(), 0);
+  }
+
+  #[test]
+  fn test_normalization() {
+      let decomposed = "caf\u{65}\u{301}";
+      let precomposed = "caf\u{e9}";
+      assert_eq!(Normalization::Nfc.apply(decomposed), precomposed);
+      assert_eq!(Normalization::Nfd.apply(precomposed), decomposed);
+      assert_eq!(Normalization::None.apply(decomposed), decomposed);
+
+      // invalid UTF-8 doesn't fail reading
+      assert_eq!(read_file_by_lines(&b"ok\r\nbad \xff"[..]), vec!["ok", "bad \u{FFFD}"]);
+  }
+
+  #[test]
+  fn test_reflow_paragraphs() {
+      let text = "The quick brown\nfox jumps over the\nlazy dog.\n\n\nSecond\n  paragraph.\n";
+      assert_eq!(reflow_paragraphs(text), "The quick brown fox jumps over the lazy dog.\n\nSecond paragraph.");
+      assert_eq!(
+          read_file_by_chars(reflow_paragraphs(text).as_bytes(), 20),
+          vec!["The quick brown fox ", "jumps over the lazy ", "dog.", "", "Second paragraph."]
+      );
+  }
+
+  #[test]
+  fn test_log_level() {
+      assert_eq!(log_level(0, false, false), LevelFilter::Warn);
+      assert_eq!(log_level(2, false, false), LevelFilter::Debug);
+      assert_eq!(log_level(5, false, false), LevelFilter::Trace);
+      assert_eq!(log_level(0, false, true), LevelFilter::Error);
+      // --debug wins over --quiet
+      assert_eq!(log_level(0, true, true), LevelFilter::Trace);
+  }
+
   #[test]
   #[should_panic(expected="should panic")]
   fn test_open_file_by_lines() {
@@ -162,6 +561,25 @@ mod test_utils{
         }
   }
 
+  #[test]
+  fn test_stream_file_lines() {
+        // the same lines as reading the whole file
+        let lines: Vec<String> = stream_file_lines("Cargo.toml", None).unwrap().collect();
+        assert_eq!(lines, open_file_by_lines("Cargo.toml").unwrap());
+        let lines: Vec<String> = stream_file_lines("Cargo.toml", Some(12)).unwrap().collect();
+        assert_eq!(lines, open_file_by_lines_width("Cargo.toml", 12, false).unwrap());
+        assert!(stream_file_lines("/tmp/file-does-not-exist", None).is_err());
+        assert!(stream_file_lines("Cargo.toml", Some(0)).is_err());
+  }
+
+  #[test]
+  fn test_skip_bom() {
+        let text = "\u{FEFF}hello\nworld";
+        assert_eq!(read_file_by_lines(text.as_bytes()), vec!["hello", "world"]);
+        assert_eq!(read_file_by_chars(text.as_bytes(), 80), vec!["hello", "world"]);
+        assert_eq!(read_file_by_lines("hello".as_bytes()), vec!["hello"]);
+  }
+
   #[test]
   fn test_width_iter_long_text() {
         let reader = BufReader::new(&b"123123123"[..]);
@@ -174,6 +592,33 @@ mod test_utils{
         });
   }
 
+  #[test]
+  fn test_width_iter_soft_hyphen() {
+        let text = "super\u{AD}cali\u{AD}fragilistic";
+        assert_eq!(read_file_by_chars(text.as_bytes(), 12), vec!["supercali-", "fragilistic"]);
+        assert_eq!(read_file_by_chars(text.as_bytes(), 8), vec!["super-", "cali-", "fragilis", "tic"]);
+        // a space after the last soft hyphen is the better break
+        assert_eq!(read_file_by_chars("ex\u{AD}tra words".as_bytes(), 8), vec!["extra", "words"]);
+        assert_eq!(read_file_by_chars(text.as_bytes(), 80), vec!["supercalifragilistic"]);
+  }
+
+  #[test]
+  fn test_width_iter_variation_selector() {
+        let text = "ab\u{2764}\u{FE0F}cd\n\u{2764}\u{FE0E}\u{2764}\u{FE0F}";
+        assert_eq!(read_file_by_chars(text.as_bytes(), 3), vec!["ab\u{2764}\u{FE0F}", "cd", "\u{2764}\u{FE0E}\u{2764}\u{FE0F}"]);
+        assert_eq!(read_file_by_chars(text.as_bytes(), 2), vec!["ab", "\u{2764}\u{FE0F}c", "d", "\u{2764}\u{FE0E}\u{2764}\u{FE0F}"]);
+  }
+
+  #[test]
+  fn test_width_iter_narrow_width() {
+        for step in [0, 1] {
+            let reader = BufReader::new(&b"abc"[..]);
+            let lines: Vec<String> = WidthIter::new(reader.bytes(), step).collect();
+            assert_eq!(lines, vec!["a", "b", "c"]);
+        }
+        assert!(open_file_by_lines_width("Cargo.toml", 0, false).is_err());
+  }
+
   #[test]
   fn test_width_iter_non_ascii() {
         let reader = BufReader::new("当我发现我童年和少年时期的旧日记时，它们已经被尘埃所覆盖。".as_bytes());
@@ -197,5 +642,13 @@ mod test_utils{
             assert_eq!(ans[idx], line);
         });
   }
+
+  #[test]
+  fn test_stable_hash() {
+      // reference values of 64 bit FNV-1a
+      assert_eq!(stable_hash(&[]), 0xcbf29ce484222325);
+      assert_eq!(stable_hash(&[b"a"]), 0xaf63dc4c8601ec8c);
+      assert_eq!(stable_hash(&[b"foo", b"bar"]), stable_hash(&[b"foobar"]));
+  }
 }
 
@@ -0,0 +1,66 @@
+// warnings of a run, printed as text through the logger or as json lines with --warnings-format json
+use clap::ValueEnum;
+use serde_json::Value;
+use std::fmt::Display;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// how warnings are written to stderr
+#[derive(ValueEnum, Debug, PartialEq, Clone, Copy, Eq)]
+#[value(rename_all="lower")]
+pub enum WarningsFormat {
+    /// `warning: <message>` lines
+    Text,
+    /// a json object per line with kind, fields of the warning and message
+    Json,
+}
+
+static JSON: AtomicBool = AtomicBool::new(false);
+
+pub fn set_format(format: WarningsFormat) {
+    JSON.store(format == WarningsFormat::Json, Ordering::Relaxed);
+}
+
+/// A warning of some kind (e.g. style_fallback) with the values scripts may look at
+#[derive(Debug, PartialEq, Clone)]
+pub struct Warning {
+    kind: &'static str,
+    // in insertion order, written after the kind
+    fields: Vec<(&'static str, Value)>,
+    message: String,
+}
+
+impl Warning {
+    pub fn new(kind: &'static str, message: impl Into<String>) -> Self {
+        Self { kind, fields: vec![], message: message.into() }
+    }
+
+    pub fn field(mut self, key: &'static str, value: impl Into<Value>) -> Self {
+        self.fields.push((key, value.into()));
+        self
+    }
+
+    /// One line json object, keys keep the order of kind, fields and message
+    pub fn to_json(&self) -> String {
+        let pairs = std::iter::once(("kind", Value::from(self.kind)))
+            .chain(self.fields.iter().cloned())
+            .chain(std::iter::once(("message", Value::from(self.message.as_str()))))
+            .map(|(key, value)| format!("{}:{}", Value::from(key), value))
+            .collect::<Vec<_>>();
+        format!("{{{}}}", pairs.join(","))
+    }
+}
+
+impl Display for Warning {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+/// Report the warning in the chosen format, -q silences both
+pub fn warn(warning: Warning) {
+    if !JSON.load(Ordering::Relaxed) {
+        log::warn!("{}", warning);
+    } else if log::log_enabled!(log::Level::Warn) {
+        eprintln!("{}", warning.to_json());
+    }
+}